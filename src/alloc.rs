@@ -1,4 +1,6 @@
 //! Internal allocator to allow wdk-mutex to use the allocator as required.
+//!
+//! Only compiled with the `global-alloc` feature, which registers [`KMAlloc`] as the `#[global_allocator]`.
 
 use core::{alloc::GlobalAlloc, ptr::null_mut};
 
@@ -12,8 +14,23 @@ use wdk_sys::{
 /// SAFETY: This is safe IRQL <= DISPATCH_LEVEL
 pub struct KMAlloc;
 
-// The value memory tags are stored as.
-const MEM_TAG_WDK_MUTEX: u32 = u32::from_le_bytes(*b"kmtx");
+#[global_allocator]
+static GLOBAL_ALLOCATOR: KMAlloc = KMAlloc;
+
+// The value memory tags are stored as. Defaults to `kmtx`, and can be overridden at compile time by setting the
+// `WDK_MUTEX_POOL_TAG` environment variable to a four character tag, for example `WDK_MUTEX_POOL_TAG=drv1`.
+const MEM_TAG_WDK_MUTEX: u32 = match option_env!("WDK_MUTEX_POOL_TAG") {
+    Some(tag) => pool_tag_from_str(tag),
+    None => u32::from_le_bytes(*b"kmtx"),
+};
+
+/// Converts a four character tag to the value it is stored as, failing the build if it is not four bytes long.
+const fn pool_tag_from_str(tag: &str) -> u32 {
+    let bytes = tag.as_bytes();
+    assert!(bytes.len() == 4, "WDK_MUTEX_POOL_TAG must be exactly 4 bytes long");
+
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
 
 unsafe impl GlobalAlloc for KMAlloc {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
@@ -0,0 +1,300 @@
+//! A non-paged, atomically reference counted pointer for sharing data between threads.
+
+use core::{
+    ffi::c_void,
+    ops::Deref,
+    ptr::{self, drop_in_place},
+    sync::atomic::{fence, AtomicUsize, Ordering},
+};
+use wdk_sys::{
+    ntddk::{ExAllocatePool2, ExFreePool, KeGetCurrentIrql},
+    DISPATCH_LEVEL, POOL_FLAG_NON_PAGED,
+};
+
+use crate::errors::DriverMutexError;
+
+/// A thread safe reference counted pointer, allocated in the non-paged pool.
+///
+/// `ArcNP<T>` is the kernel counterpart of `alloc::sync::Arc`, however the allocation is made directly from the
+/// non-paged pool with the crate's pool tag, and allocation failure is returned as an error rather than aborting.
+/// Cloning an `ArcNP` increments the strong count, and the `T` is dropped when the last `ArcNP` is dropped.
+///
+/// A [`WeakNP`] may be created with [`ArcNP::downgrade`], which does not keep the `T` alive, allowing parent / child
+/// structures to refer back to each other without leaking. The pool allocation itself is only freed once both the
+/// strong and weak counts reach zero.
+///
+/// As with `Arc`, an `ArcNP` only gives shared access to the `T`. To share mutable state between threads without
+/// the [`crate::grt::Grt`], wrap a mutex: `ArcNP<KMutex<T>>`.
+///
+/// # IRQL
+///
+/// The data is non-paged, so an `ArcNP` may be cloned, dereferenced and dropped at IRQL `<= DISPATCH_LEVEL`, subject
+/// to the requirements of `T`'s own `Drop`.
+///
+/// # Examples
+///
+/// ```
+/// let shared = ArcNP::new(KMutex::new(0u32).unwrap()).unwrap();
+///
+/// let for_thread = shared.clone();
+/// // Move `for_thread` into a system thread ...
+///
+/// *shared.lock().unwrap() += 1;
+/// println!("{} references", ArcNP::strong_count(&shared));
+/// ```
+pub struct ArcNP<T> {
+    inner: *mut ArcNPInner<T>,
+}
+
+/// The underlying data which is non-page pool allocated which is pointed to by the `ArcNP`.
+struct ArcNPInner<T> {
+    /// The number of `ArcNP`s pointing to this allocation
+    strong: AtomicUsize,
+    /// The number of `WeakNP`s pointing to this allocation, plus one held collectively by all `ArcNP`s
+    weak: AtomicUsize,
+    /// The shared data
+    data: T,
+}
+
+// SAFETY: The `T` is shared between every thread holding a clone, and is dropped by whichever thread drops the last
+// clone, so it must be both `Send` and `Sync`, as with `Arc`.
+unsafe impl<T: Send + Sync> Send for ArcNP<T> {}
+unsafe impl<T: Send + Sync> Sync for ArcNP<T> {}
+unsafe impl<T: Send + Sync> Send for WeakNP<T> {}
+unsafe impl<T: Send + Sync> Sync for WeakNP<T> {}
+
+impl<T> ArcNP<T> {
+    /// Allocates `data` in the non-paged pool with a strong count of 1.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`, or the non-paged pool allocation fails.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let shared = ArcNP::new(0u32).unwrap();
+    /// ```
+    pub fn new(data: T) -> Result<Self, DriverMutexError> {
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        let total_sz_required = size_of::<ArcNPInner<T>>();
+        let inner_heap_ptr: *mut c_void = unsafe {
+            ExAllocatePool2(
+                POOL_FLAG_NON_PAGED,
+                total_sz_required as u64,
+                u32::from_be_bytes(*b"kmtx"),
+            )
+        };
+        if inner_heap_ptr.is_null() {
+            return Err(DriverMutexError::AllocFailed {
+                requested: total_sz_required,
+            });
+        }
+
+        let inner = inner_heap_ptr as *mut ArcNPInner<T>;
+
+        // SAFETY: This raw write is safe as the pointer validity is checked above.
+        unsafe {
+            ptr::write(
+                inner,
+                ArcNPInner {
+                    strong: AtomicUsize::new(1),
+                    weak: AtomicUsize::new(1),
+                    data,
+                },
+            )
+        };
+
+        Ok(Self { inner })
+    }
+
+    /// Returns the number of `ArcNP`s pointing to the same allocation as `this`.
+    ///
+    /// Another thread may clone or drop an `ArcNP` at any time, so the count may be out of date by the time it is
+    /// used.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().strong.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of [`WeakNP`]s pointing to the same allocation as `this`.
+    ///
+    /// As with [`Self::strong_count`], the count may be out of date by the time it is used.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn weak_count(this: &Self) -> usize {
+        // Remove the weak reference held by the strong references
+        this.inner().weak.load(Ordering::Acquire) - 1
+    }
+
+    /// Creates a [`WeakNP`] pointing to the same allocation as `this`.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at IRQL `<= DISPATCH_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let parent = ArcNP::new(Node::default()).unwrap();
+    /// let child = Node { parent: Some(ArcNP::downgrade(&parent)), ..Default::default() };
+    /// ```
+    pub fn downgrade(this: &Self) -> WeakNP<T> {
+        let old = this.inner().weak.fetch_add(1, Ordering::Relaxed);
+        if old > isize::MAX as usize {
+            panic!("ArcNP weak count overflowed");
+        }
+
+        WeakNP { inner: this.inner }
+    }
+
+    /// Returns `true` if both `ArcNP`s point to the same allocation.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        ptr::eq(this.inner, other.inner)
+    }
+
+    fn inner(&self) -> &ArcNPInner<T> {
+        // SAFETY: The allocation is valid whilst any `ArcNP` pointing to it is alive.
+        unsafe { &*self.inner }
+    }
+}
+
+impl<T> Clone for ArcNP<T> {
+    fn clone(&self) -> Self {
+        // Relaxed is sufficient as a new reference can only be made from an existing one, as with `Arc`
+        let old = self.inner().strong.fetch_add(1, Ordering::Relaxed);
+
+        // Guard against the count overflowing through leaked clones, which would lead to a use after free
+        if old > isize::MAX as usize {
+            panic!("ArcNP strong count overflowed");
+        }
+
+        Self { inner: self.inner }
+    }
+}
+
+impl<T> Deref for ArcNP<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner().data
+    }
+}
+
+impl<T> Drop for ArcNP<T> {
+    fn drop(&mut self) {
+        if self.inner().strong.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+
+        // Synchronise with the release of every other clone before the data is dropped
+        fence(Ordering::Acquire);
+
+        // SAFETY: This was the last strong reference, so no other thread can access the data, and `WeakNP::upgrade`
+        // will no longer hand out references to it.
+        unsafe { drop_in_place(&mut (*self.inner).data) };
+
+        // Release the weak reference held by the strong references, freeing the allocation if no `WeakNP` remains
+        drop(WeakNP { inner: self.inner });
+    }
+}
+
+/// A weak reference to data held by an [`ArcNP`], created with [`ArcNP::downgrade`].
+///
+/// A `WeakNP` does not keep the `T` alive; [`Self::upgrade`] returns `None` once every `ArcNP` has been dropped. It
+/// does keep the pool allocation alive, so that the counts can still be read.
+///
+/// # IRQL
+///
+/// A `WeakNP` may be upgraded and dropped at IRQL `<= DISPATCH_LEVEL`.
+pub struct WeakNP<T> {
+    inner: *mut ArcNPInner<T>,
+}
+
+impl<T> WeakNP<T> {
+    /// Attempts to obtain an [`ArcNP`] to the data, returning `None` if it has already been dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if let Some(parent) = child.parent.upgrade() {
+    ///     parent.visit();
+    /// }
+    /// ```
+    pub fn upgrade(&self) -> Option<ArcNP<T>> {
+        let strong = self.strong();
+
+        // Only increment the strong count if it is not zero, as once it is zero the data has been dropped
+        let mut count = strong.load(Ordering::Relaxed);
+        loop {
+            if count == 0 {
+                return None;
+            }
+            if count > isize::MAX as usize {
+                panic!("ArcNP strong count overflowed");
+            }
+
+            match strong.compare_exchange_weak(
+                count,
+                count + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(ArcNP { inner: self.inner }),
+                Err(current) => count = current,
+            }
+        }
+    }
+
+    /// Returns the number of [`ArcNP`]s pointing to the allocation, which is zero once the data has been dropped.
+    pub fn strong_count(&self) -> usize {
+        self.strong().load(Ordering::Acquire)
+    }
+
+    fn strong(&self) -> &AtomicUsize {
+        // SAFETY: The allocation is valid whilst any `WeakNP` pointing to it is alive. The counts are accessed
+        // through the raw pointer as the data may have been dropped.
+        unsafe { &(*self.inner).strong }
+    }
+
+    fn weak(&self) -> &AtomicUsize {
+        // SAFETY: As per `Self::strong`.
+        unsafe { &(*self.inner).weak }
+    }
+}
+
+impl<T> Clone for WeakNP<T> {
+    fn clone(&self) -> Self {
+        let old = self.weak().fetch_add(1, Ordering::Relaxed);
+        if old > isize::MAX as usize {
+            panic!("ArcNP weak count overflowed");
+        }
+
+        Self { inner: self.inner }
+    }
+}
+
+impl<T> Drop for WeakNP<T> {
+    fn drop(&mut self) {
+        if self.weak().fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+
+        fence(Ordering::Acquire);
+
+        // SAFETY: This was the last reference of either kind, and the data was dropped along with the last `ArcNP`.
+        unsafe { ExFreePool(self.inner as *mut _) };
+    }
+}
@@ -0,0 +1,252 @@
+//! A condition variable for blocking on a [`KMutex`] until its protected data changes.
+//!
+//! [`KMutex`]: crate::kmutex::KMutex
+
+use core::{
+    ffi::c_void,
+    ptr::null_mut,
+    sync::atomic::{AtomicUsize, Ordering::SeqCst},
+};
+use wdk_sys::{
+    ntddk::{
+        ExAllocatePool2, ExFreePool, KeGetCurrentIrql, KeInitializeSemaphore, KeReleaseSemaphore,
+        KeWaitForSingleObject,
+    },
+    APC_LEVEL, DISPATCH_LEVEL, FALSE, KSEMAPHORE, POOL_FLAG_NON_PAGED,
+    _KWAIT_REASON::Executive,
+    _MODE::KernelMode,
+};
+
+use crate::{errors::DriverMutexError, kmutex::KMutexGuard};
+
+/// A condition variable, used together with a [`crate::kmutex::KMutex`].
+///
+/// A thread holding the mutex calls [`Self::wait`] (or [`Self::wait_while`]), which releases the mutex, blocks until
+/// another thread calls [`Self::notify_one`] or [`Self::notify_all`], and re-acquires the mutex before returning.
+///
+/// Waiters block on a kernel semaphore rather than an event. Each notification releases the semaphore once for each
+/// waiter it wakes, so a notification made between a waiter releasing the mutex and starting to block is not lost,
+/// and [`Self::notify_all`] wakes as many threads as were waiting when it was called. An event cannot do this, as
+/// setting an event which is already signalled has no further effect, and a notification event must be reset by
+/// someone.
+///
+/// As with any condition variable, the condition being waited for may no longer hold by the time the mutex is
+/// re-acquired, so it must be re-checked after each wakeup; [`Self::wait_while`] does this for you.
+///
+/// # IRQL
+///
+/// [`Self::wait`] and [`Self::wait_while`] must be called at IRQL `<= APC_LEVEL`. [`Self::notify_one`] and
+/// [`Self::notify_all`] may be called at IRQL `<= DISPATCH_LEVEL`.
+///
+/// # Examples
+///
+/// ```
+/// // Shared between a producer and a consumer
+/// let queue = KMutex::new(NpVec::<u32>::new()).unwrap();
+/// let not_empty = Condvar::new().unwrap();
+///
+/// // Producer
+/// queue.lock().unwrap().push(1).unwrap();
+/// not_empty.notify_one().unwrap();
+///
+/// // Consumer
+/// let mut items = not_empty
+///     .wait_while(queue.lock().unwrap(), |items| items.is_empty())
+///     .unwrap();
+/// let item = items.pop();
+/// ```
+pub struct Condvar {
+    /// Non-paged semaphore, released once per waiter woken
+    semaphore: *mut KSEMAPHORE,
+    /// The number of threads which are waiting, or about to wait, and have not yet been notified
+    waiters: AtomicUsize,
+}
+
+unsafe impl Sync for Condvar {}
+unsafe impl Send for Condvar {}
+
+impl Condvar {
+    /// Creates a new condition variable.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`, or the non-paged pool allocation fails.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`.
+    pub fn new() -> Result<Self, DriverMutexError> {
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // The semaphore must be non-paged as it is a dispatcher object
+        let semaphore: *mut c_void = unsafe {
+            ExAllocatePool2(
+                POOL_FLAG_NON_PAGED,
+                size_of::<KSEMAPHORE>() as u64,
+                u32::from_be_bytes(*b"kmtx"),
+            )
+        };
+        if semaphore.is_null() {
+            return Err(DriverMutexError::AllocFailed {
+                requested: size_of::<KSEMAPHORE>(),
+            });
+        }
+        let semaphore = semaphore as *mut KSEMAPHORE;
+
+        // SAFETY: The allocation is checked above and sized for a KSEMAPHORE.
+        unsafe { KeInitializeSemaphore(semaphore, 0, i32::MAX) };
+
+        Ok(Self {
+            semaphore,
+            waiters: AtomicUsize::new(0),
+        })
+    }
+
+    /// Releases the mutex held by `guard`, blocks until notified, then re-acquires the mutex and returns the guard.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `APC_LEVEL`, either on entry or once notified. In
+    /// that case the mutex is not held on return.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut ready = mtx.lock().unwrap();
+    /// while !*ready {
+    ///     ready = condvar.wait(ready).unwrap();
+    /// }
+    /// ```
+    pub fn wait<'a, T>(
+        &self,
+        mut guard: KMutexGuard<'a, T>,
+    ) -> Result<KMutexGuard<'a, T>, DriverMutexError> {
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-kewaitforsingleobject
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // Register as a waiter before releasing the mutex, so a notification made after the release is counted
+        self.waiters.fetch_add(1, SeqCst);
+
+        guard.unlocked(|| {
+            // Discard the return value; the status code does not represent an error or contain information
+            // relevant to the context of no timeout.
+            let _ = unsafe {
+                // SAFETY: IRQL checked above, the semaphore lives as long as self.
+                KeWaitForSingleObject(
+                    self.semaphore as *mut _,
+                    Executive,
+                    KernelMode as i8,
+                    FALSE as u8,
+                    null_mut(),
+                )
+            };
+        })?;
+
+        Ok(guard)
+    }
+
+    /// Blocks whilst `condition` returns `true` for the protected data, releasing the mutex whilst blocked.
+    ///
+    /// The condition is checked before the first wait and after every wakeup.
+    ///
+    /// # Errors
+    ///
+    /// As per [`Self::wait`].
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= APC_LEVEL`.
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: KMutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> Result<KMutexGuard<'a, T>, DriverMutexError> {
+        while condition(&mut guard) {
+            guard = self.wait(guard)?;
+        }
+
+        Ok(guard)
+    }
+
+    /// Wakes one thread blocked on this condition variable.
+    ///
+    /// If no thread is waiting, this has no effect.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`.
+    pub fn notify_one(&self) -> Result<(), DriverMutexError> {
+        // Claim a single waiter, if there is one
+        let claimed = self
+            .waiters
+            .fetch_update(SeqCst, SeqCst, |waiters| waiters.checked_sub(1))
+            .is_ok();
+
+        if claimed {
+            self.release(1)?;
+        }
+
+        Ok(())
+    }
+
+    /// Wakes every thread blocked on this condition variable.
+    ///
+    /// If no thread is waiting, this has no effect.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`.
+    pub fn notify_all(&self) -> Result<(), DriverMutexError> {
+        // Claim every current waiter
+        let claimed = self.waiters.swap(0, SeqCst);
+
+        if claimed != 0 {
+            self.release(claimed)?;
+        }
+
+        Ok(())
+    }
+
+    /// Releases the semaphore `count` times, waking up to `count` waiters.
+    fn release(&self, count: usize) -> Result<(), DriverMutexError> {
+        // Check the IRQL is <= DISPATCH_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-kereleasesemaphore
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            // Hand the claimed waiters back, so a later notification can wake them
+            self.waiters.fetch_add(count, SeqCst);
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // SAFETY: The semaphore was initialised in `new` and lives as long as self. The count cannot exceed the
+        // limit, as each unit corresponds to a thread which is waiting or about to wait.
+        unsafe { KeReleaseSemaphore(self.semaphore, 0, count as i32, FALSE as u8) };
+
+        Ok(())
+    }
+}
+
+impl Drop for Condvar {
+    fn drop(&mut self) {
+        // SAFETY: The semaphore was allocated in `new`, and no thread can be waiting on it as we have exclusive
+        // access.
+        unsafe { ExFreePool(self.semaphore as *mut _) };
+    }
+}
@@ -0,0 +1,90 @@
+//! An object safe locking interface, allowing differently typed mutexes to be stored and locked behind `dyn` handles.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::{
+    any::Any,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{errors::DriverMutexError, fast_mutex::FastMutex, kmutex::KMutex};
+
+/// An object safe interface for locking a mutex without knowing the type of the protected data at compile time.
+///
+/// Neither [`KMutex::lock`] nor [`FastMutex::lock`] can be called through a trait object, as the guard they return
+/// names the type `T`. `DynMutex` erases `T` so that heterogeneous mutexes can be stored together, for example as
+/// `Box<dyn DynMutex>`, and locked through the same handle.
+///
+/// The guard returned from [`Self::lock_erased`] dereferences to `dyn Any`; the protected data is recovered by
+/// downcasting to the concrete type with [`Any::downcast_ref`] or [`Any::downcast_mut`]. A downcast to the wrong type
+/// returns `None` rather than causing undefined behaviour. The lock is released when the boxed guard is dropped.
+///
+/// # IRQL
+///
+/// The IRQL requirements are those of the underlying mutex's `lock` method.
+///
+/// # Examples
+///
+/// ```
+/// let mutexes: [Box<dyn DynMutex>; 2] = [
+///     Box::new(KMutex::new(0u32).unwrap()),
+///     Box::new(FastMutex::new(false).unwrap()),
+/// ];
+///
+/// for mtx in mutexes.iter() {
+///     let mut guard = mtx.lock_erased().unwrap();
+///
+///     if let Some(counter) = guard.downcast_mut::<u32>() {
+///         *counter += 1;
+///     } else if let Some(flag) = guard.downcast_mut::<bool>() {
+///         *flag = true;
+///     }
+/// } // Each lock is released as the boxed guard falls out of scope
+/// ```
+pub trait DynMutex {
+    /// Acquires the mutex, returning a boxed RAII guard which dereferences to the protected data as `dyn Any`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the underlying mutex's `lock` method, for example if the IRQL is too high.
+    fn lock_erased(&self) -> Result<Box<dyn DerefMut<Target = dyn Any> + '_>, DriverMutexError>;
+}
+
+impl<T: Any> DynMutex for KMutex<T> {
+    fn lock_erased(&self) -> Result<Box<dyn DerefMut<Target = dyn Any> + '_>, DriverMutexError> {
+        Ok(Box::new(ErasedGuard(self.lock()?)))
+    }
+}
+
+impl<T: Any> DynMutex for FastMutex<T> {
+    fn lock_erased(&self) -> Result<Box<dyn DerefMut<Target = dyn Any> + '_>, DriverMutexError> {
+        Ok(Box::new(ErasedGuard(self.lock()?)))
+    }
+}
+
+/// Wraps a typed mutex guard so that it dereferences to `dyn Any`. Dropping this drops the inner guard, releasing
+/// the lock.
+struct ErasedGuard<G>(G);
+
+impl<G> Deref for ErasedGuard<G>
+where
+    G: Deref,
+    G::Target: Any + Sized,
+{
+    type Target = dyn Any;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl<G> DerefMut for ErasedGuard<G>
+where
+    G: DerefMut,
+    G::Target: Any + Sized,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut *self.0
+    }
+}
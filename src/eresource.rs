@@ -0,0 +1,340 @@
+//! A Rust idiomatic Windows Kernel Driver ERESOURCE reader/writer lock which protects the inner type T
+
+use core::{
+    ffi::c_void,
+    fmt::Display,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr::{self, drop_in_place},
+};
+use wdk_sys::{
+    ntddk::{
+        ExAcquireResourceExclusiveLite, ExAcquireResourceSharedLite, ExAllocatePool2,
+        ExDeleteResourceLite, ExFreePool, ExInitializeResourceLite, ExReleaseResourceLite,
+        KeEnterCriticalRegion, KeGetCurrentIrql, KeLeaveCriticalRegion,
+    },
+    APC_LEVEL, DISPATCH_LEVEL, ERESOURCE, POOL_FLAG_NON_PAGED, TRUE,
+};
+
+use crate::errors::DriverMutexError;
+
+/// A reader/writer lock implemented through an `ERESOURCE` in the Windows kernel.
+///
+/// The type `ResourceLock<T>` allows any number of threads shared read access to the inner type T through
+/// [`Self::read`], or a single thread exclusive write access through [`Self::write`]. For read-mostly state this avoids
+/// serialising readers, as a [`crate::kmutex::KMutex`] or [`crate::fast_mutex::FastMutex`] would.
+///
+/// The `ERESOURCE` and the protected data are allocated in the non-paged pool, as the `ERESOURCE` must be resident.
+///
+/// Whilst a guard is held, normal kernel APCs are disabled for the thread through `KeEnterCriticalRegion`, as is
+/// required when acquiring an `ERESOURCE`. The region is left when the guard is dropped.
+///
+/// # Deallocation
+///
+/// ResourceLock handles the deallocation of resources at the point the ResourceLock is dropped, deleting the
+/// `ERESOURCE` with `ExDeleteResourceLite`.
+///
+/// # Examples
+///
+/// ```
+/// let config = ResourceLock::new(Config::default()).unwrap();
+///
+/// // Any number of threads may read at once
+/// {
+///     let config = config.read().unwrap();
+///     println!("Timeout: {}", config.timeout);
+/// }
+///
+/// // Writers have exclusive access
+/// {
+///     let mut config = config.write().unwrap();
+///     config.timeout = 30;
+/// }
+/// ```
+pub struct ResourceLock<T> {
+    inner: *mut ResourceLockInner<T>,
+}
+
+/// The underlying data which is non-page pool allocated which is pointed to by the `ResourceLock`.
+struct ResourceLockInner<T> {
+    resource: ERESOURCE,
+    /// The pool tag this structure was allocated with
+    tag: u32,
+    /// The data for which the lock is protecting
+    data: T,
+}
+
+unsafe impl<T: Send + Sync> Sync for ResourceLock<T> {}
+unsafe impl<T: Send> Send for ResourceLock<T> {}
+
+impl<T> ResourceLock<T> {
+    /// Creates a new `ERESOURCE` backed reader/writer lock.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`, or the non-paged pool allocation fails.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at IRQL <= DISPATCH_LEVEL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let lock = ResourceLock::new(0u32).unwrap();
+    /// ```
+    pub fn new(data: T) -> Result<Self, DriverMutexError> {
+        // This can only be called at a level <= DISPATCH_LEVEL; check current IRQL
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-exinitializeresourcelite
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        //
+        // Non-Paged heap alloc for all struct data required for ResourceLockInner
+        //
+        let tag = u32::from_be_bytes(*b"kmtx");
+        let total_sz_required = size_of::<ResourceLockInner<T>>();
+        let inner_heap_ptr: *mut c_void =
+            unsafe { ExAllocatePool2(POOL_FLAG_NON_PAGED, total_sz_required as u64, tag) };
+        if inner_heap_ptr.is_null() {
+            return Err(DriverMutexError::AllocFailed {
+                requested: total_sz_required,
+            });
+        }
+
+        // Cast the memory allocation to a pointer to the inner
+        let resource_inner_ptr = inner_heap_ptr as *mut ResourceLockInner<T>;
+
+        // SAFETY: This raw write is safe as the pointer validity is checked above.
+        unsafe {
+            ptr::write(
+                resource_inner_ptr,
+                ResourceLockInner {
+                    resource: ERESOURCE::default(),
+                    tag,
+                    data,
+                },
+            );
+
+            // Initialise the resource via the kernel. This cannot fail for a resident, aligned ERESOURCE.
+            let _ = ExInitializeResourceLite(&mut (*resource_inner_ptr).resource);
+        }
+
+        Ok(Self {
+            inner: resource_inner_ptr,
+        })
+    }
+
+    /// Acquires the lock for shared read access, waiting whilst a writer holds it.
+    ///
+    /// The returned [`ResourceReadGuard`] releases the lock, and leaves the critical region, when dropped.
+    ///
+    /// # Errors
+    ///
+    /// If the IRQL is above `APC_LEVEL`, this function will return an error and will not acquire the lock.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let value = *lock.read().unwrap();
+    /// ```
+    pub fn read(&self) -> Result<ResourceReadGuard<'_, T>, DriverMutexError> {
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-exacquireresourcesharedlite
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // SAFETY: RAII manages pointer validity and IRQL checked. Normal kernel APCs must be disabled whilst the
+        // resource is held.
+        unsafe {
+            KeEnterCriticalRegion();
+            ExAcquireResourceSharedLite(&mut (*self.inner).resource, TRUE as u8);
+        }
+
+        Ok(ResourceReadGuard {
+            lock: self,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Acquires the lock for exclusive write access, waiting whilst any reader or writer holds it.
+    ///
+    /// The returned [`ResourceWriteGuard`] releases the lock, and leaves the critical region, when dropped.
+    ///
+    /// # Errors
+    ///
+    /// If the IRQL is above `APC_LEVEL`, this function will return an error and will not acquire the lock.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// *lock.write().unwrap() += 1;
+    /// ```
+    pub fn write(&self) -> Result<ResourceWriteGuard<'_, T>, DriverMutexError> {
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-exacquireresourceexclusivelite
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // SAFETY: RAII manages pointer validity and IRQL checked. Normal kernel APCs must be disabled whilst the
+        // resource is held.
+        unsafe {
+            KeEnterCriticalRegion();
+            ExAcquireResourceExclusiveLite(&mut (*self.inner).resource, TRUE as u8);
+        }
+
+        Ok(ResourceWriteGuard {
+            lock: self,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Returns a mutable reference to the protected data, without acquiring the lock.
+    ///
+    /// As this takes `&mut self`, the borrow checker guarantees no other reference to the lock (and so no guard)
+    /// exists, so the lock does not need to be acquired.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn get_mut(&mut self) -> &mut T {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation, and `&mut self`
+        // guarantees exclusive access.
+        unsafe { &mut (*self.inner).data }
+    }
+
+    /// Returns the pool tag the lock was allocated with.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn tag(&self) -> u32 {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        unsafe { (*self.inner).tag }
+    }
+}
+
+impl<T> Drop for ResourceLock<T> {
+    fn drop(&mut self) {
+        unsafe {
+            // Drop the underlying data and run destructors for the data
+            drop_in_place(&mut (*self.inner).data);
+
+            // The resource must be deleted before its memory is freed, no guard can be outstanding as we have
+            // exclusive access.
+            let _ = ExDeleteResourceLite(&mut (*self.inner).resource);
+
+            // Free the memory we allocated
+            ExFreePool(self.inner as *mut _);
+        }
+    }
+}
+
+/// A RAII scoped guard giving shared read access to the data protected by a [`ResourceLock`].
+///
+/// When this structure is dropped, the resource is released with `ExReleaseResourceLite` and the critical region
+/// entered by [`ResourceLock::read`] is left.
+///
+/// # IRQL
+///
+/// This guard must be dropped at the same IRQL as the lock was acquired at.
+pub struct ResourceReadGuard<'a, T> {
+    lock: &'a ResourceLock<T>,
+    /// The resource must be released, and the critical region left, by the thread which acquired it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Display for ResourceReadGuard<'_, T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        write!(f, "{}", unsafe { &(*self.lock.inner).data })
+    }
+}
+
+impl<T> Deref for ResourceReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations, and no writer can
+        // hold the resource whilst this guard exists.
+        unsafe { &(*self.lock.inner).data }
+    }
+}
+
+impl<T> Drop for ResourceReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: The resource is held shared by this guard, and the critical region was entered on acquisition.
+        unsafe {
+            ExReleaseResourceLite(&mut (*self.lock.inner).resource);
+            KeLeaveCriticalRegion();
+        }
+    }
+}
+
+/// A RAII scoped guard giving exclusive write access to the data protected by a [`ResourceLock`].
+///
+/// When this structure is dropped, the resource is released with `ExReleaseResourceLite` and the critical region
+/// entered by [`ResourceLock::write`] is left.
+///
+/// # IRQL
+///
+/// This guard must be dropped at the same IRQL as the lock was acquired at.
+pub struct ResourceWriteGuard<'a, T> {
+    lock: &'a ResourceLock<T>,
+    /// The resource must be released, and the critical region left, by the thread which acquired it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Display for ResourceWriteGuard<'_, T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        write!(f, "{}", unsafe { &(*self.lock.inner).data })
+    }
+}
+
+impl<T> Deref for ResourceWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        unsafe { &(*self.lock.inner).data }
+    }
+}
+
+impl<T> DerefMut for ResourceWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        // Mutable access is safe due to Self only being given out whilst the resource is held exclusively.
+        unsafe { &mut (*self.lock.inner).data }
+    }
+}
+
+impl<T> Drop for ResourceWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: The resource is held exclusively by this guard, and the critical region was entered on
+        // acquisition.
+        unsafe {
+            ExReleaseResourceLite(&mut (*self.lock.inner).resource);
+            KeLeaveCriticalRegion();
+        }
+    }
+}
@@ -1,10 +1,34 @@
 //! Error types used by wdk-mutex
 
+use wdk_sys::{
+    NTSTATUS, STATUS_ALREADY_INITIALIZED, STATUS_CANCELLED, STATUS_CANT_WAIT, STATUS_DEVICE_BUSY,
+    STATUS_INSUFFICIENT_RESOURCES, STATUS_INVALID_DEVICE_STATE, STATUS_INVALID_PARAMETER,
+    STATUS_IO_TIMEOUT, STATUS_NOT_FOUND, STATUS_OBJECT_NAME_COLLISION, STATUS_OBJECT_TYPE_MISMATCH,
+    STATUS_POSSIBLE_DEADLOCK,
+};
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum DriverMutexError {
     IrqlTooHigh,
     IrqlNotAPCLevel,
+    /// No longer returned by the crate; pool allocation failures are reported through [`Self::AllocFailed`]. This
+    /// variant will be removed in a future release.
+    #[deprecated(note = "allocation failures are now reported as `DriverMutexError::AllocFailed`")]
     PagedPoolAllocFailed,
+    AllocFailed {
+        requested: usize,
+    },
+    TooManyWaitObjects,
+    DuplicateMutex,
+    WouldBlock,
+    Aborted,
+    NotInitialised,
+    Timeout,
+    Alerted,
+    NotArmed,
+    AlreadyArmed,
+    Poisoned,
+    WouldDeadlock,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -15,5 +39,151 @@ pub enum GrtError {
     KeyNotFound,
     KeyExists,
     DowncastError,
+    StillInUse,
     DriverMutexError(DriverMutexError),
+    /// `PsCreateSystemThread`, or referencing the thread it created, failed with the contained status.
+    ThreadCreateFailed(NTSTATUS),
+}
+
+impl DriverMutexError {
+    /// Maps the error to an `NTSTATUS`, for returning from dispatch routines and IOCTL handlers.
+    ///
+    /// | Error | `NTSTATUS` |
+    /// |-------|------------|
+    /// | `IrqlTooHigh`, `IrqlNotAPCLevel`, `NotInitialised`, `NotArmed`, `AlreadyArmed`, `Poisoned` | `STATUS_INVALID_DEVICE_STATE` |
+    /// | `PagedPoolAllocFailed`, `AllocFailed` | `STATUS_INSUFFICIENT_RESOURCES` |
+    /// | `TooManyWaitObjects`, `DuplicateMutex` | `STATUS_INVALID_PARAMETER` |
+    /// | `WouldBlock` | `STATUS_CANT_WAIT` |
+    /// | `Aborted`, `Alerted` | `STATUS_CANCELLED` |
+    /// | `Timeout` | `STATUS_IO_TIMEOUT` |
+    /// | `WouldDeadlock` | `STATUS_POSSIBLE_DEADLOCK` |
+    ///
+    /// `Timeout` and `Alerted` do not map to `STATUS_TIMEOUT` / `STATUS_ALERTED`, as those are success codes and
+    /// would pass `NT_SUCCESS`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut lock = match mutex.lock() {
+    ///     Ok(lock) => lock,
+    ///     Err(e) => return e.to_ntstatus(),
+    /// };
+    /// ```
+    pub fn to_ntstatus(&self) -> NTSTATUS {
+        match self {
+            DriverMutexError::IrqlTooHigh
+            | DriverMutexError::IrqlNotAPCLevel
+            | DriverMutexError::NotInitialised
+            | DriverMutexError::NotArmed
+            | DriverMutexError::AlreadyArmed
+            | DriverMutexError::Poisoned => STATUS_INVALID_DEVICE_STATE,
+            #[allow(deprecated)]
+            DriverMutexError::PagedPoolAllocFailed | DriverMutexError::AllocFailed { .. } => {
+                STATUS_INSUFFICIENT_RESOURCES
+            }
+            DriverMutexError::TooManyWaitObjects | DriverMutexError::DuplicateMutex => {
+                STATUS_INVALID_PARAMETER
+            }
+            DriverMutexError::WouldBlock => STATUS_CANT_WAIT,
+            DriverMutexError::Aborted | DriverMutexError::Alerted => STATUS_CANCELLED,
+            DriverMutexError::Timeout => STATUS_IO_TIMEOUT,
+            DriverMutexError::WouldDeadlock => STATUS_POSSIBLE_DEADLOCK,
+        }
+    }
+}
+
+impl core::fmt::Display for DriverMutexError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DriverMutexError::IrqlTooHigh => write!(f, "IRQL too high for mutex operation"),
+            DriverMutexError::IrqlNotAPCLevel => write!(f, "IRQL is not APC_LEVEL"),
+            #[allow(deprecated)]
+            DriverMutexError::PagedPoolAllocFailed => write!(f, "paged pool allocation failed"),
+            DriverMutexError::AllocFailed { requested } => {
+                write!(f, "pool allocation of {} bytes failed", requested)
+            }
+            DriverMutexError::TooManyWaitObjects => {
+                write!(f, "too many objects to wait on at once")
+            }
+            DriverMutexError::DuplicateMutex => {
+                write!(f, "the same mutex was passed more than once")
+            }
+            DriverMutexError::WouldBlock => write!(f, "mutex is held by another thread"),
+            DriverMutexError::Aborted => write!(f, "operation aborted"),
+            DriverMutexError::NotInitialised => write!(f, "mutex has not been initialised"),
+            DriverMutexError::Timeout => write!(f, "timed out waiting for the mutex"),
+            DriverMutexError::Alerted => write!(f, "wait was interrupted by an alert or user APC"),
+            DriverMutexError::NotArmed => write!(f, "mutex has not been armed"),
+            DriverMutexError::AlreadyArmed => write!(f, "mutex has already been armed"),
+            DriverMutexError::Poisoned => write!(f, "mutex has been poisoned"),
+            DriverMutexError::WouldDeadlock => {
+                write!(f, "mutex is already held by the current thread")
+            }
+        }
+    }
+}
+
+impl core::error::Error for DriverMutexError {}
+
+impl GrtError {
+    /// Maps the error to an `NTSTATUS`, for returning from dispatch routines and IOCTL handlers.
+    ///
+    /// | Error | `NTSTATUS` |
+    /// |-------|------------|
+    /// | `GrtAlreadyExists` | `STATUS_ALREADY_INITIALIZED` |
+    /// | `GrtIsNull` | `STATUS_INVALID_DEVICE_STATE` |
+    /// | `GrtIsEmpty`, `KeyNotFound` | `STATUS_NOT_FOUND` |
+    /// | `KeyExists` | `STATUS_OBJECT_NAME_COLLISION` |
+    /// | `DowncastError` | `STATUS_OBJECT_TYPE_MISMATCH` |
+    /// | `StillInUse` | `STATUS_DEVICE_BUSY` |
+    /// | `DriverMutexError` | See [`DriverMutexError::to_ntstatus`] |
+    /// | `ThreadCreateFailed` | The contained status |
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let my_mutex = match Grt::get_kmutex::<u32>("my_test_mutex") {
+    ///     Ok(m) => m,
+    ///     Err(e) => return e.to_ntstatus(),
+    /// };
+    /// ```
+    pub fn to_ntstatus(&self) -> NTSTATUS {
+        match self {
+            GrtError::GrtAlreadyExists => STATUS_ALREADY_INITIALIZED,
+            GrtError::GrtIsNull => STATUS_INVALID_DEVICE_STATE,
+            GrtError::GrtIsEmpty | GrtError::KeyNotFound => STATUS_NOT_FOUND,
+            GrtError::KeyExists => STATUS_OBJECT_NAME_COLLISION,
+            GrtError::DowncastError => STATUS_OBJECT_TYPE_MISMATCH,
+            GrtError::StillInUse => STATUS_DEVICE_BUSY,
+            GrtError::DriverMutexError(e) => e.to_ntstatus(),
+            GrtError::ThreadCreateFailed(status) => *status,
+        }
+    }
+}
+
+impl core::fmt::Display for GrtError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            GrtError::GrtAlreadyExists => write!(f, "Grt has already been initialised"),
+            GrtError::GrtIsNull => write!(f, "Grt has not been initialised"),
+            GrtError::GrtIsEmpty => write!(f, "Grt is empty"),
+            GrtError::KeyNotFound => write!(f, "key not found in the Grt"),
+            GrtError::KeyExists => write!(f, "key already exists in the Grt"),
+            GrtError::DowncastError => write!(f, "mutex in the Grt is of a different type"),
+            GrtError::StillInUse => write!(f, "Grt is still in use by a handle"),
+            GrtError::DriverMutexError(e) => write!(f, "mutex error: {}", e),
+            GrtError::ThreadCreateFailed(status) => {
+                write!(f, "failed to create system thread: {:#x}", status)
+            }
+        }
+    }
+}
+
+impl core::error::Error for GrtError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            GrtError::DriverMutexError(e) => Some(e),
+            _ => None,
+        }
+    }
 }
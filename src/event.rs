@@ -0,0 +1,249 @@
+//! A Rust idiomatic wrapper for a Windows Kernel `KEVENT`, for signalling between threads, DPCs and callbacks.
+
+use core::{ffi::c_void, ptr::null_mut};
+use wdk_sys::{
+    ntddk::{
+        ExAllocatePool2, ExFreePool, KeClearEvent, KeGetCurrentIrql, KeInitializeEvent,
+        KeReadStateEvent, KeSetEvent, KeWaitForSingleObject,
+    },
+    APC_LEVEL, DISPATCH_LEVEL, FALSE, KEVENT, LARGE_INTEGER, POOL_FLAG_NON_PAGED, STATUS_TIMEOUT,
+    TRUE,
+    _EVENT_TYPE::{NotificationEvent, SynchronizationEvent},
+    _KWAIT_REASON::Executive,
+    _MODE::KernelMode,
+};
+
+use crate::errors::DriverMutexError;
+
+/// A kernel event, allocated in the non-paged pool.
+///
+/// An event is either signalled or not. Threads wait for it to become signalled with [`Self::wait`] or
+/// [`Self::wait_timeout`], and any thread, DPC or callback running at `<= DISPATCH_LEVEL` may signal it with
+/// [`Self::set`]. The kind of event is chosen at creation:
+///
+/// - A **notification** event stays signalled once set, releasing every waiter, until it is reset with
+///   [`Self::clear`].
+/// - A **synchronization** event releases a single waiter and is then automatically reset.
+///
+/// # Examples
+///
+/// ```
+/// // Shared between a DPC and a worker thread
+/// let work_ready = KernelEvent::new(false, false).unwrap();
+///
+/// // In the DPC
+/// work_ready.set().unwrap();
+///
+/// // In the worker thread
+/// work_ready.wait().unwrap();
+/// ```
+pub struct KernelEvent {
+    /// Non-paged event, as it is a dispatcher object which may be signalled at DISPATCH_LEVEL
+    event: *mut KEVENT,
+}
+
+unsafe impl Sync for KernelEvent {}
+unsafe impl Send for KernelEvent {}
+
+impl KernelEvent {
+    /// Creates a new event.
+    ///
+    /// `notification` selects a notification event if `true`, or a synchronization event if `false`.
+    /// `initial_state` selects whether the event starts signalled.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`, or the non-paged pool allocation fails.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // A notification event which starts unsignalled
+    /// let shutdown = KernelEvent::new(true, false).unwrap();
+    /// ```
+    pub fn new(notification: bool, initial_state: bool) -> Result<Self, DriverMutexError> {
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        let event: *mut c_void = unsafe {
+            ExAllocatePool2(
+                POOL_FLAG_NON_PAGED,
+                size_of::<KEVENT>() as u64,
+                u32::from_be_bytes(*b"kmtx"),
+            )
+        };
+        if event.is_null() {
+            return Err(DriverMutexError::AllocFailed {
+                requested: size_of::<KEVENT>(),
+            });
+        }
+        let event = event as *mut KEVENT;
+
+        let event_type = if notification {
+            NotificationEvent
+        } else {
+            SynchronizationEvent
+        };
+        let state = if initial_state { TRUE } else { FALSE };
+
+        // SAFETY: The allocation is checked above and sized for a KEVENT.
+        unsafe { KeInitializeEvent(event, event_type, state as u8) };
+
+        Ok(Self { event })
+    }
+
+    /// Signals the event, releasing waiting threads.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`.
+    pub fn set(&self) -> Result<(), DriverMutexError> {
+        // Check the IRQL is <= DISPATCH_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-kesetevent
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // SAFETY: The event was initialised in `new` and lives as long as self.
+        unsafe { KeSetEvent(self.event, 0, FALSE as u8) };
+
+        Ok(())
+    }
+
+    /// Resets the event to the unsignalled state.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`.
+    pub fn clear(&self) -> Result<(), DriverMutexError> {
+        // Check the IRQL is <= DISPATCH_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-keclearevent
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // SAFETY: The event was initialised in `new` and lives as long as self.
+        unsafe { KeClearEvent(self.event) };
+
+        Ok(())
+    }
+
+    /// Returns `true` if the event is currently signalled.
+    ///
+    /// Another thread may set or clear the event at any time, so the state may be out of date by the time it is used.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn is_set(&self) -> bool {
+        // SAFETY: The event was initialised in `new` and lives as long as self.
+        unsafe { KeReadStateEvent(self.event) != 0 }
+    }
+
+    /// Blocks until the event is signalled.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is too high to wait.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= APC_LEVEL`.
+    pub fn wait(&self) -> Result<(), DriverMutexError> {
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-kewaitforsingleobject
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // Discard the return value; the status code does not represent an error or contain information
+        // relevant to the context of no timeout.
+        let _ = unsafe {
+            // SAFETY: IRQL checked above, the event lives as long as self.
+            KeWaitForSingleObject(
+                self.event as *mut _,
+                Executive,
+                KernelMode as i8,
+                FALSE as u8,
+                null_mut(),
+            )
+        };
+
+        Ok(())
+    }
+
+    /// Blocks until the event is signalled, or until `relative_100ns` (in 100 nanosecond units) has elapsed.
+    ///
+    /// A timeout of 0 tests the event without waiting.
+    ///
+    /// # Errors
+    ///
+    /// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is too high to wait.
+    /// - [`DriverMutexError::Timeout`] if the event was not signalled before the timeout elapsed.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Wait for up to 1 second
+    /// match event.wait_timeout(10_000_000) {
+    ///     Ok(()) => { /* Signalled */ }
+    ///     Err(DriverMutexError::Timeout) => { /* Timed out */ }
+    ///     Err(e) => println!("Error: {:?}", e),
+    /// }
+    /// ```
+    pub fn wait_timeout(&self, relative_100ns: i64) -> Result<(), DriverMutexError> {
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-kewaitforsingleobject
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // A negative value is interpreted by the kernel as relative to the current time
+        let mut timeout = LARGE_INTEGER {
+            QuadPart: -relative_100ns.max(0),
+        };
+
+        let status = unsafe {
+            // SAFETY: IRQL checked above, the event lives as long as self.
+            KeWaitForSingleObject(
+                self.event as *mut _,
+                Executive,
+                KernelMode as i8,
+                FALSE as u8,
+                &mut timeout,
+            )
+        };
+
+        if status == STATUS_TIMEOUT {
+            return Err(DriverMutexError::Timeout);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for KernelEvent {
+    fn drop(&mut self) {
+        // SAFETY: The event was allocated in `new`, and no thread can be waiting on it as we have exclusive access.
+        unsafe { ExFreePool(self.event as *mut _) };
+    }
+}
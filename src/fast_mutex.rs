@@ -2,12 +2,19 @@
 
 use alloc::boxed::Box;
 use core::{
-    ffi::c_void, fmt::Display, mem::ManuallyDrop, ops::{Deref, DerefMut}, ptr::{self, drop_in_place}
+    ffi::c_void, fmt::Display, hint::spin_loop, marker::PhantomData, mem::{forget, ManuallyDrop}, ops::{Deref, DerefMut}, ptr::{self, drop_in_place}
 };
+#[cfg(feature = "debug")]
+use core::{
+    ptr::null_mut,
+    sync::atomic::{AtomicPtr, Ordering::SeqCst},
+};
+#[cfg(feature = "debug")]
+use wdk_sys::{ntddk::PsGetCurrentThreadId, HANDLE};
 use wdk_sys::{
     ntddk::{
-        ExAcquireFastMutex, ExAllocatePool2, ExFreePool, ExReleaseFastMutex, KeGetCurrentIrql,
-        KeInitializeEvent,
+        ExAcquireFastMutex, ExAcquireFastMutexUnsafe, ExAllocatePool2, ExFreePool, ExReleaseFastMutex,
+        ExReleaseFastMutexUnsafe, ExTryToAcquireFastMutex, KeGetCurrentIrql, KeInitializeEvent,
     },
     APC_LEVEL, DISPATCH_LEVEL, FALSE, FAST_MUTEX, FM_LOCK_BIT, POOL_FLAG_NON_PAGED,
     _EVENT_TYPE::SynchronizationEvent,
@@ -16,6 +23,10 @@ use wdk_sys::{
 extern crate alloc;
 
 use crate::errors::DriverMutexError;
+#[cfg(feature = "debug")]
+use crate::irql::check_release_irql;
+#[cfg(feature = "stats")]
+use crate::stats::{MutexStats, StatCounters};
 
 /// An internal binding for the ExInitializeFastMutex routine.
 ///
@@ -121,6 +132,14 @@ pub struct FastMutex<T> {
 /// The underlying data which is non-page pool allocated which is pointed to by the `FastMutex`.
 struct FastMutexInner<T> {
     mutex: FAST_MUTEX,
+    /// The pool tag this structure was allocated with
+    tag: u32,
+    /// Acquisition and contention counters
+    #[cfg(feature = "stats")]
+    stats: StatCounters,
+    /// The thread ID of the thread holding the mutex, or null if it is free
+    #[cfg(feature = "debug")]
+    owner: AtomicPtr<c_void>,
     /// The data for which the mutex is protecting
     data: T,
 }
@@ -143,6 +162,31 @@ impl<T> FastMutex<T> {
     /// let my_mutex = wdk_mutex::FastMutex::new(0u32);
     /// ```
     pub fn new(data: T) -> Result<Self, DriverMutexError> {
+        Self::new_with_tag(data, u32::from_be_bytes(*b"kmtx"))
+    }
+
+    /// Creates a new `FAST_MUTEX` Windows Kernel Driver Mutex, allocating it with the pool tag `tag` rather than the
+    /// default `kmtx`.
+    ///
+    /// As with [`crate::kmutex::KMutex::new_with_tag`], a distinct tag per subsystem allows pool usage (and leaks) to
+    /// be attributed with tools such as `!poolused` in WinDbg. The tag can be read back with [`Self::tag`].
+    ///
+    /// # Paged data
+    ///
+    /// Unlike [`crate::kmutex::KMutex::new_paged`], there is no paged variant. The `FAST_MUTEX` contains a
+    /// `KEVENT`, a dispatcher object which the kernel may touch at `DISPATCH_LEVEL`, so the control block must always
+    /// be non-paged, and the data is stored inline within the same allocation.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at IRQL <= DISPATCH_LEVEL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let net_state = FastMutex::new_with_tag(NetState::default(), u32::from_be_bytes(*b"net1")).unwrap();
+    /// ```
+    pub fn new_with_tag(data: T, tag: u32) -> Result<Self, DriverMutexError> {
         // This can only be called at a level <= DISPATCH_LEVEL; check current IRQL
         // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-exinitializefastmutex
         if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
@@ -153,15 +197,12 @@ impl<T> FastMutex<T> {
         // Non-Paged heap alloc for all struct data required for FastMutexInner
         //
         let total_sz_required = size_of::<FastMutexInner<T>>();
-        let inner_heap_ptr: *mut c_void = unsafe {
-            ExAllocatePool2(
-                POOL_FLAG_NON_PAGED,
-                total_sz_required as u64,
-                u32::from_be_bytes(*b"kmtx"),
-            )
-        };
+        let inner_heap_ptr: *mut c_void =
+            unsafe { ExAllocatePool2(POOL_FLAG_NON_PAGED, total_sz_required as u64, tag) };
         if inner_heap_ptr.is_null() {
-            return Err(DriverMutexError::PagedPoolAllocFailed);
+            return Err(DriverMutexError::AllocFailed {
+                requested: total_sz_required,
+            });
         }
 
         // Cast the memory allocation to a pointer to the inner
@@ -173,6 +214,11 @@ impl<T> FastMutex<T> {
                 fast_mtx_inner_ptr,
                 FastMutexInner {
                     mutex: FAST_MUTEX::default(),
+                    tag,
+                    #[cfg(feature = "stats")]
+                    stats: StatCounters::new(),
+                    #[cfg(feature = "debug")]
+                    owner: AtomicPtr::new(null_mut()),
                     data,
                 },
             );
@@ -205,6 +251,12 @@ impl<T> FastMutex<T> {
     /// will not alter the IRQL for the caller, as this may introduce undefined behaviour elsewhere in the
     /// driver / kernel.
     ///
+    /// # Recursion
+    ///
+    /// Unlike a [`crate::kmutex::KMutex`], a `FAST_MUTEX` is not recursive: a thread which already holds the mutex and
+    /// acquires it again deadlocks. With the `debug` feature enabled, the owning thread is recorded and such an
+    /// acquisition returns [`DriverMutexError::WouldDeadlock`] instead. The check is compiled out otherwise.
+    ///
     /// # Examples
     ///
     /// ```
@@ -219,10 +271,316 @@ impl<T> FastMutex<T> {
             return Err(DriverMutexError::IrqlTooHigh);
         }
 
+        #[cfg(feature = "debug")]
+        self.check_not_owner()?;
+
+        // When counting contention, try the mutex first so that we know whether we had to wait for it
+        #[cfg(feature = "stats")]
+        let acquired = unsafe { ExTryToAcquireFastMutex(&mut (*self.inner).mutex) } != 0;
+        #[cfg(not(feature = "stats"))]
+        let acquired = false;
+
+        if !acquired {
+            // SAFETY: RAII manages pointer validity and IRQL checked.
+            unsafe { ExAcquireFastMutex(&mut (*self.inner).mutex as *mut _ as *mut _) };
+        }
+
+        #[cfg(feature = "stats")]
+        unsafe { (*self.inner).stats.record_acquire(!acquired) };
+
+        #[cfg(feature = "debug")]
+        self.set_owner(unsafe { PsGetCurrentThreadId() });
+
+        Ok(FastMutexGuard {
+            fast_mutex: self,
+            owned: true,
+            #[cfg(feature = "debug")]
+            irql_at_acquire: irql,
+        })
+    }
+
+    /// Attempts to acquire the mutex without waiting, raising the IRQL to `APC_LEVEL` on success.
+    ///
+    /// This calls `ExTryToAcquireFastMutex`. If the mutex is free, it is acquired and a `FastMutexGuard` is returned
+    /// as per [`Self::lock`], which releases the mutex (and restores the IRQL) when dropped. If the mutex is held,
+    /// [`DriverMutexError::WouldBlock`] is returned immediately rather than waiting.
+    ///
+    /// # Errors
+    ///
+    /// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `APC_LEVEL`.
+    /// - [`DriverMutexError::WouldBlock`] if the mutex is held.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`, if the IRQL is higher than this, the function will return
+    /// an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// match mtx.try_lock() {
+    ///     Ok(mut lock) => *lock += 1,
+    ///     Err(DriverMutexError::WouldBlock) => { /* Busy, try again later */ }
+    ///     Err(e) => println!("Error: {:?}", e),
+    /// }
+    /// ```
+    pub fn try_lock(&self) -> Result<FastMutexGuard<'_, T>, DriverMutexError> {
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-extrytoacquirefastmutex
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
         // SAFETY: RAII manages pointer validity and IRQL checked.
-        unsafe { ExAcquireFastMutex(&mut (*self.inner).mutex as *mut _ as *mut _) };
+        let acquired = unsafe { ExTryToAcquireFastMutex(&mut (*self.inner).mutex) } != 0;
+        if !acquired {
+            #[cfg(feature = "stats")]
+            unsafe { (*self.inner).stats.record_contention() };
+
+            return Err(DriverMutexError::WouldBlock);
+        }
+
+        #[cfg(feature = "stats")]
+        unsafe { (*self.inner).stats.record_acquire(false) };
+
+        #[cfg(feature = "debug")]
+        self.set_owner(unsafe { PsGetCurrentThreadId() });
+
+        Ok(FastMutexGuard {
+            fast_mutex: self,
+            owned: true,
+            #[cfg(feature = "debug")]
+            irql_at_acquire: irql,
+        })
+    }
+
+    /// Attempts to acquire the mutex, polling it at most `spins` times before giving up.
+    ///
+    /// Unlike a `KMUTEX`, a `FAST_MUTEX` cannot be waited on with a timeout: `ExAcquireFastMutex` waits indefinitely,
+    /// so there is no `FastMutex` equivalent of [`crate::kmutex::KMutex::lock_timeout`]. This approximates one by
+    /// polling the mutex with `ExTryToAcquireFastMutex` up to `spins` times, returning
+    /// [`DriverMutexError::WouldBlock`] if it is not acquired. A budget of `0` polls the mutex once, as per
+    /// [`Self::try_lock`].
+    ///
+    /// The thread spins rather than sleeping between polls, so the budget should be small; the time it represents
+    /// depends on the processor. Where a mutex may be held for a long time and callers need to give up after a given
+    /// interval, use a [`crate::kmutex::KMutex`] and its `lock_timeout` instead.
+    ///
+    /// With the `stats` feature, a call which does not acquire the mutex on the first poll is counted as a single
+    /// contention.
+    ///
+    /// # Errors
+    ///
+    /// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `APC_LEVEL`.
+    /// - [`DriverMutexError::WouldBlock`] if the mutex was not acquired within the budget.
+    /// - [`DriverMutexError::WouldDeadlock`] if the current thread already holds the mutex, with the `debug`
+    ///   feature enabled.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// match mtx.try_lock_for(1_000) {
+    ///     Ok(mut lock) => *lock += 1,
+    ///     Err(DriverMutexError::WouldBlock) => { /* Still busy, defer the work */ }
+    ///     Err(e) => println!("Error: {:?}", e),
+    /// }
+    /// ```
+    #[doc(alias = "lock_timeout")]
+    pub fn try_lock_for(&self, spins: u32) -> Result<FastMutexGuard<'_, T>, DriverMutexError> {
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-extrytoacquirefastmutex
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // A held mutex would never be released by polling from the thread which holds it
+        #[cfg(feature = "debug")]
+        self.check_not_owner()?;
+
+        for attempt in 0..=spins {
+            // SAFETY: RAII manages pointer validity and IRQL checked.
+            if unsafe { ExTryToAcquireFastMutex(&mut (*self.inner).mutex) } != 0 {
+                #[cfg(feature = "stats")]
+                unsafe { (*self.inner).stats.record_acquire(attempt != 0) };
+
+                #[cfg(feature = "debug")]
+                self.set_owner(unsafe { PsGetCurrentThreadId() });
+
+                return Ok(FastMutexGuard {
+                    fast_mutex: self,
+                    owned: true,
+                    #[cfg(feature = "debug")]
+                    irql_at_acquire: irql,
+                });
+            }
+
+            if attempt != spins {
+                spin_loop();
+            }
+        }
+
+        #[cfg(feature = "stats")]
+        unsafe { (*self.inner).stats.record_contention() };
+
+        Err(DriverMutexError::WouldBlock)
+    }
+
+    /// Acquires the mutex with `ExAcquireFastMutexUnsafe`, which neither raises the IRQL nor disables APCs.
+    ///
+    /// This is intended for code which has already disabled APCs itself, for example through `KeEnterGuardedRegion`
+    /// or `KeEnterCriticalRegion`, or which is already running at `APC_LEVEL`, and wishes to avoid the IRQL change
+    /// made by [`Self::lock`]. The returned [`FastMutexUnsafeGuard`] releases the mutex with
+    /// `ExReleaseFastMutexUnsafe` when dropped.
+    ///
+    /// Despite the name, the mutex may not be acquired at `DISPATCH_LEVEL`, as acquiring a held mutex waits.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that, for the whole time the guard is held:
+    ///
+    /// - The IRQL is `APC_LEVEL`, or the IRQL is `PASSIVE_LEVEL` and the thread is inside a guarded region
+    ///   (`KeEnterGuardedRegion`) or critical region (`KeEnterCriticalRegion`), so that no APC can run on the thread
+    ///   and attempt to acquire the same mutex.
+    /// - The region is not left, and the IRQL is not changed, until the guard has been dropped.
+    /// - The mutex is not also acquired through [`Self::lock`] by the same thread, which would deadlock.
+    ///
+    /// # Errors
+    ///
+    /// If the IRQL is above `APC_LEVEL`, this function will return an error and will not acquire a lock.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL` with APCs disabled, see the safety section.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// unsafe {
+    ///     KeEnterGuardedRegion();
+    ///     {
+    ///         let mut lock = mtx.lock_unsafe_dispatch().unwrap();
+    ///         *lock += 1;
+    ///     }
+    ///     KeLeaveGuardedRegion();
+    /// }
+    /// ```
+    pub unsafe fn lock_unsafe_dispatch(&self) -> Result<FastMutexUnsafeGuard<'_, T>, DriverMutexError> {
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-exacquirefastmutexunsafe
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        #[cfg(feature = "debug")]
+        self.check_not_owner()?;
+
+        // SAFETY: RAII manages pointer validity, the IRQL is checked and the caller guarantees APCs are disabled.
+        unsafe { ExAcquireFastMutexUnsafe(&mut (*self.inner).mutex) };
+
+        #[cfg(feature = "stats")]
+        unsafe { (*self.inner).stats.record_acquire(false) };
+
+        #[cfg(feature = "debug")]
+        self.set_owner(unsafe { PsGetCurrentThreadId() });
 
-        Ok(FastMutexGuard { fast_mutex: self })
+        Ok(FastMutexUnsafeGuard { fast_mutex: self })
+    }
+
+    /// Returns a mutable reference to the protected data, without acquiring the mutex.
+    ///
+    /// As this takes `&mut self`, the borrow checker guarantees no other reference to the mutex (and so no guard)
+    /// exists, so the mutex does not need to be acquired and the IRQL is not raised. This is useful during single
+    /// threaded initialisation before the mutex is shared.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL, however the data is subject to the usual rules for the memory it refers to.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut mtx = FastMutex::new(0u32).unwrap();
+    /// *mtx.get_mut() = 10;
+    /// ```
+    pub fn get_mut(&mut self) -> &mut T {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation, and `&mut self`
+        // guarantees exclusive access.
+        unsafe { &mut (*self.inner).data }
+    }
+
+    /// Returns the pool tag the mutex was allocated with.
+    ///
+    /// This is the tag which the allocation is attributed to in tools such as `!poolused`.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mtx = FastMutex::new(0u32).unwrap();
+    /// assert_eq!(mtx.tag(), u32::from_be_bytes(*b"kmtx"));
+    /// ```
+    pub fn tag(&self) -> u32 {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        unsafe { (*self.inner).tag }
+    }
+
+    /// Returns a snapshot of the acquisition and contention counters for the mutex.
+    ///
+    /// Only available with the `stats` feature.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> MutexStats {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        unsafe { (*self.inner).stats.snapshot() }
+    }
+
+    /// Returns the number of times the mutex has been acquired, as per [`Self::stats`].
+    ///
+    /// Only available with the `stats` (or `metrics`) feature.
+    #[cfg(feature = "stats")]
+    pub fn acquire_count(&self) -> u64 {
+        self.stats().acquisitions
+    }
+
+    /// Returns the number of acquisition attempts which found the mutex already held, as per [`Self::stats`].
+    ///
+    /// Only available with the `stats` (or `metrics`) feature.
+    #[cfg(feature = "stats")]
+    pub fn contended_count(&self) -> u64 {
+        self.stats().contentions
+    }
+
+    /// Returns [`DriverMutexError::WouldDeadlock`] if the current thread already holds the mutex, as a `FAST_MUTEX` is
+    /// not recursive and acquiring it again would never return.
+    #[cfg(feature = "debug")]
+    fn check_not_owner(&self) -> Result<(), DriverMutexError> {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        let owner = unsafe { (*self.inner).owner.load(SeqCst) };
+        if owner == unsafe { PsGetCurrentThreadId() } {
+            return Err(DriverMutexError::WouldDeadlock);
+        }
+
+        Ok(())
+    }
+
+    /// Records the thread holding the mutex, or null once it is released.
+    #[cfg(feature = "debug")]
+    fn set_owner(&self, owner: HANDLE) {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        unsafe { (*self.inner).owner.store(owner, SeqCst) };
     }
 
     /// Consumes the mutex and returns an owned copy of the protected data (`T`).
@@ -340,6 +698,12 @@ impl<T> Drop for FastMutex<T> {
 ///
 pub struct FastMutexGuard<'a, T> {
     fast_mutex: &'a FastMutex<T>,
+    /// False once the guard no longer owns the mutex, after `drop_safe`
+    owned: bool,
+    /// The IRQL the caller was at before the mutex raised it, reported if the guard is dropped at any IRQL other
+    /// than `APC_LEVEL`
+    #[cfg(feature = "debug")]
+    irql_at_acquire: u8,
 }
 
 impl<T> Display for FastMutexGuard<'_, T>
@@ -347,8 +711,26 @@ where
     T: Display,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
-        write!(f, "{}", unsafe { &(*self.fast_mutex.inner).data })
+        write!(f, "{}", &**self)
+    }
+}
+
+/// Compares the protected data against a value, so `guard == 5` may be written in place of `*guard == 5`.
+impl<T> PartialEq<T> for FastMutexGuard<'_, T>
+where
+    T: PartialEq,
+{
+    fn eq(&self, other: &T) -> bool {
+        **self == *other
+    }
+}
+
+impl<T> core::fmt::Write for FastMutexGuard<'_, T>
+where
+    T: core::fmt::Write,
+{
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        (**self).write_str(s)
     }
 }
 
@@ -356,6 +738,8 @@ impl<T> Deref for FastMutexGuard<'_, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
+        self.assert_owned();
+
         // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
         unsafe { &(*self.fast_mutex.inner).data }
     }
@@ -363,6 +747,8 @@ impl<T> Deref for FastMutexGuard<'_, T> {
 
 impl<T> DerefMut for FastMutexGuard<'_, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        self.assert_owned();
+
         // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
         // Mutable access is safe due to Self only being given out whilst a mutex is held from the
         // kernel.
@@ -372,15 +758,66 @@ impl<T> DerefMut for FastMutexGuard<'_, T> {
 
 impl<T> Drop for FastMutexGuard<'_, T> {
     fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
+
+        #[cfg(feature = "debug")]
+        check_release_irql("FastMutexGuard", self.irql_at_acquire, APC_LEVEL as u8, APC_LEVEL as u8);
+
+        #[cfg(feature = "debug")]
+        self.fast_mutex.set_owner(null_mut());
+
         // NOT SAFE AT AN INVALID IRQL
         unsafe { ExReleaseFastMutex(&mut (*self.fast_mutex.inner).mutex) };
     }
 }
 
+impl<'a, T> FastMutexGuard<'a, T> {
+    /// Projects the guard onto a part of the protected data, such as a field, returning a guard which derefs to that
+    /// part only.
+    ///
+    /// The mutex remains held until the returned [`MappedFastMutexGuard`] is dropped, as per
+    /// [`crate::kmutex::KMutexGuard::map`].
+    ///
+    /// This is an associated function rather than a method, so that it does not shadow a `map` method on `T`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let config = FastMutex::new(Config::default()).unwrap();
+    ///
+    /// let mut timeout = FastMutexGuard::map(config.lock().unwrap(), |config| &mut config.timeout);
+    /// *timeout = 30;
+    /// ```
+    pub fn map<U>(mut guard: Self, f: impl FnOnce(&mut T) -> &mut U) -> MappedFastMutexGuard<'a, U> {
+        let data: *mut U = f(&mut guard);
+
+        let inner = guard.fast_mutex.inner;
+
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        let mapped = MappedFastMutexGuard {
+            mutex: unsafe { ptr::addr_of_mut!((*inner).mutex) },
+            data,
+            #[cfg(feature = "debug")]
+            owner: unsafe { ptr::addr_of!((*inner).owner) },
+            #[cfg(feature = "debug")]
+            irql_at_acquire: guard.irql_at_acquire,
+            _marker: PhantomData,
+        };
+
+        // Responsibility for releasing the mutex passes to the mapped guard
+        forget(guard);
+
+        mapped
+    }
+}
+
 impl<T> FastMutexGuard<'_, T> {
     /// Safely drop the `FastMutexGuard`, an alternative to RAII.
     ///
-    /// This function checks the IRQL before attempting to drop the guard.
+    /// This function checks the IRQL before attempting to drop the guard. Once the mutex has been released, the guard
+    /// no longer owns it: dropping the guard afterwards does nothing, and dereferencing it panics.
     ///
     /// # Errors
     ///
@@ -396,8 +833,143 @@ impl<T> FastMutexGuard<'_, T> {
             return Err(DriverMutexError::IrqlTooHigh);
         }
 
-        unsafe { ExReleaseFastMutex(&mut (*self.fast_mutex.inner).mutex) };
+        if self.owned {
+            #[cfg(feature = "debug")]
+            self.fast_mutex.set_owner(null_mut());
+
+            unsafe { ExReleaseFastMutex(&mut (*self.fast_mutex.inner).mutex) };
+            self.owned = false;
+        }
 
         Ok(())
     }
+
+    /// Panics if the guard no longer owns the mutex.
+    fn assert_owned(&self) {
+        assert!(self.owned, "FastMutexGuard used after it released the mutex");
+    }
+}
+
+/// A RAII scoped guard for part of the data protected by a [`FastMutex`], created by [`FastMutexGuard::map`].
+///
+/// When this structure is dropped (falls out of scope), the mutex is released.
+///
+/// # IRQL
+///
+/// As per [`FastMutexGuard`].
+pub struct MappedFastMutexGuard<'a, U> {
+    /// The mutex which is held, and released when the guard is dropped
+    mutex: *mut FAST_MUTEX,
+    /// The projected part of the protected data
+    data: *mut U,
+    /// The owner recorded by the mutex, cleared when the guard is dropped
+    #[cfg(feature = "debug")]
+    owner: *const AtomicPtr<c_void>,
+    /// As per `FastMutexGuard`
+    #[cfg(feature = "debug")]
+    irql_at_acquire: u8,
+    _marker: PhantomData<&'a mut U>,
+}
+
+impl<'a, U> MappedFastMutexGuard<'a, U> {
+    /// Projects the guard further onto a part of `U`, as per [`FastMutexGuard::map`].
+    pub fn map<V>(guard: Self, f: impl FnOnce(&mut U) -> &mut V) -> MappedFastMutexGuard<'a, V> {
+        // Responsibility for releasing the mutex passes to the new mapped guard
+        let guard = ManuallyDrop::new(guard);
+        let data = guard.data;
+
+        // SAFETY: The data is valid and exclusively accessible whilst the mutex is held by the guard.
+        let data: *mut V = f(unsafe { &mut *data });
+
+        MappedFastMutexGuard {
+            mutex: guard.mutex,
+            data,
+            #[cfg(feature = "debug")]
+            owner: guard.owner,
+            #[cfg(feature = "debug")]
+            irql_at_acquire: guard.irql_at_acquire,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<U> Display for MappedFastMutexGuard<'_, U>
+where
+    U: Display,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", &**self)
+    }
+}
+
+impl<U> Deref for MappedFastMutexGuard<'_, U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: The data is valid and exclusively accessible whilst the mutex is held by the guard.
+        unsafe { &*self.data }
+    }
+}
+
+impl<U> DerefMut for MappedFastMutexGuard<'_, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: The data is valid and exclusively accessible whilst the mutex is held by the guard.
+        unsafe { &mut *self.data }
+    }
+}
+
+impl<U> Drop for MappedFastMutexGuard<'_, U> {
+    fn drop(&mut self) {
+        #[cfg(feature = "debug")]
+        check_release_irql("MappedFastMutexGuard", self.irql_at_acquire, APC_LEVEL as u8, APC_LEVEL as u8);
+
+        // SAFETY: The owner lives in the mutex allocation, which outlives the guard.
+        #[cfg(feature = "debug")]
+        unsafe { (*self.owner).store(null_mut(), SeqCst) };
+
+        // NOT SAFE AT AN INVALID IRQL
+        unsafe { ExReleaseFastMutex(self.mutex) };
+    }
+}
+
+/// A RAII scoped guard for a [`FastMutex`] acquired through [`FastMutex::lock_unsafe_dispatch`].
+///
+/// When this structure is dropped, the mutex is released with `ExReleaseFastMutexUnsafe`, which does not alter the
+/// IRQL.
+///
+/// # IRQL
+///
+/// This guard must be dropped at the same IRQL, and within the same guarded or critical region, as the mutex was
+/// acquired in.
+pub struct FastMutexUnsafeGuard<'a, T> {
+    fast_mutex: &'a FastMutex<T>,
+}
+
+impl<T> Deref for FastMutexUnsafeGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        unsafe { &(*self.fast_mutex.inner).data }
+    }
+}
+
+impl<T> DerefMut for FastMutexUnsafeGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        // Mutable access is safe due to Self only being given out whilst a mutex is held from the
+        // kernel.
+        unsafe { &mut (*self.fast_mutex.inner).data }
+    }
+}
+
+impl<T> Drop for FastMutexUnsafeGuard<'_, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "debug")]
+        self.fast_mutex.set_owner(null_mut());
+
+        // SAFETY: The mutex was acquired with ExAcquireFastMutexUnsafe, and the caller of `lock_unsafe_dispatch`
+        // guarantees the IRQL and APC state are unchanged.
+        unsafe { ExReleaseFastMutexUnsafe(&mut (*self.fast_mutex.inner).mutex) };
+    }
 }
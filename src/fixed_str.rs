@@ -0,0 +1,118 @@
+//! A fixed capacity, stack allocated string buffer suitable for formatting into whilst holding a lock.
+
+use core::{
+    fmt::{self, Debug, Display, Write},
+    ops::Deref,
+    str,
+};
+
+/// A UTF-8 string buffer with a fixed capacity of `N` bytes, which never allocates.
+///
+/// `FixedStr` implements [`core::fmt::Write`], as do [`crate::kmutex::KMutexGuard`] and
+/// [`crate::fast_mutex::FastMutexGuard`] whenever their protected data does, so `write!` can be used to append to a
+/// `FixedStr` protected by a mutex, for example to build up a log line under the lock.
+///
+/// # Buffer full behaviour
+///
+/// When a write does not fit in the remaining capacity, as much of the string as fits is appended (truncated on a
+/// `char` boundary, so the buffer always holds valid UTF-8) and the write returns [`core::fmt::Error`]. The truncated
+/// contents remain in the buffer.
+///
+/// # Examples
+///
+/// ```
+/// let line = KMutex::new(FixedStr::<128>::new()).unwrap();
+///
+/// {
+///     let mut line = line.lock().unwrap();
+///     write!(line, "pid {} opened handle {:#x}", pid, handle).unwrap();
+/// }
+/// ```
+#[derive(Clone, Copy)]
+pub struct FixedStr<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> FixedStr<N> {
+    /// Creates a new, empty `FixedStr`.
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Returns the contents of the buffer as a string slice.
+    pub fn as_str(&self) -> &str {
+        // SAFETY: Only whole UTF-8 sequences are ever copied into `buf[..len]`.
+        unsafe { str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+
+    /// Returns the length of the contents in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the capacity of the buffer in bytes, `N`.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Empties the buffer.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> Write for FixedStr<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let remaining = N - self.len;
+
+        // Truncate to the largest prefix which fits and ends on a char boundary
+        let mut take = s.len().min(remaining);
+        while !s.is_char_boundary(take) {
+            take -= 1;
+        }
+
+        self.buf[self.len..self.len + take].copy_from_slice(&s.as_bytes()[..take]);
+        self.len += take;
+
+        if take < s.len() {
+            return Err(fmt::Error);
+        }
+
+        Ok(())
+    }
+}
+
+impl<const N: usize> Default for FixedStr<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Deref for FixedStr<N> {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+
+impl<const N: usize> Display for FixedStr<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<const N: usize> Debug for FixedStr<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
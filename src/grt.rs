@@ -4,17 +4,38 @@
 
 extern crate alloc;
 
-use crate::{errors::GrtError, fast_mutex::FastMutex, kmutex::KMutex};
-use alloc::{boxed::Box, collections::BTreeMap};
+#[cfg(feature = "stats")]
+use crate::stats::MutexStats;
+use crate::{
+    errors::{DriverMutexError, GrtError},
+    fast_mutex::{FastMutex, FastMutexGuard},
+    kmutex::KMutex,
+};
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
 use core::{
     any::Any,
+    borrow::Borrow,
+    ffi::c_void,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
     ptr::null_mut,
-    sync::atomic::{AtomicPtr, Ordering::SeqCst},
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering::SeqCst},
+};
+use wdk_sys::{
+    ntddk::{KeGetCurrentIrql, ObReferenceObjectByHandle, PsCreateSystemThread, PsThreadType, ZwClose},
+    _MODE::KernelMode,
+    APC_LEVEL, HANDLE, OBJECT_ATTRIBUTES, OBJ_KERNEL_HANDLE, PASSIVE_LEVEL, PETHREAD, STATUS_SUCCESS, THREAD_ALL_ACCESS,
 };
 
 // A static which points to an initialised box containing the `Grt`
 static WDK_MTX_GRT_PTR: AtomicPtr<Grt> = AtomicPtr::new(null_mut());
 
+// The number of live `GrtHandle`s, checked by `Grt::destroy_when_idle`
+static WDK_MTX_GRT_HANDLES: AtomicUsize = AtomicUsize::new(0);
+
+// The number of calls currently using the `Grt` through `GrtEntrant`, checked by `Grt::destroy_when_idle`
+static WDK_MTX_GRT_ENTRANTS: AtomicUsize = AtomicUsize::new(0);
+
 /// The Global Reference Tracker (Grt) for `wdk-mutex` is a module designed to improve the development ergonomics
 /// of manually managing memory in a driver required for tracking objects passed between threads.
 ///
@@ -30,6 +51,23 @@ static WDK_MTX_GRT_PTR: AtomicPtr<Grt> = AtomicPtr::new(null_mut());
 ///
 /// [`Self::get_kmutex`] / [`Self::get_fast_mutex`] etc will then allow you to retrieve the `Mutex` dynamically.
 ///
+/// Where a key is only known at runtime (for example, derived from a device instance ID), the `_owned` family of
+/// functions such as [`Self::register_kmutex_owned`] and [`Self::get_kmutex_owned`] take an owned [`String`] key
+/// instead. Owned keys are held separately from `&'static str` keys, so a mutex registered through one API is not
+/// visible through the other.
+///
+/// # Thread safety
+///
+/// The map of registered mutexes is itself protected by a [`FastMutex`], so registering, looking up and removing
+/// mutexes may be done concurrently from any number of threads. As a result, every function which accesses the
+/// registered mutexes must be called at IRQL `<= APC_LEVEL`.
+///
+/// The getters return `&'static` references to the registered mutex. Each mutex is boxed, so it does not move when
+/// other entries are inserted or removed, and it is only dropped when its entry is removed with
+/// [`Self::remove_mutex`], replaced with [`Self::replace`] or overwritten by a `register_` function, or when the `Grt`
+/// is destroyed. Each of those requires that no reference to an affected mutex is still in use, which is what makes
+/// the `'static` lifetime sound; the `_checked` registration functions never overwrite an entry.
+///
 /// # Examples
 ///
 /// ```
@@ -75,7 +113,56 @@ static WDK_MTX_GRT_PTR: AtomicPtr<Grt> = AtomicPtr::new(null_mut());
 /// }
 /// ```
 pub struct Grt {
-    global_kmutex: BTreeMap<&'static str, Box<dyn Any>>,
+    global_kmutex: FastMutex<GrtMap>,
+}
+
+/// The maps of registered mutexes, protected as a whole by the `Grt`'s [`FastMutex`].
+struct GrtMap {
+    /// Mutexes registered under a `&'static str` label
+    labels: BTreeMap<&'static str, Box<dyn GrtEntry>>,
+    /// Mutexes registered under a key only known at runtime
+    owned: BTreeMap<String, Box<dyn GrtEntry>>,
+}
+
+/// The type erased form in which mutexes are stored in the `Grt`, allowing them to be downcast back to their concrete
+/// type, and allowing per mutex information to be read without knowing the type of the protected data.
+trait GrtEntry {
+    fn as_any(&self) -> &dyn Any;
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> MutexStats;
+}
+
+impl<T: Any> GrtEntry for KMutex<T> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> MutexStats {
+        KMutex::stats(self)
+    }
+}
+
+impl<T: Any> GrtEntry for FastMutex<T> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> MutexStats {
+        FastMutex::stats(self)
+    }
 }
 
 /// The type of mutexes which is passed in to the Grt to correctly initialise a new `mutex`.
@@ -84,6 +171,316 @@ pub enum MutexType {
     KMutex,
 }
 
+/// A typed key for a [`KMutex`] stored in the [`Grt`], bundling the string key with the type of the protected data.
+///
+/// Looking a mutex up by a plain `&str` requires a turbofish specifier on every call, and a mismatch between the type
+/// registered and the type requested only surfaces at runtime as [`GrtError::DowncastError`]. A `GrtKey<T>` is passed
+/// to [`Grt::register`] and [`Grt::get`] instead, which take the type from the key, so a mismatch is a compile error at
+/// the call site.
+///
+/// Keys are declared with the [`crate::grt_key`] macro, which names the key after the constant.
+///
+/// # Examples
+///
+/// ```
+/// grt_key!(pub MY_CONFIG: Config);
+///
+/// Grt::register(MY_CONFIG, Config::default()).unwrap();
+///
+/// let config: &'static KMutex<Config> = Grt::get(MY_CONFIG).unwrap();
+///
+/// // Does not compile: MY_CONFIG is a `GrtKey<Config>`, not a `GrtKey<u32>`
+/// // Grt::register(MY_CONFIG, 0u32);
+/// ```
+pub struct GrtKey<T> {
+    name: &'static str,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> GrtKey<T> {
+    /// Creates a typed key with the given name. Prefer the [`crate::grt_key`] macro.
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The string key the mutex is stored under in the `Grt`.
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<T> Clone for GrtKey<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for GrtKey<T> {}
+
+/// Declares a constant [`GrtKey`], using the name of the constant as the string key.
+///
+/// `grt_key!(MY_KEY: Config)` expands to `const MY_KEY: GrtKey<Config> = GrtKey::new("MY_KEY");`. A visibility and
+/// attributes (such as doc comments) may precede the name.
+///
+/// # Examples
+///
+/// ```
+/// grt_key!(
+///     /// Tracks the number of open handles
+///     pub HANDLE_COUNT: u64
+/// );
+///
+/// Grt::register(HANDLE_COUNT, 0).unwrap();
+/// *Grt::get(HANDLE_COUNT).unwrap().lock().unwrap() += 1;
+/// ```
+#[macro_export]
+macro_rules! grt_key {
+    ($(#[$meta:meta])* $vis:vis $name:ident : $ty:ty) => {
+        $(#[$meta])*
+        $vis const $name: $crate::grt::GrtKey<$ty> = $crate::grt::GrtKey::new(stringify!($name));
+    };
+}
+
+/// A counted reference to a mutex held by the [`Grt`], returned by [`Grt::get_kmutex_handle`] and
+/// [`Grt::get_fast_mutex_handle`].
+///
+/// The handle dereferences to the mutex. While any handle is alive, [`Grt::destroy_when_idle`] refuses to tear down
+/// the `Grt`, returning [`GrtError::StillInUse`], so driver unload can wait for callbacks and threads to finish with
+/// their mutexes rather than relying on the developer to prove none are active. Cloning a handle increments the count,
+/// and dropping it decrements the count.
+///
+/// # Examples
+///
+/// ```
+/// unsafe extern "C" fn my_thread_fn_pointer(_: *mut c_void) {
+///     let Ok(my_mutex) = Grt::get_kmutex_handle::<u32>("my_test_mutex") else {
+///         return;
+///     };
+///
+///     *my_mutex.lock().unwrap() += 1;
+///
+///     // The handle is dropped here, allowing the `Grt` to be destroyed
+/// }
+/// ```
+pub struct GrtHandle<M: 'static> {
+    mutex: &'static M,
+}
+
+impl<M> Deref for GrtHandle<M> {
+    type Target = M;
+
+    fn deref(&self) -> &Self::Target {
+        self.mutex
+    }
+}
+
+impl<M> Clone for GrtHandle<M> {
+    fn clone(&self) -> Self {
+        WDK_MTX_GRT_HANDLES.fetch_add(1, SeqCst);
+        Self { mutex: self.mutex }
+    }
+}
+
+impl<M> Drop for GrtHandle<M> {
+    fn drop(&mut self) {
+        WDK_MTX_GRT_HANDLES.fetch_sub(1, SeqCst);
+    }
+}
+
+/// Marks the current call as using the `Grt`, so that [`Grt::destroy_when_idle`] does not free it in the meantime.
+///
+/// The entrant is counted before the `Grt` pointer is loaded, and is only released once the call has finished with
+/// the `Grt`, so a thread which loaded the pointer before `destroy_when_idle` unpublished it is always seen there.
+struct GrtEntrant;
+
+impl GrtEntrant {
+    /// Counts the current call as an entrant and returns the published `Grt`.
+    fn enter() -> Result<(Self, &'static Grt), GrtError> {
+        WDK_MTX_GRT_ENTRANTS.fetch_add(1, SeqCst);
+        let entrant = GrtEntrant;
+
+        let ptr = WDK_MTX_GRT_PTR.load(SeqCst);
+        if ptr.is_null() {
+            return Err(GrtError::GrtIsNull);
+        }
+
+        // SAFETY: The atomic pointer is checked above for a nullptr after this call was counted. `destroy_when_idle`
+        // only frees the `Grt` if it sees no entrants after unpublishing it, and `destroy` forbids concurrent use.
+        Ok((entrant, unsafe { &*ptr }))
+    }
+}
+
+impl Drop for GrtEntrant {
+    fn drop(&mut self) {
+        WDK_MTX_GRT_ENTRANTS.fetch_sub(1, SeqCst);
+    }
+}
+
+/// The locked map of registered mutexes, returned by `Grt::map`.
+struct GrtMapGuard {
+    // Fields are dropped in declaration order, so the lock is released before the entrant is
+    guard: FastMutexGuard<'static, GrtMap>,
+    _entrant: GrtEntrant,
+}
+
+impl Deref for GrtMapGuard {
+    type Target = GrtMap;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl DerefMut for GrtMapGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+/// The start context passed by [`Grt::spawn_with`] to the new thread.
+struct SpawnContext<T: 'static> {
+    handle: GrtHandle<KMutex<T>>,
+    routine: fn(&'static KMutex<T>),
+}
+
+/// The start routine of a thread created by [`Grt::spawn_with`], which runs the caller's routine with the mutex.
+unsafe extern "C" fn spawn_trampoline<T: 'static>(context: *mut c_void) {
+    // SAFETY: The context was leaked from a box by `spawn_with`, and ownership passed to this thread.
+    let context = unsafe { Box::from_raw(context as *mut SpawnContext<T>) };
+
+    (context.routine)(context.handle.mutex);
+
+    // The handle is released once the routine has returned, then the thread terminates on return
+}
+
+/// Collects several mutexes to be registered in the [`Grt`] together, created by [`Grt::builder`].
+///
+/// Each mutex is allocated as it is added, however nothing is inserted into the `Grt` until [`Self::commit`] (or
+/// [`Self::commit_checked`]) is called, which acquires the `Grt` once and inserts every entry in a single locked
+/// section. This avoids re-acquiring the `Grt` for each registration during driver initialisation, and means other
+/// threads never observe a partially registered set of mutexes.
+///
+/// If a mutex could not be allocated, the error is held by the builder and returned from `commit`, so the chain of
+/// calls does not need to be interrupted to check each one.
+///
+/// # Examples
+///
+/// ```
+/// Grt::builder()
+///     .with_kmutex("packet_count", 0u32)
+///     .with_kmutex("monitoring_enabled", false)
+///     .with_fast_mutex("config", Config::default())
+///     .commit()
+///     .unwrap();
+/// ```
+pub struct GrtBuilder {
+    entries: Vec<(&'static str, Box<dyn GrtEntry>)>,
+    error: Option<GrtError>,
+}
+
+impl GrtBuilder {
+    /// Adds a new [`KMutex`] protecting `data`, to be registered under `label`.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`.
+    pub fn with_kmutex<T: Any>(self, label: &'static str, data: T) -> Self {
+        self.with_entry(label, || {
+            Ok(Box::new(
+                KMutex::new(data).map_err(GrtError::DriverMutexError)?,
+            ))
+        })
+    }
+
+    /// Adds a new [`FastMutex`] protecting `data`, to be registered under `label`.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`.
+    pub fn with_fast_mutex<T: Any>(self, label: &'static str, data: T) -> Self {
+        self.with_entry(label, || {
+            Ok(Box::new(
+                FastMutex::new(data).map_err(GrtError::DriverMutexError)?,
+            ))
+        })
+    }
+
+    /// Creates an entry, unless an earlier entry already failed, recording any error for `commit`.
+    fn with_entry(
+        mut self,
+        label: &'static str,
+        create: impl FnOnce() -> Result<Box<dyn GrtEntry>, GrtError>,
+    ) -> Self {
+        if self.error.is_none() {
+            match create() {
+                Ok(mtx) => self.entries.push((label, mtx)),
+                Err(e) => self.error = Some(e),
+            }
+        }
+
+        self
+    }
+
+    /// Registers every mutex added to the builder, overwriting any existing mutex with the same key, as per
+    /// [`Grt::register_kmutex`].
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - A mutex added to the builder could not be created, in which case nothing is registered
+    /// - `Grt` has not been initialised, see [`Grt::init`]
+    /// - The IRQL is above `APC_LEVEL`
+    pub fn commit(self) -> Result<(), GrtError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        let mut map = Grt::map()?;
+        for (label, mtx) in self.entries {
+            map.labels.insert(label, mtx);
+        }
+
+        Ok(())
+    }
+
+    /// Registers every mutex added to the builder, provided none of the keys are already registered or repeated
+    /// within the builder.
+    ///
+    /// The keys are checked before anything is inserted, so either every mutex is registered or none are.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - A mutex added to the builder could not be created
+    /// - `Grt` has not been initialised, see [`Grt::init`]
+    /// - The IRQL is above `APC_LEVEL`
+    /// - Any of the keys already exists
+    pub fn commit_checked(self) -> Result<(), GrtError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        // Hold the lock across the checks and the inserts, so a concurrent registration cannot slip in between
+        let mut map = Grt::map()?;
+        for (i, (label, _)) in self.entries.iter().enumerate() {
+            if map.labels.contains_key(label) || self.entries[..i].iter().any(|(l, _)| l == label) {
+                return Err(GrtError::KeyExists);
+            }
+        }
+
+        for (label, mtx) in self.entries {
+            map.labels.insert(label, mtx);
+        }
+
+        Ok(())
+    }
+}
+
 impl Grt {
     /// Initialise a new instance of the Global Reference Tracker for `wdk-mutex`.
     ///
@@ -95,6 +492,11 @@ impl Grt {
     /// This function will error if:
     ///
     /// - You have already initialised the `Grt`
+    /// - The [`FastMutex`] protecting the `Grt` could not be allocated
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`.
     ///
     /// # Examples
     ///
@@ -125,14 +527,74 @@ impl Grt {
         //
 
         let pool_ptr = Box::into_raw(Box::new(Grt {
-            global_kmutex: BTreeMap::new(),
+            global_kmutex: FastMutex::new(GrtMap {
+                labels: BTreeMap::new(),
+                owned: BTreeMap::new(),
+            })
+            .map_err(GrtError::DriverMutexError)?,
         }));
 
-        WDK_MTX_GRT_PTR.store(pool_ptr, SeqCst);
+        // Another thread may have initialised the `Grt` since the check above, in which case free ours
+        if WDK_MTX_GRT_PTR
+            .compare_exchange(null_mut(), pool_ptr, SeqCst, SeqCst)
+            .is_err()
+        {
+            // SAFETY: The pointer was created from a box above and was never published
+            let _ = unsafe { Box::from_raw(pool_ptr) };
+            return Err(GrtError::GrtAlreadyExists);
+        }
 
         Ok(())
     }
 
+    /// Acquires the mutex protecting the map of registered mutexes.
+    fn map() -> Result<GrtMapGuard, GrtError> {
+        let (entrant, grt) = GrtEntrant::enter()?;
+        let guard = grt.global_kmutex.lock().map_err(GrtError::DriverMutexError)?;
+
+        Ok(GrtMapGuard {
+            guard,
+            _entrant: entrant,
+        })
+    }
+
+    /// Looks up `key` in `map` and downcasts the entry to the mutex type `M`.
+    fn get_entry<K, M>(
+        map: &BTreeMap<K, Box<dyn GrtEntry>>,
+        key: &str,
+    ) -> Result<&'static M, GrtError>
+    where
+        K: Borrow<str> + Ord,
+        M: Any,
+    {
+        //
+        // Perform checks for erroneous state
+        //
+        if map.is_empty() {
+            return Err(GrtError::GrtIsEmpty);
+        }
+
+        let mutex = map.get(key);
+        if mutex.is_none() {
+            return Err(GrtError::KeyNotFound);
+        }
+
+        //
+        // The mutex is valid so obtain a reference to it which can be returned
+        //
+
+        let m = &**mutex.unwrap();
+        let km: *const M = match m.as_any().downcast_ref::<M>() {
+            Some(km) => km,
+            None => return Err(GrtError::DowncastError),
+        };
+
+        // SAFETY: The mutex is boxed so does not move when the map is modified after the lock is released, and it is
+        // only dropped when its entry is removed, replaced or overwritten, or by `destroy`, all of which require that
+        // no reference to it is still in use.
+        Ok(unsafe { &*km })
+    }
+
     /// Register a new [`KMutex`] for the global reference tracker to control.
     ///
     /// The function takes a label as a static &str which is the key of a BTreeMap, and the type you wish
@@ -142,11 +604,16 @@ impl Grt {
     /// If you wish to perform this function checking for an existing key before registering the mutex object,
     /// use [`Self::register_kmutex_checked`].
     ///
+    /// **Note:** Overwriting an existing key drops the previous mutex, so as with [`Self::remove_mutex`], no reference
+    /// to (or guard for) the previous mutex may still be in use. Prefer [`Self::register_kmutex_checked`] for keys
+    /// which may already be registered and looked up.
+    ///
     /// # Errors
     ///
     /// This function will error if:
     ///
     /// - `Grt` has not been initialised, see [`Grt::init`]
+    /// - The IRQL is above `APC_LEVEL`
     ///
     /// # Examples
     ///
@@ -154,19 +621,10 @@ impl Grt {
     /// Grt::register_kmutex("my_test_mutex", 0u32);
     /// ```
     pub fn register_kmutex<T: Any>(label: &'static str, data: T) -> Result<(), GrtError> {
-        // Check for a null pointer on the atomic
-        let atomic_ptr = WDK_MTX_GRT_PTR.load(SeqCst);
-        if atomic_ptr.is_null() {
-            return Err(GrtError::GrtIsNull);
-        }
-
         // Try initialise a new mutex
         let mtx = Box::new(KMutex::new(data).map_err(|e| GrtError::DriverMutexError(e))?);
 
-        // SAFETY: The atomic pointer is checked at the start of the fn for a nullptr
-        unsafe {
-            (*atomic_ptr).global_kmutex.insert(label, mtx);
-        }
+        Self::map()?.labels.insert(label, mtx);
 
         Ok(())
     }
@@ -180,11 +638,16 @@ impl Grt {
     /// If you wish to perform this function checking for an existing key before registering the mutex object,
     /// use [`Self::register_fast_mutex_checked`].
     ///
+    /// **Note:** Overwriting an existing key drops the previous mutex, so as with [`Self::remove_mutex`], no reference
+    /// to (or guard for) the previous mutex may still be in use. Prefer [`Self::register_fast_mutex_checked`] for keys
+    /// which may already be registered and looked up.
+    ///
     /// # Errors
     ///
     /// This function will error if:
     ///
     /// - `Grt` has not been initialised, see [`Grt::init`]
+    /// - The IRQL is above `APC_LEVEL`
     ///
     /// # Examples
     ///
@@ -192,19 +655,10 @@ impl Grt {
     /// Grt::register_fast_mutex("my_test_mutex", 0u32);
     /// ```
     pub fn register_fast_mutex<T: Any>(label: &'static str, data: T) -> Result<(), GrtError> {
-        // Check for a null pointer on the atomic
-        let atomic_ptr = WDK_MTX_GRT_PTR.load(SeqCst);
-        if atomic_ptr.is_null() {
-            return Err(GrtError::GrtIsNull);
-        }
-
         // Try initialise a new mutex
         let mtx = Box::new(FastMutex::new(data).map_err(|e| GrtError::DriverMutexError(e))?);
 
-        // SAFETY: The atomic pointer is checked at the start of the fn for a nullptr
-        unsafe {
-            (*atomic_ptr).global_kmutex.insert(label, mtx);
-        }
+        Self::map()?.labels.insert(label, mtx);
 
         Ok(())
     }
@@ -219,6 +673,7 @@ impl Grt {
     /// This function will error if:
     ///
     /// - `Grt` has not been initialised, see [`Grt::init`]
+    /// - The IRQL is above `APC_LEVEL`
     /// - The mutex key already exists
     ///
     /// # Examples
@@ -227,25 +682,17 @@ impl Grt {
     /// let result = Grt::register_kmutex_checked("my_test_mutex", 0u32);
     /// ```
     pub fn register_kmutex_checked<T: Any>(label: &'static str, data: T) -> Result<(), GrtError> {
-        // Check for a null pointer on the atomic
-        let atomic_ptr = WDK_MTX_GRT_PTR.load(SeqCst);
-        if atomic_ptr.is_null() {
-            return Err(GrtError::GrtIsNull);
-        }
-
         // Try initialise a new mutex
         let mtx = Box::new(KMutex::new(data).map_err(|e| GrtError::DriverMutexError(e))?);
 
-        // SAFETY: The atomic pointer is checked at the start of the fn for a nullptr
-        unsafe {
-            let bucket = (*atomic_ptr).global_kmutex.get(label);
-            if bucket.is_some() {
-                return Err(GrtError::KeyExists);
-            }
-
-            (*atomic_ptr).global_kmutex.insert(label, mtx);
+        // Hold the lock across the check and the insert, so a concurrent registration cannot slip in between
+        let mut map = Self::map()?;
+        if map.labels.contains_key(label) {
+            return Err(GrtError::KeyExists);
         }
 
+        map.labels.insert(label, mtx);
+
         Ok(())
     }
 
@@ -259,6 +706,7 @@ impl Grt {
     /// This function will error if:
     ///
     /// - `Grt` has not been initialised, see [`Grt::init`]
+    /// - The IRQL is above `APC_LEVEL`
     /// - The mutex key already exists
     ///
     /// # Examples
@@ -270,88 +718,149 @@ impl Grt {
         label: &'static str,
         data: T,
     ) -> Result<(), GrtError> {
-        // Check for a null pointer on the atomic
-        let atomic_ptr = WDK_MTX_GRT_PTR.load(SeqCst);
-        if atomic_ptr.is_null() {
-            return Err(GrtError::GrtIsNull);
-        }
-
         // Try initialise a new mutex
         let mtx = Box::new(FastMutex::new(data).map_err(|e| GrtError::DriverMutexError(e))?);
 
-        // SAFETY: The atomic pointer is checked at the start of the fn for a nullptr
-        unsafe {
-            let bucket = (*atomic_ptr).global_kmutex.get(label);
-            if bucket.is_some() {
-                return Err(GrtError::KeyExists);
-            }
-
-            (*atomic_ptr).global_kmutex.insert(label, mtx);
+        // Hold the lock across the check and the insert, so a concurrent registration cannot slip in between
+        let mut map = Self::map()?;
+        if map.labels.contains_key(label) {
+            return Err(GrtError::KeyExists);
         }
 
+        map.labels.insert(label, mtx);
+
         Ok(())
     }
 
-    /// Retrieve a mutex by name from the `wdk-mutex` global reference tracker.
+    /// Returns the [`KMutex`] registered under `key`, registering a new one protecting the data returned by `init` if
+    /// the key does not exist.
     ///
-    /// This function takes in a static `&str` to lookup your Mutex by key (where the key is the argument). When calling
-    /// this function, a turbofish specifier is required to tell the compiler what type is contained in the `Mutex`. See
-    /// examples for more information.
+    /// The lookup and the registration happen under a single acquisition of the `Grt`, so if several threads race to
+    /// register the same key, exactly one mutex is created and every caller receives a reference to it. This is the
+    /// `Grt` counterpart to `BTreeMap::entry(key).or_insert_with(init)`. `init` is only run if the key is missing.
     ///
     /// # Errors
     ///
     /// This function will error if:
     ///
-    /// - The `Grt` has not been initialised
-    /// - The `Grt` is empty
-    /// - The key does not exist
-    /// - The mutex type is anything other than a [`KMutex`]
+    /// - `Grt` has not been initialised, see [`Grt::init`]
+    /// - The IRQL is above `APC_LEVEL`
+    /// - The key exists, but the mutex is anything other than a [`KMutex`] protecting a `T`
+    /// - A new mutex could not be allocated
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`. `init` runs whilst the `Grt` is locked, at `APC_LEVEL`,
+    /// so it must not access the `Grt` itself.
+    ///
+    /// # Lifetime
+    ///
+    /// As per [`Self::get_kmutex`].
     ///
     /// # Examples
     ///
     /// ```
-    /// {
-    ///     let my_mutex = Grt::get_kmutex::<u32>("my_test_mutex");
-    ///     if let Err(e) = my_mutex {
-    ///         println!("An error occurred: {:?}", e);
-    ///         return;
-    ///     }
-    ///     let mut lock = my_mutex.unwrap().lock().unwrap();
-    ///     *lock += 1;
-    /// }
+    /// // Called concurrently from several callbacks, only one counter is ever created
+    /// let counter = Grt::get_or_register("process_count", || 0u32).unwrap();
+    /// *counter.lock().unwrap() += 1;
     /// ```
-    pub fn get_kmutex<T>(key: &'static str) -> Result<&'static KMutex<T>, GrtError> {
-        //
-        // Perform checks for erroneous state
-        //
-        let ptr = WDK_MTX_GRT_PTR.load(SeqCst);
-        if ptr.is_null() {
-            return Err(GrtError::GrtIsNull);
+    pub fn get_or_register<T: Any>(
+        key: &'static str,
+        init: impl FnOnce() -> T,
+    ) -> Result<&'static KMutex<T>, GrtError> {
+        // Hold the lock across the check and the insert, so a concurrent registration cannot slip in between
+        let mut map = Self::map()?;
+        if !map.labels.contains_key(key) {
+            let mtx = Box::new(KMutex::new(init()).map_err(GrtError::DriverMutexError)?);
+            map.labels.insert(key, mtx);
         }
 
-        let grt = unsafe { &(*ptr).global_kmutex };
-        if grt.is_empty() {
-            return Err(GrtError::GrtIsEmpty);
-        }
+        Self::get_entry(&map.labels, key)
+    }
 
-        let mutex = grt.get(key);
-        if mutex.is_none() {
-            return Err(GrtError::KeyNotFound);
+    /// Start building a set of mutexes to register in the global reference tracker in one locked section.
+    ///
+    /// See [`GrtBuilder`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// Grt::builder()
+    ///     .with_kmutex("a", 0u32)
+    ///     .with_kmutex("b", false)
+    ///     .commit_checked()
+    ///     .unwrap();
+    /// ```
+    pub fn builder() -> GrtBuilder {
+        GrtBuilder {
+            entries: Vec::new(),
+            error: None,
         }
+    }
 
-        //
-        // The mutex is valid so obtain a reference to it which can be returned
-        //
+    /// Register a new [`KMutex`] under `key`, returning the data protected by the mutex it replaced, if any.
+    ///
+    /// Unlike [`Self::register_kmutex`], which drops a mutex it overwrites along with its data, this hands the previous
+    /// data back to the caller, allowing a global to be reconfigured whilst salvaging its old state. If the key does
+    /// not exist, the mutex is registered and `None` is returned.
+    ///
+    /// # Safety
+    ///
+    /// The previous mutex is freed before this function returns, as its data is moved out of it. References returned
+    /// by [`Self::get_kmutex`] are `'static` and are not counted, so neither the `Grt` lock nor the [`GrtHandle`]
+    /// count can show that the previous mutex is no longer in use; see [`Self::remove_mutex`]. The caller must ensure
+    /// that no reference to (or guard for) the previous mutex is still in use. Later lookups of `key` return the new
+    /// mutex.
+    ///
+    /// [`Self::register_kmutex`] frees an overwritten mutex in the same way and is subject to the same requirement,
+    /// however it is kept safe for compatibility, whereas this function is new and states the requirement in its
+    /// signature.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - `Grt` has not been initialised, see [`Grt::init`]
+    /// - The IRQL is above `APC_LEVEL`
+    /// - The existing mutex is not a `KMutex<T>`, in which case it is left in place
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// Grt::register_kmutex("config", Config::default()).unwrap();
+    ///
+    /// // ...
+    ///
+    /// let old: Option<Box<Config>> = unsafe { Grt::replace("config", new_config).unwrap() };
+    /// ```
+    pub unsafe fn replace<T: Any>(key: &'static str, data: T) -> Result<Option<Box<T>>, GrtError> {
+        // Try initialise a new mutex
+        let mtx = Box::new(KMutex::new(data).map_err(|e| GrtError::DriverMutexError(e))?);
 
-        // SAFETY: Null pointer and inner null pointers have both been checked in the above lines.
-        let m = &**mutex.unwrap();
-        let km = m.downcast_ref::<KMutex<T>>();
+        // Swap the entry under the lock, but drop the previous mutex after the lock is released
+        let previous = {
+            let mut map = Self::map()?;
+            if let Some(existing) = map.labels.get(key) {
+                if !existing.as_any().is::<KMutex<T>>() {
+                    return Err(GrtError::DowncastError);
+                }
+            }
 
-        if km.is_none() {
-            return Err(GrtError::DowncastError);
-        }
+            map.labels.insert(key, mtx)
+        };
+
+        let Some(previous) = previous else {
+            return Ok(None);
+        };
 
-        Ok(km.unwrap())
+        // The type was checked above, whilst the map was locked
+        let previous = previous
+            .into_any()
+            .downcast::<KMutex<T>>()
+            .map_err(|_| GrtError::DowncastError)?;
+
+        // SAFETY: The mutex has been removed from the `Grt`, and the caller guarantees no reference to it remains.
+        Ok(Some(unsafe { previous.to_owned_box() }))
     }
 
     /// Retrieve a mutex by name from the `wdk-mutex` global reference tracker.
@@ -365,10 +874,54 @@ impl Grt {
     /// This function will error if:
     ///
     /// - The `Grt` has not been initialised
+    /// - The IRQL is above `APC_LEVEL`
+    /// - The `Grt` is empty
+    /// - The key does not exist
+    /// - The mutex type is anything other than a [`KMutex`]
+    ///
+    /// # Lifetime
+    ///
+    /// The returned reference remains valid until the mutex is removed with [`Self::remove_mutex`] or the `Grt` is
+    /// destroyed with [`Self::destroy`]; see the safety sections of those functions.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// {
+    ///     let my_mutex = Grt::get_kmutex::<u32>("my_test_mutex");
+    ///     if let Err(e) = my_mutex {
+    ///         println!("An error occurred: {:?}", e);
+    ///         return;
+    ///     }
+    ///     let mut lock = my_mutex.unwrap().lock().unwrap();
+    ///     *lock += 1;
+    /// }
+    /// ```
+    pub fn get_kmutex<T>(key: &'static str) -> Result<&'static KMutex<T>, GrtError> {
+        Self::get_entry(&Self::map()?.labels, key)
+    }
+
+    /// Retrieve a mutex by name from the `wdk-mutex` global reference tracker.
+    ///
+    /// This function takes in a static `&str` to lookup your Mutex by key (where the key is the argument). When calling
+    /// this function, a turbofish specifier is required to tell the compiler what type is contained in the `Mutex`. See
+    /// examples for more information.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - The `Grt` has not been initialised
+    /// - The IRQL is above `APC_LEVEL`
     /// - The `Grt` is empty
     /// - The key does not exist
     /// - The mutex type is anything other than a [`FastMutex`]
     ///
+    /// # Lifetime
+    ///
+    /// The returned reference remains valid until the mutex is removed with [`Self::remove_mutex`] or the `Grt` is
+    /// destroyed with [`Self::destroy`]; see the safety sections of those functions.
+    ///
     /// # Examples
     ///
     /// ```
@@ -383,37 +936,588 @@ impl Grt {
     /// }
     /// ```
     pub fn get_fast_mutex<T>(key: &'static str) -> Result<&'static FastMutex<T>, GrtError> {
-        //
-        // Perform checks for erroneous state
-        //
-        let ptr = WDK_MTX_GRT_PTR.load(SeqCst);
-        if ptr.is_null() {
-            return Err(GrtError::GrtIsNull);
+        Self::get_entry(&Self::map()?.labels, key)
+    }
+
+    /// Register a new [`KMutex`] for the global reference tracker to control, under a typed [`GrtKey`].
+    ///
+    /// This behaves as [`Self::register_kmutex`], however the type of `data` is checked against the key at compile
+    /// time.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - `Grt` has not been initialised, see [`Grt::init`]
+    /// - The IRQL is above `APC_LEVEL`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// grt_key!(MY_COUNTER: u32);
+    ///
+    /// Grt::register(MY_COUNTER, 0u32).unwrap();
+    /// ```
+    pub fn register<T: Any>(key: GrtKey<T>, data: T) -> Result<(), GrtError> {
+        Self::register_kmutex(key.name, data)
+    }
+
+    /// Retrieve a [`KMutex`] from the global reference tracker by typed [`GrtKey`].
+    ///
+    /// This behaves as [`Self::get_kmutex`], however the type is taken from the key so no turbofish is required.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - The `Grt` has not been initialised
+    /// - The IRQL is above `APC_LEVEL`
+    /// - The `Grt` is empty
+    /// - The key does not exist
+    /// - The key was registered through the untyped API with a different type or mutex kind
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// grt_key!(MY_COUNTER: u32);
+    ///
+    /// let mut lock = Grt::get(MY_COUNTER).unwrap().lock().unwrap();
+    /// *lock += 1;
+    /// ```
+    pub fn get<T: Any>(key: GrtKey<T>) -> Result<&'static KMutex<T>, GrtError> {
+        Self::get_kmutex::<T>(key.name)
+    }
+
+    /// Retrieve a [`KMutex`] from the global reference tracker as a counted [`GrtHandle`].
+    ///
+    /// This behaves as [`Self::get_kmutex`], however [`Self::destroy_when_idle`] will not tear down the `Grt` while the
+    /// returned handle, or any clone of it, is alive.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - The `Grt` has not been initialised
+    /// - The IRQL is above `APC_LEVEL`
+    /// - The `Grt` is empty
+    /// - The key does not exist
+    /// - The mutex type is anything other than a [`KMutex`]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let my_mutex = Grt::get_kmutex_handle::<u32>("my_test_mutex").unwrap();
+    /// *my_mutex.lock().unwrap() += 1;
+    /// ```
+    pub fn get_kmutex_handle<T: Any>(key: &'static str) -> Result<GrtHandle<KMutex<T>>, GrtError> {
+        let map = Self::map()?;
+        let mutex = Self::get_entry(&map.labels, key)?;
+
+        // Count the handle whilst the `Grt` is locked, so `destroy_when_idle` cannot observe a zero count in between
+        WDK_MTX_GRT_HANDLES.fetch_add(1, SeqCst);
+
+        Ok(GrtHandle { mutex })
+    }
+
+    /// Retrieve a [`FastMutex`] from the global reference tracker as a counted [`GrtHandle`].
+    ///
+    /// This behaves as [`Self::get_fast_mutex`], however [`Self::destroy_when_idle`] will not tear down the `Grt` while
+    /// the returned handle, or any clone of it, is alive.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - The `Grt` has not been initialised
+    /// - The IRQL is above `APC_LEVEL`
+    /// - The `Grt` is empty
+    /// - The key does not exist
+    /// - The mutex type is anything other than a [`FastMutex`]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let my_mutex = Grt::get_fast_mutex_handle::<u32>("my_test_mutex").unwrap();
+    /// *my_mutex.lock().unwrap() += 1;
+    /// ```
+    pub fn get_fast_mutex_handle<T: Any>(
+        key: &'static str,
+    ) -> Result<GrtHandle<FastMutex<T>>, GrtError> {
+        let map = Self::map()?;
+        let mutex = Self::get_entry(&map.labels, key)?;
+
+        // Count the handle whilst the `Grt` is locked, so `destroy_when_idle` cannot observe a zero count in between
+        WDK_MTX_GRT_HANDLES.fetch_add(1, SeqCst);
+
+        Ok(GrtHandle { mutex })
+    }
+
+    /// Creates a system thread which runs `routine` with the [`KMutex`] registered under `key`.
+    ///
+    /// This replaces the common pattern of passing a mutex to `PsCreateSystemThread` as a `*mut c_void` start context
+    /// and casting it back in the thread routine; `routine` receives a typed `&'static KMutex<T>` instead. The mutex
+    /// is looked up before the thread is created, so a missing key or mismatched type is reported to the caller
+    /// rather than inside the new thread.
+    ///
+    /// The thread holds a [`GrtHandle`] to the mutex until `routine` returns, so [`Self::destroy_when_idle`] will not
+    /// tear down the `Grt` whilst the thread is running.
+    ///
+    /// # Returns
+    ///
+    /// A referenced pointer to the new thread object, which the caller owns. To wait for the thread on unload, wait on
+    /// it with `KeWaitForSingleObject`, then release the reference with `ObDereferenceObject`.
+    ///
+    /// The thread is created with a kernel handle (`OBJ_KERNEL_HANDLE`), so it is not placed in the handle table of
+    /// whichever process the caller happens to be running in, and the handle is closed before this function returns.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - The IRQL is not `PASSIVE_LEVEL`
+    /// - Any of the errors from [`Self::get_kmutex_handle`]
+    /// - `PsCreateSystemThread` fails, returning [`GrtError::ThreadCreateFailed`] with its status
+    /// - `ObReferenceObjectByHandle` fails, returning [`GrtError::ThreadCreateFailed`] with its status. In this case
+    ///   the thread has already started and runs `routine` to completion, but cannot be waited on
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at `PASSIVE_LEVEL`. `routine` runs at `PASSIVE_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// fn worker(queue: &'static KMutex<WorkQueue>) {
+    ///     while let Some(item) = queue.lock().unwrap().pop() {
+    ///         item.process();
+    ///     }
+    /// }
+    ///
+    /// let thread = Grt::spawn_with::<WorkQueue>("work_queue", worker).unwrap();
+    ///
+    /// // On unload, wait for the thread to finish before releasing it
+    /// unsafe {
+    ///     KeWaitForSingleObject(thread as *mut c_void, Executive, KernelMode as i8, FALSE as u8, null_mut());
+    ///     ObfDereferenceObject(thread as *mut c_void);
+    /// }
+    /// ```
+    pub fn spawn_with<T: Any + Send>(
+        key: &'static str,
+        routine: fn(&'static KMutex<T>),
+    ) -> Result<PETHREAD, GrtError> {
+        // PsCreateSystemThread must be called at PASSIVE_LEVEL
+        if unsafe { KeGetCurrentIrql() } != PASSIVE_LEVEL as u8 {
+            return Err(GrtError::DriverMutexError(DriverMutexError::IrqlTooHigh));
         }
 
-        let grt = unsafe { &(*ptr).global_kmutex };
-        if grt.is_empty() {
-            return Err(GrtError::GrtIsEmpty);
+        let context = Box::into_raw(Box::new(SpawnContext {
+            handle: Self::get_kmutex_handle::<T>(key)?,
+            routine,
+        }));
+
+        // Equivalent of InitializeObjectAttributes(&attributes, NULL, OBJ_KERNEL_HANDLE, NULL, NULL)
+        let mut attributes = OBJECT_ATTRIBUTES {
+            Length: size_of::<OBJECT_ATTRIBUTES>() as u32,
+            RootDirectory: null_mut(),
+            ObjectName: null_mut(),
+            Attributes: OBJ_KERNEL_HANDLE,
+            SecurityDescriptor: null_mut(),
+            SecurityQualityOfService: null_mut(),
+        };
+
+        let mut thread: HANDLE = null_mut();
+        let status = unsafe {
+            PsCreateSystemThread(
+                &mut thread,
+                THREAD_ALL_ACCESS,
+                &mut attributes,
+                null_mut(),
+                null_mut(),
+                Some(spawn_trampoline::<T>),
+                context as *mut c_void,
+            )
+        };
+        if status != STATUS_SUCCESS {
+            // SAFETY: The thread was not created, so ownership of the context was not passed to it.
+            drop(unsafe { Box::from_raw(context) });
+            return Err(GrtError::ThreadCreateFailed(status));
         }
 
-        let mutex = grt.get(key);
-        if mutex.is_none() {
-            return Err(GrtError::KeyNotFound);
+        let mut object: *mut c_void = null_mut();
+        let status = unsafe {
+            ObReferenceObjectByHandle(
+                thread,
+                THREAD_ALL_ACCESS,
+                *PsThreadType,
+                KernelMode as i8,
+                &mut object,
+                null_mut(),
+            )
+        };
+
+        // The handle is not needed past this point, whether or not the thread object could be referenced
+        unsafe { ZwClose(thread) };
+
+        if status != STATUS_SUCCESS {
+            return Err(GrtError::ThreadCreateFailed(status));
         }
 
-        //
-        // The mutex is valid so obtain a reference to it which can be returned
-        //
+        Ok(object as PETHREAD)
+    }
 
-        // SAFETY: Null pointer and inner null pointers have both been checked in the above lines.
-        let m = &**mutex.unwrap();
-        let km = m.downcast_ref::<FastMutex<T>>();
+    /// Register a new [`KMutex`] for the global reference tracker to control, under a key only known at runtime.
+    ///
+    /// This behaves as [`Self::register_kmutex`], however the key is an owned [`String`] rather than a `&'static str`,
+    /// allowing keys to be built from runtime identifiers. If the key already exists, the existing mutex is
+    /// overwritten. The mutex is retrieved with [`Self::get_kmutex_owned`].
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - `Grt` has not been initialised, see [`Grt::init`]
+    /// - The IRQL is above `APC_LEVEL`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let key = format!("device_{}", instance_id);
+    /// Grt::register_kmutex_owned(key, 0u32).unwrap();
+    /// ```
+    pub fn register_kmutex_owned<T: Any>(key: String, data: T) -> Result<(), GrtError> {
+        // Try initialise a new mutex
+        let mtx = Box::new(KMutex::new(data).map_err(|e| GrtError::DriverMutexError(e))?);
+
+        Self::map()?.owned.insert(key, mtx);
+
+        Ok(())
+    }
+
+    /// Register a new [`FastMutex`] for the global reference tracker to control, under a key only known at runtime.
+    ///
+    /// This behaves as [`Self::register_fast_mutex`], however the key is an owned [`String`] rather than a
+    /// `&'static str`, allowing keys to be built from runtime identifiers. If the key already exists, the existing
+    /// mutex is overwritten. The mutex is retrieved with [`Self::get_fast_mutex_owned`].
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - `Grt` has not been initialised, see [`Grt::init`]
+    /// - The IRQL is above `APC_LEVEL`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let key = format!("device_{}", instance_id);
+    /// Grt::register_fast_mutex_owned(key, 0u32).unwrap();
+    /// ```
+    pub fn register_fast_mutex_owned<T: Any>(key: String, data: T) -> Result<(), GrtError> {
+        // Try initialise a new mutex
+        let mtx = Box::new(FastMutex::new(data).map_err(|e| GrtError::DriverMutexError(e))?);
 
-        if km.is_none() {
-            return Err(GrtError::DowncastError);
+        Self::map()?.owned.insert(key, mtx);
+
+        Ok(())
+    }
+
+    /// Retrieve a [`KMutex`] registered with [`Self::register_kmutex_owned`] by its runtime key.
+    ///
+    /// As with [`Self::get_kmutex`], a turbofish specifier is required to tell the compiler what type is contained in
+    /// the `Mutex`.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - The `Grt` has not been initialised
+    /// - The IRQL is above `APC_LEVEL`
+    /// - No mutexes have been registered under an owned key
+    /// - The key does not exist
+    /// - The mutex type is anything other than a [`KMutex`]
+    ///
+    /// # Lifetime
+    ///
+    /// The returned reference remains valid until the mutex is removed with [`Self::remove_mutex_owned`] or the `Grt`
+    /// is destroyed with [`Self::destroy`]; see the safety sections of those functions.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let key = format!("device_{}", instance_id);
+    /// let mut lock = Grt::get_kmutex_owned::<u32>(&key).unwrap().lock().unwrap();
+    /// *lock += 1;
+    /// ```
+    pub fn get_kmutex_owned<T: Any>(key: &str) -> Result<&'static KMutex<T>, GrtError> {
+        Self::get_entry(&Self::map()?.owned, key)
+    }
+
+    /// Retrieve a [`FastMutex`] registered with [`Self::register_fast_mutex_owned`] by its runtime key.
+    ///
+    /// As with [`Self::get_fast_mutex`], a turbofish specifier is required to tell the compiler what type is contained
+    /// in the `Mutex`.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - The `Grt` has not been initialised
+    /// - The IRQL is above `APC_LEVEL`
+    /// - No mutexes have been registered under an owned key
+    /// - The key does not exist
+    /// - The mutex type is anything other than a [`FastMutex`]
+    ///
+    /// # Lifetime
+    ///
+    /// The returned reference remains valid until the mutex is removed with [`Self::remove_mutex_owned`] or the `Grt`
+    /// is destroyed with [`Self::destroy`]; see the safety sections of those functions.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let key = format!("device_{}", instance_id);
+    /// let mut lock = Grt::get_fast_mutex_owned::<u32>(&key).unwrap().lock().unwrap();
+    /// *lock += 1;
+    /// ```
+    pub fn get_fast_mutex_owned<T: Any>(key: &str) -> Result<&'static FastMutex<T>, GrtError> {
+        Self::get_entry(&Self::map()?.owned, key)
+    }
+
+    /// Collects the acquisition and contention counters of every mutex in the global reference tracker into a single
+    /// report, keyed by the label each mutex was registered under.
+    ///
+    /// Only available with the `stats` feature. The report is ordered by key, and each entry is a snapshot taken as
+    /// the report is built, so the counters of different mutexes are not captured at exactly the same instant.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - The `Grt` has not been initialised
+    /// - The IRQL is above `APC_LEVEL`
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// for (key, stats) in Grt::collect_stats().unwrap() {
+    ///     println!("{}: {} acquisitions, {} contended", key, stats.acquisitions, stats.contentions);
+    /// }
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn collect_stats() -> Result<Vec<(&'static str, MutexStats)>, GrtError> {
+        let grt = Self::map()?;
+
+        Ok(grt
+            .labels
+            .iter()
+            .map(|(key, mtx)| (*key, mtx.stats()))
+            .collect())
+    }
+
+    /// Returns a snapshot of the acquisition and contention counters for the `Grt`'s own internal lock, which every
+    /// registration, lookup and removal acquires.
+    ///
+    /// Only available with the `stats` feature. A high contention count relative to acquisitions suggests that the
+    /// `Grt` itself is a bottleneck, for example when many threads look up mutexes on a hot path, in which case
+    /// caching the `&'static` references or [`GrtHandle`]s returned by the lookup functions avoids the internal lock.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if the `Grt` has not been initialised.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let stats = Grt::internal_lock_stats().unwrap();
+    /// println!("Grt lock: {} acquisitions, {} contended", stats.acquisitions, stats.contentions);
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn internal_lock_stats() -> Result<MutexStats, GrtError> {
+        let (_entrant, grt) = GrtEntrant::enter()?;
+
+        Ok(grt.global_kmutex.stats())
+    }
+
+    /// Returns `true` if a mutex is registered in the global reference tracker under `key`.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - The `Grt` has not been initialised
+    /// - The IRQL is above `APC_LEVEL`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if !Grt::contains_key("my_test_mutex").unwrap() {
+    ///     Grt::register_kmutex("my_test_mutex", 0u32).unwrap();
+    /// }
+    /// ```
+    pub fn contains_key(key: &'static str) -> Result<bool, GrtError> {
+        Ok(Self::map()?.labels.contains_key(key))
+    }
+
+    /// Returns a snapshot of the `&'static str` keys of every mutex registered in the global reference tracker, in key
+    /// order.
+    ///
+    /// Only the keys are returned, not the mutexes. Keys registered through the `_owned` API are not included, see
+    /// [`Self::for_each_key`] to visit those.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - The `Grt` has not been initialised
+    /// - The IRQL is above `APC_LEVEL`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// for key in Grt::keys().unwrap() {
+    ///     println!("Registered: {}", key);
+    /// }
+    /// ```
+    pub fn keys() -> Result<Vec<&'static str>, GrtError> {
+        Ok(Self::map()?.labels.keys().copied().collect())
+    }
+
+    /// Calls `f` with the key of every mutex registered in the global reference tracker, including keys registered
+    /// through the `_owned` API, without allocating.
+    ///
+    /// `&'static str` keys are visited first in key order, followed by owned keys in key order.
+    ///
+    /// The `Grt` is locked for the duration of the call, so `f` must not call back into the `Grt`, as doing so will
+    /// deadlock.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - The `Grt` has not been initialised
+    /// - The IRQL is above `APC_LEVEL`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// Grt::for_each_key(|key| println!("Registered: {}", key)).unwrap();
+    /// ```
+    pub fn for_each_key(mut f: impl FnMut(&str)) -> Result<(), GrtError> {
+        let map = Self::map()?;
+
+        map.labels.keys().for_each(|key| f(key));
+        map.owned.keys().for_each(|key| f(key));
+
+        Ok(())
+    }
+
+    /// Returns the number of mutexes registered in the global reference tracker, under both `&'static str` and owned
+    /// keys.
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - The `Grt` has not been initialised
+    /// - The IRQL is above `APC_LEVEL`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// println!("{} mutexes are registered", Grt::len().unwrap());
+    /// ```
+    pub fn len() -> Result<usize, GrtError> {
+        let map = Self::map()?;
+
+        Ok(map.labels.len() + map.owned.len())
+    }
+
+    /// Remove a single mutex from the global reference tracker, dropping it and freeing its pool allocation.
+    ///
+    /// This allows long running drivers to recycle transient state without destroying the whole `Grt`. The mutex may
+    /// be either a [`KMutex`] or a [`FastMutex`].
+    ///
+    /// # Safety
+    ///
+    /// The mutex is freed before this function returns. References returned by [`Self::get_kmutex`] /
+    /// [`Self::get_fast_mutex`] (and their `_owned` counterparts) are `'static` and outlive the `Grt` lock, so holding
+    /// the lock whilst removing the entry does not stop another thread from still using the mutex. The [`GrtHandle`]
+    /// count cannot be relied upon either, as it is shared by every entry and does not include those references.
+    ///
+    /// The caller must therefore ensure that no reference to (or guard for) the mutex obtained before the removal is
+    /// still in use. Looking the key up again afterwards is sound and returns [`GrtError::KeyNotFound`].
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - The `Grt` has not been initialised
+    /// - The IRQL is above `APC_LEVEL`
+    /// - The key does not exist
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// Grt::register_kmutex("transient_state", 0u32).unwrap();
+    ///
+    /// // ...
+    ///
+    /// unsafe { Grt::remove_mutex("transient_state").unwrap() };
+    /// ```
+    pub unsafe fn remove_mutex(key: &'static str) -> Result<(), GrtError> {
+        // Take the entry out under the lock, but drop it after the lock is released
+        let removed = Self::map()?.labels.remove(key);
+
+        // Dropping the removed box frees the mutex via RAII.
+        match removed {
+            Some(_) => Ok(()),
+            None => Err(GrtError::KeyNotFound),
         }
+    }
+
+    /// Remove a single mutex registered under an owned key from the global reference tracker, dropping it and freeing
+    /// its pool allocation.
+    ///
+    /// This is the counterpart of [`Self::remove_mutex`] for mutexes registered through [`Self::register_kmutex_owned`]
+    /// or [`Self::register_fast_mutex_owned`].
+    ///
+    /// # Safety
+    ///
+    /// The mutex is freed before this function returns, so the caller must ensure that no reference returned by
+    /// [`Self::get_kmutex_owned`] / [`Self::get_fast_mutex_owned`] (or guard for) the mutex is still in use. The `Grt`
+    /// lock and the [`GrtHandle`] count cannot establish this, see [`Self::remove_mutex`].
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - The `Grt` has not been initialised
+    /// - The IRQL is above `APC_LEVEL`
+    /// - The key does not exist
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let key = format!("device_{}", instance_id);
+    /// unsafe { Grt::remove_mutex_owned(&key).unwrap() };
+    /// ```
+    pub unsafe fn remove_mutex_owned(key: &str) -> Result<(), GrtError> {
+        // Take the entry out under the lock, but drop it after the lock is released
+        let removed = Self::map()?.owned.remove(key);
 
-        Ok(km.unwrap())
+        // Dropping the removed box frees the mutex via RAII.
+        match removed {
+            Some(_) => Ok(()),
+            None => Err(GrtError::KeyNotFound),
+        }
     }
 
     /// Destroy the global reference tracker for `wdk-mutex`.
@@ -440,19 +1544,84 @@ impl Grt {
     /// }
     /// ```
     pub unsafe fn destroy() -> Result<(), GrtError> {
-        // Check that the static pointer is not already null
-        let grt_ptr = WDK_MTX_GRT_PTR.load(SeqCst);
+        // Set the atomic global to null, checking that it was not already null
+        let grt_ptr = WDK_MTX_GRT_PTR.swap(null_mut(), SeqCst);
         if grt_ptr.is_null() {
             return Err(GrtError::GrtIsNull);
         }
 
-        // Set the atomic global to null
-        WDK_MTX_GRT_PTR.store(null_mut(), SeqCst);
-
         // Convert the pointer back to a box which wraps the inner `Grt`, allowing Box to drop all it's content
         // which will free all inner memory, drop will properly be called on all Mutexes.
         let _ = unsafe { Box::from_raw(grt_ptr) };
 
         Ok(())
     }
+
+    /// Destroy the global reference tracker for `wdk-mutex`, provided no [`GrtHandle`] is alive.
+    ///
+    /// This is an alternative to [`Self::destroy`] for drivers which access their mutexes through
+    /// [`Self::get_kmutex_handle`] / [`Self::get_fast_mutex_handle`]. If any handle is still alive, the `Grt` is left
+    /// untouched and [`GrtError::StillInUse`] is returned, allowing driver unload to wait and try again once
+    /// outstanding callbacks and threads have finished.
+    ///
+    /// Other threads may call into the `Grt` concurrently. A call which is already using the `Grt` when this function
+    /// runs causes [`GrtError::StillInUse`] to be returned, and a call made once the `Grt` has been destroyed returns
+    /// [`GrtError::GrtIsNull`].
+    ///
+    /// # Safety
+    ///
+    /// Only handles and calls currently inside the `Grt` are counted. References returned by the uncounted getters,
+    /// such as [`Self::get_kmutex`], are plain `'static` references which cannot be tracked once the getter has
+    /// returned, so the caller must ensure none of those are still in use, as with [`Self::destroy`].
+    ///
+    /// # Errors
+    ///
+    /// This function will error if:
+    ///
+    /// - The `Grt` has not been initialised
+    /// - The IRQL is above `APC_LEVEL`
+    /// - A [`GrtHandle`] is still alive, or another thread is currently using the `Grt`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// /// Driver exit routine
+    /// extern "C" fn driver_exit(driver: *mut DRIVER_OBJECT) {
+    ///     while let Err(GrtError::StillInUse) = unsafe { Grt::destroy_when_idle() } {
+    ///         // Give outstanding work items a chance to finish
+    ///         let mut interval = LARGE_INTEGER { QuadPart: -10_000 };
+    ///         unsafe { KeDelayExecutionThread(KernelMode as i8, FALSE as u8, &mut interval) };
+    ///     }
+    /// }
+    /// ```
+    pub unsafe fn destroy_when_idle() -> Result<(), GrtError> {
+        if unsafe { KeGetCurrentIrql() } > APC_LEVEL as u8 {
+            return Err(GrtError::DriverMutexError(DriverMutexError::IrqlTooHigh));
+        }
+
+        if WDK_MTX_GRT_HANDLES.load(SeqCst) != 0 {
+            return Err(GrtError::StillInUse);
+        }
+
+        // Unpublish the `Grt` first, so that any call counted as an entrant from here on sees a nullptr
+        let grt_ptr = WDK_MTX_GRT_PTR.swap(null_mut(), SeqCst);
+        if grt_ptr.is_null() {
+            return Err(GrtError::GrtIsNull);
+        }
+
+        // A call which loaded the pointer before it was unpublished is still counted, and may also have created a
+        // handle before leaving, so both counts are checked once the pointer can no longer be loaded
+        if WDK_MTX_GRT_ENTRANTS.load(SeqCst) != 0 || WDK_MTX_GRT_HANDLES.load(SeqCst) != 0 {
+            // Publish the `Grt` again. If `init` has published a new one in the meantime, the old `Grt` is leaked
+            // rather than freed, as it may still be in use.
+            let _ = WDK_MTX_GRT_PTR.compare_exchange(null_mut(), grt_ptr, SeqCst, SeqCst);
+            return Err(GrtError::StillInUse);
+        }
+
+        // SAFETY: The pointer was published by `init` and has been unpublished above, and no entrant or handle can
+        // still refer to it, so this is the only owner.
+        let _ = unsafe { Box::from_raw(grt_ptr) };
+
+        Ok(())
+    }
 }
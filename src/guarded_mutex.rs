@@ -0,0 +1,271 @@
+//! A Rust idiomatic Windows Kernel Driver KGUARDED_MUTEX type which protects the inner type T
+
+use core::{
+    ffi::c_void,
+    fmt::Display,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr::{self, drop_in_place},
+};
+use wdk_sys::{
+    ntddk::{
+        ExAllocatePool2, ExFreePool, KeAcquireGuardedMutex, KeGetCurrentIrql,
+        KeInitializeGuardedMutex, KeReleaseGuardedMutex, KeTryToAcquireGuardedMutex,
+    },
+    APC_LEVEL, DISPATCH_LEVEL, KGUARDED_MUTEX, POOL_FLAG_NON_PAGED,
+};
+
+use crate::errors::DriverMutexError;
+
+/// A thread safe mutex implemented through acquiring a `KGUARDED_MUTEX` in the Windows kernel.
+///
+/// The type `GuardedMutex<T>` provides mutually exclusive access to the inner type T allocated through this crate in
+/// the non-paged pool. Acquiring the mutex enters a guarded region, which disables all kernel APCs (including special
+/// kernel APCs) for the thread, whereas a [`crate::fast_mutex::FastMutex`] raises to `APC_LEVEL`. The IRQL is not
+/// changed whilst the mutex is held.
+///
+/// # Deallocation
+///
+/// GuardedMutex handles the deallocation of resources at the point the GuardedMutex is dropped.
+///
+/// # Examples
+///
+/// ```
+/// {
+///     let mtx = GuardedMutex::new(0u32).unwrap();
+///     let mut lock = mtx.lock().unwrap();
+///     *lock += 1;
+/// } // Mutex will become unlocked as it is managed via RAII
+/// ```
+pub struct GuardedMutex<T> {
+    inner: *mut GuardedMutexInner<T>,
+}
+
+/// The underlying data which is non-page pool allocated which is pointed to by the `GuardedMutex`.
+struct GuardedMutexInner<T> {
+    mutex: KGUARDED_MUTEX,
+    /// The pool tag this structure was allocated with
+    tag: u32,
+    /// The data for which the mutex is protecting
+    data: T,
+}
+
+// SAFETY: Only the thread holding the guarded mutex can access the `T`, however that may be any thread, and the `T`
+// may be dropped on a different thread to the one which created it, so the `T` must be `Send`.
+unsafe impl<T: Send> Sync for GuardedMutex<T> {}
+unsafe impl<T: Send> Send for GuardedMutex<T> {}
+
+impl<T> GuardedMutex<T> {
+    /// Creates a new `KGUARDED_MUTEX` Windows Kernel Driver Mutex.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`, or the non-paged pool allocation fails.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at IRQL <= DISPATCH_LEVEL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let my_mutex = GuardedMutex::new(0u32).unwrap();
+    /// ```
+    pub fn new(data: T) -> Result<Self, DriverMutexError> {
+        // This can only be called at a level <= DISPATCH_LEVEL; check current IRQL
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-keinitializeguardedmutex
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        //
+        // Non-Paged heap alloc for all struct data required for GuardedMutexInner
+        //
+        let tag = u32::from_be_bytes(*b"kmtx");
+        let total_sz_required = size_of::<GuardedMutexInner<T>>();
+        let inner_heap_ptr: *mut c_void =
+            unsafe { ExAllocatePool2(POOL_FLAG_NON_PAGED, total_sz_required as u64, tag) };
+        if inner_heap_ptr.is_null() {
+            return Err(DriverMutexError::AllocFailed {
+                requested: total_sz_required,
+            });
+        }
+
+        // Cast the memory allocation to a pointer to the inner
+        let guarded_mtx_inner_ptr = inner_heap_ptr as *mut GuardedMutexInner<T>;
+
+        // SAFETY: This raw write is safe as the pointer validity is checked above.
+        unsafe {
+            ptr::write(
+                guarded_mtx_inner_ptr,
+                GuardedMutexInner {
+                    mutex: KGUARDED_MUTEX::default(),
+                    tag,
+                    data,
+                },
+            );
+
+            // Initialise the guarded mutex object via the kernel
+            KeInitializeGuardedMutex(&mut (*guarded_mtx_inner_ptr).mutex);
+        }
+
+        Ok(Self {
+            inner: guarded_mtx_inner_ptr,
+        })
+    }
+
+    /// Acquires the mutex, entering a guarded region.
+    ///
+    /// Once the thread has acquired the mutex, it will return a `GuardedMutexGuard` which is a RAII scoped guard
+    /// allowing exclusive access to the inner T. All kernel APCs are disabled for the thread until the guard is
+    /// dropped.
+    ///
+    /// # Errors
+    ///
+    /// If the IRQL is too high, this function will return an error and will not acquire a lock.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`, if the IRQL is higher than this, the function will return
+    /// an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mtx = GuardedMutex::new(0u32).unwrap();
+    /// let lock = mtx.lock().unwrap();
+    /// ```
+    pub fn lock(&self) -> Result<GuardedMutexGuard<'_, T>, DriverMutexError> {
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-keacquireguardedmutex
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // SAFETY: RAII manages pointer validity and IRQL checked.
+        unsafe { KeAcquireGuardedMutex(&mut (*self.inner).mutex) };
+
+        Ok(GuardedMutexGuard {
+            guarded_mutex: self,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Attempts to acquire the mutex without waiting, entering a guarded region on success.
+    ///
+    /// If the mutex is free, it is acquired and a `GuardedMutexGuard` is returned as per [`Self::lock`]. If the mutex
+    /// is held, [`DriverMutexError::WouldBlock`] is returned immediately rather than waiting.
+    ///
+    /// # Errors
+    ///
+    /// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `APC_LEVEL`.
+    /// - [`DriverMutexError::WouldBlock`] if the mutex is held.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`, if the IRQL is higher than this, the function will return
+    /// an error.
+    pub fn try_lock(&self) -> Result<GuardedMutexGuard<'_, T>, DriverMutexError> {
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-ketrytoacquireguardedmutex
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // SAFETY: RAII manages pointer validity and IRQL checked.
+        if unsafe { KeTryToAcquireGuardedMutex(&mut (*self.inner).mutex) } == 0 {
+            return Err(DriverMutexError::WouldBlock);
+        }
+
+        Ok(GuardedMutexGuard {
+            guarded_mutex: self,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Returns a mutable reference to the protected data, without acquiring the mutex.
+    ///
+    /// As this takes `&mut self`, the borrow checker guarantees no other reference to the mutex (and so no guard)
+    /// exists, so the mutex does not need to be acquired.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn get_mut(&mut self) -> &mut T {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation, and `&mut self`
+        // guarantees exclusive access.
+        unsafe { &mut (*self.inner).data }
+    }
+
+    /// Returns the pool tag the mutex was allocated with.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn tag(&self) -> u32 {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        unsafe { (*self.inner).tag }
+    }
+}
+
+impl<T> Drop for GuardedMutex<T> {
+    fn drop(&mut self) {
+        unsafe {
+            // Drop the underlying data and run destructors for the data
+            drop_in_place(&mut (*self.inner).data);
+
+            // Free the memory we allocated
+            ExFreePool(self.inner as *mut _);
+        }
+    }
+}
+
+/// A RAII scoped guard for the inner data protected by a [`GuardedMutex`].
+///
+/// When this structure is dropped (falls out of scope), the mutex is released and the guarded region entered by
+/// [`GuardedMutex::lock`] is left.
+///
+/// # IRQL
+///
+/// This guard must be dropped at the same IRQL as the mutex was acquired at.
+pub struct GuardedMutexGuard<'a, T> {
+    guarded_mutex: &'a GuardedMutex<T>,
+    /// The mutex must be released by the thread which acquired it, as releasing it leaves that thread's guarded region
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Display for GuardedMutexGuard<'_, T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        write!(f, "{}", unsafe { &(*self.guarded_mutex.inner).data })
+    }
+}
+
+impl<T> Deref for GuardedMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        unsafe { &(*self.guarded_mutex.inner).data }
+    }
+}
+
+impl<T> DerefMut for GuardedMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        // Mutable access is safe due to Self only being given out whilst a mutex is held from the kernel.
+        unsafe { &mut (*self.guarded_mutex.inner).data }
+    }
+}
+
+impl<T> Drop for GuardedMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: The mutex is held by this guard.
+        unsafe { KeReleaseGuardedMutex(&mut (*self.guarded_mutex.inner).mutex) };
+    }
+}
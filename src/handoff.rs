@@ -0,0 +1,166 @@
+//! A single producer, single consumer handoff of a value between two threads.
+
+use core::{ffi::c_void, ptr::null_mut};
+use wdk_sys::{
+    ntddk::{
+        ExAllocatePool2, ExFreePool, KeGetCurrentIrql, KeInitializeEvent, KeSetEvent,
+        KeWaitForSingleObject,
+    },
+    APC_LEVEL, DISPATCH_LEVEL, FALSE, KEVENT, POOL_FLAG_NON_PAGED,
+    _EVENT_TYPE::SynchronizationEvent,
+    _KWAIT_REASON::Executive,
+    _MODE::KernelMode,
+};
+
+use crate::{errors::DriverMutexError, kmutex::KMutex};
+
+/// Passes a value from a producing thread to a consuming thread, baton style.
+///
+/// `Handoff` is composed of a [`KMutex<Option<T>>`] holding the value in flight, and a synchronization `KEVENT` which
+/// the producer signals once the value has been stored. The consumer blocks on the event in [`Self::consume`] until a
+/// value is available, then takes it out of the mutex. The producer builds its state however it wishes and hands it
+/// over with [`Self::produce`].
+///
+/// # Single producer, single consumer
+///
+/// `Handoff` is designed for exactly one producing and one consuming thread. Only one value is held at a time; if the
+/// producer hands over a second value before the first has been consumed, the first is returned back to the producer.
+/// With more than one consumer, which consumer receives a given value is unspecified.
+///
+/// # IRQL
+///
+/// [`Self::produce`] must be called at IRQL `<= APC_LEVEL` as it acquires the `KMutex`. [`Self::consume`] waits on the
+/// event and must also be called at IRQL `<= APC_LEVEL`.
+///
+/// # Examples
+///
+/// ```
+/// // Registered in the Grt, or otherwise shared between both threads
+/// let handoff: Handoff<Config> = Handoff::new().unwrap();
+///
+/// // Thread A
+/// let config = build_config();
+/// handoff.produce(config).unwrap();
+///
+/// // Thread B, blocks until thread A has produced
+/// let config = handoff.consume().unwrap();
+/// ```
+pub struct Handoff<T> {
+    slot: KMutex<Option<T>>,
+    /// Non-paged synchronization event, signalled when a value is placed in `slot`
+    event: *mut KEVENT,
+}
+
+unsafe impl<T: Send> Sync for Handoff<T> {}
+unsafe impl<T: Send> Send for Handoff<T> {}
+
+impl<T> Handoff<T> {
+    /// Creates a new, empty `Handoff`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`, or the non-paged pool allocation fails.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`.
+    pub fn new() -> Result<Self, DriverMutexError> {
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        let slot = KMutex::new(None)?;
+
+        // The event must be non-paged as it is a dispatcher object
+        let event: *mut c_void = unsafe {
+            ExAllocatePool2(
+                POOL_FLAG_NON_PAGED,
+                size_of::<KEVENT>() as u64,
+                u32::from_be_bytes(*b"kmtx"),
+            )
+        };
+        if event.is_null() {
+            return Err(DriverMutexError::AllocFailed {
+                requested: size_of::<KEVENT>(),
+            });
+        }
+        let event = event as *mut KEVENT;
+
+        // SAFETY: The allocation is checked above and sized for a KEVENT.
+        unsafe { KeInitializeEvent(event, SynchronizationEvent, FALSE as u8) };
+
+        Ok(Self { slot, event })
+    }
+
+    /// Stores `value` for the consumer and wakes it.
+    ///
+    /// If a previously produced value has not yet been consumed, it is replaced by `value` and returned in `Ok(Some(_))`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mutex could not be acquired, for example if the IRQL is too high. In that case `value`
+    /// is dropped and the consumer is not woken.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= APC_LEVEL`.
+    pub fn produce(&self, value: T) -> Result<Option<T>, DriverMutexError> {
+        let previous = {
+            let mut slot = self.slot.lock()?;
+            slot.replace(value)
+        };
+
+        // SAFETY: The event was initialised in `new` and lives as long as self.
+        unsafe { KeSetEvent(self.event, 0, FALSE as u8) };
+
+        Ok(previous)
+    }
+
+    /// Blocks until the producer has handed over a value, then takes it.
+    ///
+    /// If a value was produced before this function was called, it is returned without blocking.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is too high to wait.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= APC_LEVEL`.
+    pub fn consume(&self) -> Result<T, DriverMutexError> {
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-kewaitforsingleobject
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        loop {
+            // Discard the return value; the status code does not represent an error or contain information
+            // relevant to the context of no timeout.
+            let _ = unsafe {
+                // SAFETY: IRQL checked above, the event lives as long as self.
+                KeWaitForSingleObject(
+                    self.event as *mut _,
+                    Executive,
+                    KernelMode as i8,
+                    FALSE as u8,
+                    null_mut(),
+                )
+            };
+
+            // The event may have been left signalled by a value which has already been taken (for example if the
+            // producer replaced an unconsumed value), in which case go back to waiting.
+            if let Some(value) = self.slot.lock()?.take() {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+impl<T> Drop for Handoff<T> {
+    fn drop(&mut self) {
+        // SAFETY: The event was allocated in `new`, and no thread can be waiting on it as we have exclusive access.
+        unsafe { ExFreePool(self.event as *mut _) };
+    }
+}
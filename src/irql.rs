@@ -0,0 +1,168 @@
+//! Helpers for raising the IRQL around a section of code and restoring it afterwards.
+
+use core::marker::PhantomData;
+
+#[cfg(feature = "debug")]
+use crate::log::{emit, flush_log};
+#[cfg(feature = "debug")]
+use wdk_sys::ntddk::KeGetCurrentIrql;
+use wdk_sys::{
+    ntddk::{KeLowerIrql, KfRaiseIrql},
+    KIRQL,
+};
+
+/// Raises the IRQL to `level`, runs `f`, then restores the IRQL the caller was running at.
+///
+/// This provides a scoped IRQL bracket which cannot be left raised by accident. A common use is raising to
+/// `DISPATCH_LEVEL` to prevent the current thread from being rescheduled whilst it touches per-CPU data, without
+/// acquiring a lock.
+///
+/// # IRQL
+///
+/// `level` must be greater than or equal to the current IRQL; attempting to raise to a lower IRQL causes a kernel
+/// panic (`IRQL_NOT_GREATER_OR_EQUAL`). With the `debug` feature enabled this is asserted before the IRQL is raised.
+///
+/// Whilst `f` runs, the thread is at `level`. If `level >= DISPATCH_LEVEL`, `f` **must not** block, wait on a
+/// dispatcher object, acquire a [`crate::kmutex::KMutex`] or [`crate::fast_mutex::FastMutex`], or touch paged memory.
+///
+/// # Examples
+///
+/// ```
+/// let cpu_local = wdk_mutex::with_raised_irql(DISPATCH_LEVEL as u8, || {
+///     // Thread switching is disabled here
+///     read_per_cpu_data()
+/// });
+/// ```
+pub fn with_raised_irql<R>(level: KIRQL, f: impl FnOnce() -> R) -> R {
+    #[cfg(feature = "debug")]
+    {
+        let current = unsafe { KeGetCurrentIrql() };
+        assert!(
+            level >= current,
+            "with_raised_irql: cannot raise IRQL to {} from {}",
+            level,
+            current
+        );
+    }
+
+    // SAFETY: Raising the IRQL is sound so long as `level` is not lower than the current IRQL, as documented.
+    let old_irql = unsafe { KfRaiseIrql(level) };
+
+    let result = f();
+
+    // SAFETY: Restores the IRQL captured above, which is less than or equal to the current IRQL.
+    unsafe { KeLowerIrql(old_irql) };
+
+    result
+}
+
+/// A RAII guard which raises the IRQL when created, through [`Self::raise_to`], and restores the previous IRQL when
+/// dropped.
+///
+/// This is the guard form of [`with_raised_irql`], for functions where a closure is awkward, such as when the raised
+/// section returns early through `?`. The IRQL is per processor, so the guard cannot be sent to another thread.
+///
+/// # IRQL
+///
+/// As per [`with_raised_irql`]. Where several guards are nested, they must be dropped in the reverse order to that in
+/// which they were created, as dropping an outer guard first lowers the IRQL below the level an inner guard would
+/// then "restore" to, which causes a kernel panic. Guards held in local variables are dropped in reverse order
+/// automatically; take care when moving them or dropping them explicitly. With the `debug` feature enabled, an out
+/// of order drop panics before the IRQL is changed.
+///
+/// # Examples
+///
+/// ```
+/// fn update_per_cpu(stats: &PerCpu) -> Result<(), DriverMutexError> {
+///     let _irql = RaisedIrql::raise_to(DISPATCH_LEVEL as u8);
+///
+///     // Thread switching is disabled until `_irql` is dropped, including on the early return
+///     let slot = stats.current()?;
+///     slot.count += 1;
+///     Ok(())
+/// }
+/// ```
+pub struct RaisedIrql {
+    /// The IRQL the caller was running at before it was raised, restored on drop
+    old_irql: KIRQL,
+    /// The IRQL must be restored on the processor it was raised on
+    _not_send: PhantomData<*const ()>,
+}
+
+impl RaisedIrql {
+    /// Raises the IRQL to `level`, returning a guard which restores the current IRQL when dropped.
+    ///
+    /// # IRQL
+    ///
+    /// `level` must be greater than or equal to the current IRQL; attempting to raise to a lower IRQL causes a kernel
+    /// panic (`IRQL_NOT_GREATER_OR_EQUAL`). With the `debug` feature enabled this is asserted before the IRQL is
+    /// raised.
+    pub fn raise_to(level: KIRQL) -> Self {
+        #[cfg(feature = "debug")]
+        {
+            let current = unsafe { KeGetCurrentIrql() };
+            assert!(
+                level >= current,
+                "RaisedIrql::raise_to: cannot raise IRQL to {} from {}",
+                level,
+                current
+            );
+        }
+
+        // SAFETY: Raising the IRQL is sound so long as `level` is not lower than the current IRQL, as documented.
+        let old_irql = unsafe { KfRaiseIrql(level) };
+
+        Self {
+            old_irql,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Returns the IRQL which will be restored when the guard is dropped.
+    pub fn old_irql(&self) -> KIRQL {
+        self.old_irql
+    }
+}
+
+impl Drop for RaisedIrql {
+    fn drop(&mut self) {
+        #[cfg(feature = "debug")]
+        {
+            let current = unsafe { KeGetCurrentIrql() };
+            assert!(
+                self.old_irql <= current,
+                "RaisedIrql dropped at IRQL {}, below the IRQL {} it would restore",
+                current,
+                self.old_irql
+            );
+        }
+
+        // SAFETY: Restores the IRQL captured in `raise_to`, which is less than or equal to the current IRQL so long as
+        // guards are dropped in reverse order, as documented.
+        unsafe { KeLowerIrql(self.old_irql) };
+    }
+}
+
+/// Checks that a guard is being dropped at an IRQL from which its mutex may be released, for the `debug` feature.
+///
+/// Releasing a mutex at the wrong IRQL bugchecks, often somewhere which says little about the cause. If the current
+/// IRQL is outside `min..=max`, a diagnostic naming the guard and the IRQL it was acquired at is written through the
+/// log sink and flushed, and the function panics, before the release is attempted.
+#[cfg(feature = "debug")]
+pub(crate) fn check_release_irql(guard: &str, acquired_at: KIRQL, min: KIRQL, max: KIRQL) {
+    let current = unsafe { KeGetCurrentIrql() };
+    if (min..=max).contains(&current) {
+        return;
+    }
+
+    emit(format_args!(
+        "wdk-mutex: {} released at IRQL {}, outside the permitted {}..={} (acquired at IRQL {})",
+        guard, current, min, max, acquired_at
+    ));
+    flush_log();
+
+    panic!(
+        "{} released at IRQL {}, outside the permitted {}..={}",
+        guard, current, min, max
+    );
+}
@@ -2,21 +2,35 @@
 
 use alloc::boxed::Box;
 use core::{
-    ffi::c_void, fmt::Display, mem::ManuallyDrop, ops::{Deref, DerefMut}, ptr::{self, drop_in_place, null_mut}
+    alloc::Layout, ffi::c_void, fmt::{Debug, Display}, marker::PhantomData, mem::{self, forget, ManuallyDrop}, ops::{Deref, DerefMut}, panic::Location, ptr::{self, drop_in_place, null_mut}
 };
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering::SeqCst};
 use wdk_sys::{
     ntddk::{
-        ExAllocatePool2, ExFreePool, KeGetCurrentIrql, KeInitializeMutex, KeReleaseMutex,
-        KeWaitForSingleObject,
+        ExAllocatePool2, KeGetCurrentIrql, KeInitializeMutex, KeQueryPerformanceCounter,
+        KeReadStateMutex, KeReleaseMutex, KeWaitForSingleObject,
     },
-    APC_LEVEL, DISPATCH_LEVEL, FALSE, KMUTEX, POOL_FLAG_NON_PAGED,
+    APC_LEVEL, DISPATCH_LEVEL, FALSE, KMUTEX, KWAIT_REASON, LARGE_INTEGER, PASSIVE_LEVEL, POOL_FLAG_PAGED,
+    STATUS_ALERTED, STATUS_TIMEOUT, STATUS_USER_APC, TRUE,
     _KWAIT_REASON::Executive,
     _MODE::KernelMode,
 };
 
 extern crate alloc;
 
-use crate::errors::DriverMutexError;
+use crate::{
+    arc::ArcNP,
+    errors::DriverMutexError,
+    kmutex_slice::KMutexSlice,
+    lookaside::{free_entry, KMutexLookaside, LookasideInner},
+    multi_lock::lock_both,
+    pool::{NonPagedPool, PoolAllocator},
+};
+#[cfg(feature = "debug")]
+use crate::irql::check_release_irql;
+#[cfg(feature = "stats")]
+use crate::stats::{MutexStats, StatCounters};
+
 /// A thread safe mutex implemented through acquiring a KMUTEX in the Windows kernel.
 ///
 /// The type `Kmutex<T>` provides mutually exclusive access to the inner type T allocated through
@@ -41,6 +55,42 @@ use crate::errors::DriverMutexError;
 ///
 /// KMutex handles the deallocation of resources at the point the KMutex is dropped.
 ///
+/// # Two phase initialisation
+///
+/// A `KMutex` may be created without any allocation through the `const` function [`Self::uninit`], allowing it to be
+/// placed directly in a `static`, and later allocated with [`Self::initialize`] (for example during `DriverEntry`).
+/// Until it is initialised, functions which acquire the mutex return [`DriverMutexError::NotInitialised`].
+///
+/// # Arming
+///
+/// For data which is not ready until some later event (such as a device arrival callback), a `KMutex` can be created
+/// unarmed, holding a placeholder value, through [`Self::new_unarmed`]. The mutex moves through two states:
+///
+/// - **Unarmed**: the mutex is allocated and may be shared, but functions which acquire the mutex return
+///   [`DriverMutexError::NotArmed`], so the placeholder cannot be observed or modified.
+/// - **Armed**: [`Self::arm`] replaces the placeholder with the real data and permits acquisition from then on. A
+///   mutex created with [`Self::new`] starts armed. Arming is one way; arming an armed mutex returns
+///   [`DriverMutexError::AlreadyArmed`].
+///
+/// # Poisoning
+///
+/// As in `std::sync::Mutex`, a mutex is poisoned when the data it protects may have been left half modified. Once
+/// poisoned, functions which acquire the mutex return [`DriverMutexError::Poisoned`] (releasing it again) until
+/// [`Self::clear_poison`] is called; [`Self::lock_ignore_poison`] acquires the mutex regardless, for recovering the
+/// data.
+///
+/// `core` provides no way to detect that a thread is unwinding, so a guard cannot poison the mutex on its own when it
+/// is dropped during a panic. Instead, a mutex is poisoned when:
+///
+/// - the closure passed to [`Self::with_lock`], [`Self::with_lock_timeout`] or [`Self::try_with_timed`] unwinds, or
+/// - a guard holder calls [`KMutexGuard::poison`], for example when an update fails part way through.
+///
+/// Poisoning on panic therefore only applies to the closure based functions. A [`KMutexGuard`] obtained from
+/// [`Self::lock`] or any other guard returning function which is dropped whilst unwinding releases the mutex without
+/// poisoning it, so a panic part way through an update made through a guard leaves the mutex unpoisoned.
+///
+/// With the `panic = "abort"` strategy normally used for drivers, a panic never unwinds, so only the latter applies.
+///
 /// # Examples
 ///
 /// ## Locally scoped mutex:
@@ -103,20 +153,70 @@ pub struct KMutex<T> {
     inner: *mut KMutexInner<T>,
 }
 
+/// The functions of the [`PoolAllocator`] a mutex was allocated with.
+///
+/// Allocators are stateless, so recording their functions is enough to free the mutex, and to allocate clones of it,
+/// through the same allocator without making `KMutex` generic over it.
+#[derive(Clone, Copy)]
+struct AllocatorFns {
+    allocate: fn(usize, u32) -> *mut c_void,
+    free: unsafe fn(*mut c_void),
+}
+
+impl AllocatorFns {
+    /// Records the functions of the allocator `A`.
+    fn of<A: PoolAllocator>() -> Self {
+        Self {
+            allocate: A::allocate,
+            free: A::free,
+        }
+    }
+}
+
 /// The underlying data which is non-page pool allocated which is pointed to by the `KMutex`.
+///
+/// Unless the mutex was created with `KMutex::new_paged`, the protected data is stored immediately after this
+/// structure in the same allocation.
 struct KMutexInner<T> {
     /// A KMUTEX structure allocated into KMutexInner
     mutex: KMUTEX,
+    /// The pool tag this structure was allocated with
+    tag: u32,
+    /// Whether the mutex may be acquired, false between `new_unarmed` and `arm`
+    armed: AtomicBool,
+    /// Set when the protected data may have been left in an inconsistent state, see `KMutex` poisoning
+    poisoned: AtomicBool,
+    /// The source location of the most recent acquisition of the mutex, null if it has never been acquired
+    #[cfg(feature = "debug")]
+    last_acquire_site: AtomicPtr<Location<'static>>,
+    /// Set whilst a raw pointer to the data is lent out by `KMutexGuard::with_exclusive`
+    #[cfg(feature = "debug")]
+    exclusive: AtomicBool,
+    /// Acquisition and contention counters
+    #[cfg(feature = "stats")]
+    stats: StatCounters,
+    /// Whether the data is held in a separate paged pool allocation
+    paged: bool,
+    /// The allocator the control block was allocated with, used to free it and to allocate clones, or `None` if the
+    /// memory was provided through `from_raw_parts` or a lookaside list
+    allocator: Option<AllocatorFns>,
+    /// The lookaside list the control block was allocated from, or null if it was allocated from the pool
+    lookaside: *mut LookasideInner,
     /// The data for which the mutex is protecting
-    data: T,
+    data: *mut T,
 }
 
-unsafe impl<T> Sync for KMutex<T> {}
-unsafe impl<T> Send for KMutex<T> {}
+// SAFETY: The mutex gives exclusive access to the `T` to whichever thread holds it, and the `T` is dropped by
+// whichever thread drops the mutex, so as with `std::sync::Mutex` the `T` only needs to be `Send`.
+unsafe impl<T: Send> Sync for KMutex<T> {}
+unsafe impl<T: Send> Send for KMutex<T> {}
 
 impl<T> KMutex<T> {
     /// Creates a new KMUTEX Windows Kernel Driver Mutex in a signaled (free) state.
     ///
+    /// `ExAllocatePool2` only guarantees 16 byte alignment, so if `T` requires a greater alignment the allocation is
+    /// padded and the data placed at the required alignment within it.
+    ///
     /// # IRQL
     ///
     /// This can be called at any IRQL.
@@ -128,270 +228,2556 @@ impl<T> KMutex<T> {
     ///
     /// let my_mutex = wdk_mutex::KMutex::new(0u32);
     /// ```
+    ///
+    /// An over-aligned type:
+    ///
+    /// ```
+    /// #[repr(align(64))]
+    /// struct CacheLine([u8; 64]);
+    ///
+    /// let mtx = KMutex::new(CacheLine([0; 64])).unwrap();
+    /// assert_eq!(&*mtx.lock().unwrap() as *const CacheLine as usize % 64, 0);
+    /// ```
     pub fn new(data: T) -> Result<Self, DriverMutexError> {
-        //
-        // Non-Paged heap alloc for all struct data required for KMutexInner
-        //
-        let total_sz_required = size_of::<KMutexInner<T>>();
-        let inner_heap_ptr: *mut c_void = unsafe {
-            ExAllocatePool2(
-                POOL_FLAG_NON_PAGED,
-                total_sz_required as u64,
-                u32::from_be_bytes(*b"kmtx"),
-            )
-        };
-        if inner_heap_ptr.is_null() {
-            return Err(DriverMutexError::PagedPoolAllocFailed);
-        }
-
-        // Cast the memory allocation to a pointer to the inner
-        let kmutex_inner_ptr = inner_heap_ptr as *mut KMutexInner<T>;
-
-        // SAFETY: This raw write is safe as the pointer validity is checked above.
-        unsafe {
-            ptr::write(
-                kmutex_inner_ptr,
-                KMutexInner {
-                    mutex: KMUTEX::default(),
-                    data,
-                },
-            );
-
-            // Initialise the KMUTEX object via the kernel
-            KeInitializeMutex(&(*kmutex_inner_ptr).mutex as *const _ as *mut _, 0);
-        }
-
-        Ok(Self {
-            inner: kmutex_inner_ptr,
-        })
+        Self::new_with_tag(data, u32::from_be_bytes(*b"kmtx"))
     }
 
-    /// Acquires a mutex in a non-alertable manner.
+    /// Creates a new KMUTEX Windows Kernel Driver Mutex in a signaled (free) state, protecting `T::default()`.
     ///
-    /// Once the thread has acquired the mutex, it will return a `KMutexGuard` which is a RAII scoped
-    /// guard allowing exclusive access to the inner T.
+    /// This is provided in place of a `Default` implementation for `KMutex<T>`, as allocating the mutex can fail and
+    /// `Default` would have to panic on failure.
     ///
     /// # Errors
     ///
-    /// If the IRQL is too high, this function will return an error and will not acquire a lock. To prevent
-    /// a kernel panic, the caller should match the return value rather than just unwrapping the value.
+    /// Returns [`DriverMutexError::AllocFailed`] if the non-paged pool allocation fails.
     ///
     /// # IRQL
     ///
-    /// This function must be called at IRQL `<= APC_LEVEL`, if the IRQL is higher than this,
-    /// the function will return an error.
-    ///
-    /// It is the callers responsibility to ensure the IRQL is sufficient to call this function and it
-    /// will not alter the IRQL for the caller, as this may introduce undefined behaviour elsewhere in the
-    /// driver / kernel.
+    /// This can be called at any IRQL, subject to the requirements of `T::default()`.
     ///
     /// # Examples
     ///
     /// ```
-    /// let mtx = KMutex::new(0u32).unwrap();
-    /// let lock = mtx.lock().unwrap();
+    /// let stats = KMutex::<DriverStats>::new_default().unwrap();
     /// ```
-    pub fn lock(&self) -> Result<KMutexGuard<'_, T>, DriverMutexError> {
-        // Check the IRQL is <= APC_LEVEL as per remarks at
-        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-kewaitforsingleobject
-        let irql = unsafe { KeGetCurrentIrql() };
-        if irql > APC_LEVEL as u8 {
-            return Err(DriverMutexError::IrqlTooHigh);
-        }
-
-        // Discard the return value; the status code does not represent an error or contain information
-        // relevant to the context of no timeout.
-        let _ = unsafe {
-            // SAFETY: The IRQL is sufficient for the operation as checked above, and we know our pointer
-            // is valid as RAII manages the lifetime of the heap allocation, ensuring it will only be deallocated
-            // once Self gets dropped.
-            KeWaitForSingleObject(
-                &mut (*self.inner).mutex as *mut _ as *mut _,
-                Executive,
-                KernelMode as i8,
-                FALSE as u8,
-                null_mut(),
-            )
-        };
+    pub fn new_default() -> Result<Self, DriverMutexError>
+    where
+        T: Default,
+    {
+        Self::new(T::default())
+    }
 
-        Ok(KMutexGuard { kmutex: self })
+    /// Creates a new KMUTEX Windows Kernel Driver Mutex in a signaled (free) state, allocating it with the pool tag
+    /// `tag` rather than the default `kmtx`.
+    ///
+    /// Using a distinct tag per subsystem allows pool usage (and leaks) to be attributed with tools such as
+    /// `!poolused` in WinDbg. The tag can be read back with [`Self::tag`].
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let net_state = KMutex::new_with_tag(NetState::default(), u32::from_be_bytes(*b"net1")).unwrap();
+    /// ```
+    pub fn new_with_tag(data: T, tag: u32) -> Result<Self, DriverMutexError> {
+        Self::allocate(data, tag, false, AllocatorFns::of::<NonPagedPool>())
     }
 
-    /// Consumes the mutex and returns an owned copy of the protected data (`T`).
+    /// Creates a new KMUTEX Windows Kernel Driver Mutex in a signaled (free) state, with the allocation made by the
+    /// [`PoolAllocator`] `A` rather than directly from the non-paged pool.
     ///
-    /// This method performs a deep copy of the data (`T`) guarded by the mutex before
-    /// deallocating the internal memory. Be cautious when using this method with large
-    /// data types, as it may lead to inefficiencies or stack overflows.
+    /// The allocator is chosen by type, as allocators are stateless. The mutex is freed through the same allocator when
+    /// it is dropped, and [`Self::try_clone`] allocates the clone through it too. [`Self::new`] is equivalent to
+    /// `new_in::<NonPagedPool>(data)`.
     ///
-    /// For scenarios involving large data that you prefer not to allocate on the stack,
-    /// consider using [`Self::to_owned_box`] instead.
+    /// # Errors
     ///
-    /// # Safety
+    /// Returns [`DriverMutexError::AllocFailed`] if the allocator returns null.
     ///
-    /// This function moves `T` out of the mutex without running `Drop` on the original 
-    /// in-place value. The returned `T` remains fully owned by the caller and will be 
-    /// dropped normally.
-    /// 
-    /// - **Single Ownership Guarantee:** After calling [`Self::to_owned`], ensure that
-    ///   no other references (especially static or global ones) attempt to access the
-    ///   underlying mutex. This is because the mutexes memory is deallocated once this
-    ///   method is invoked.
-    /// - **Exclusive Access:** This function should only be called when you can guarantee
-    ///   that there will be no further access to the protected `T`. Violating this can
-    ///   lead to undefined behavior since the memory is freed after the call.
+    /// # IRQL
     ///
-    /// # Example
+    /// This can be called at any IRQL supported by the allocator.
+    ///
+    /// # Examples
     ///
     /// ```
-    /// unsafe {
-    ///     let owned_data: T = mutex.to_owned();
-    ///     // Use `owned_data` safely here
-    /// }
+    /// let mtx = KMutex::new_in::<QuotaPool>(Config::default()).unwrap();
     /// ```
-    pub unsafe fn to_owned(self) -> T {
-        let manually_dropped = ManuallyDrop::new(self);
-        let data_read = unsafe { ptr::read(&(*manually_dropped.inner).data) };
-        
-        // Free the mutex allocation without using drop semantics which could cause an
-        // accidental double drop of the underlying `T`.
-        unsafe { ExFreePool(manually_dropped.inner as _) };
-
-        data_read
+    pub fn new_in<A: PoolAllocator>(data: T) -> Result<Self, DriverMutexError> {
+        Self::allocate(data, u32::from_be_bytes(*b"kmtx"), false, AllocatorFns::of::<A>())
     }
 
-    /// Consumes the mutex and returns an owned `Box<T>` containing the protected data (`T`).
+    /// Creates a new KMUTEX Windows Kernel Driver Mutex in a signaled (free) state, with the protected data allocated
+    /// in the paged pool.
     ///
-    /// This method is an alternative to [`Self::to_owned`] and is particularly useful when
-    /// dealing with large data types. By returning a `Box<T>`, the data is pool-allocated,
-    /// avoiding potential stack overflows associated with large stack allocations.
+    /// This is intended for large data which is only ever accessed at `PASSIVE_LEVEL` / `APC_LEVEL`, to save
+    /// non-paged pool. The `KMUTEX` itself must remain resident, so the mutex is split into two allocations: a small
+    /// non-paged control block holding the `KMUTEX`, and a paged allocation holding the data.
     ///
-    /// # Safety
-    /// 
-    /// This function moves `T` out of the mutex without running `Drop` on the original 
-    /// in-place value. The returned `T` remains fully owned by the caller and will be 
-    /// dropped normally.
+    /// As the data may be paged out, it must not be accessed above `APC_LEVEL`. [`Self::lock`] already requires
+    /// IRQL `<= APC_LEVEL`, however the caller must not raise the IRQL above `APC_LEVEL` whilst accessing the data
+    /// through the guard.
     ///
-    /// - **Single Ownership Guarantee:** After calling [`Self::to_owned_box`], ensure that
-    /// no other references (especially static or global ones) attempt to access the
-    /// underlying mutex. This is because the mutexes memory is deallocated once this
-    /// method is invoked.
-    /// - **Exclusive Access:** This function should only be called when you can guarantee
-    /// that there will be no further access to the protected `T`. Violating this can
-    /// lead to undefined behavior since the memory is freed after the call.
+    /// # Errors
     ///
-    /// # Example
+    /// Returns an error if the IRQL is above `APC_LEVEL`, or either pool allocation fails.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
     ///
-    /// ```rust
-    /// unsafe {
-    ///     let boxed_data: Box<T> = mutex.to_owned_box();
-    ///     // Use `boxed_data` safely here
-    /// }
     /// ```
-    pub unsafe fn to_owned_box(self) -> Box<T> {
-        let manually_dropped = ManuallyDrop::new(self);
-        let data_read = unsafe { ptr::read(&(*manually_dropped.inner).data) };
-        
-        // Free the mutex allocation without using drop semantics which could cause an
-        // accidental double drop of the underlying `T`.
-        unsafe { ExFreePool(manually_dropped.inner as _) };
+    /// let history = KMutex::new_paged([0u8; 64 * 1024]).unwrap();
+    /// ```
+    pub fn new_paged(data: T) -> Result<Self, DriverMutexError> {
+        // Paged pool can only be allocated at IRQL <= APC_LEVEL
+        if unsafe { KeGetCurrentIrql() } > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
 
-        Box::new(data_read)
+        Self::allocate(data, u32::from_be_bytes(*b"kmtx"), true, AllocatorFns::of::<NonPagedPool>())
     }
-}
 
-impl<T> Drop for KMutex<T> {
-    fn drop(&mut self) {
-        unsafe {
-            // Drop the underlying data and run destructors for the data, this would be relevant in the
-            // case where Self contains other heap allocated types which have their own deallocation
-            // methods.
-            drop_in_place(&mut (*self.inner).data);
+    /// Creates a new mutex protecting a slice of `len` elements, with element `i` initialised to `init(i)`.
+    ///
+    /// The `KMUTEX` and the elements are held in a single non-paged pool allocation, avoiding the second allocation
+    /// a `KMutex<Box<[T]>>` would require. See [`KMutexSlice`].
+    ///
+    /// # Errors
+    ///
+    /// As per [`KMutexSlice::new`].
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`, subject to the requirements of `init`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let buffer = KMutex::new_slice(512, |_| 0u8).unwrap();
+    /// buffer.lock().unwrap()[..4].copy_from_slice(b"head");
+    /// ```
+    pub fn new_slice(len: usize, init: impl FnMut(usize) -> T) -> Result<KMutexSlice<T>, DriverMutexError> {
+        KMutexSlice::new(len, init)
+    }
 
-            // Free the memory we allocated
-            ExFreePool(self.inner as *mut _);
+    /// Allocates and initialises the control block from `allocator`, and the data either inline after it or (if
+    /// `paged`) in a separate paged pool allocation.
+    fn allocate(data: T, tag: u32, paged: bool, allocator: AllocatorFns) -> Result<Self, DriverMutexError> {
+        //
+        // Non-Paged heap alloc for all struct data required for KMutexInner, followed by the data itself unless
+        // it is to be paged.
+        //
+        let (layout, data_offset) = if paged {
+            (Layout::new::<KMutexInner<T>>(), 0)
+        } else {
+            Layout::new::<KMutexInner<T>>()
+                .extend(Layout::new::<T>())
+                .map_err(|_| DriverMutexError::AllocFailed {
+                    requested: size_of::<KMutexInner<T>>().saturating_add(size_of::<T>()),
+                })?
+        };
+        let inner_heap_ptr: *mut c_void = unsafe { allocate_aligned_with(layout, |size| (allocator.allocate)(size, tag)) };
+        if inner_heap_ptr.is_null() {
+            return Err(DriverMutexError::AllocFailed {
+                requested: layout.size(),
+            });
         }
-    }
-}
 
-/// A RAII scoped guard for the inner data protected by the mutex. Once this guard is given out, the protected data
-/// may be safely mutated by the caller as we guarantee exclusive access via Windows Kernel Mutex primitives.
-///
-/// When this structure is dropped (falls out of scope), the lock will be unlocked.
-///
-/// # IRQL
-///
-/// Access to the data within this guard must be done at <= APC_LEVEL if a non-alertable lock was acquired, or <=
-/// DISPATCH_LEVEL if an alertable lock was acquired. It is the callers responsible to manage APC levels whilst
-/// using the KMutex.
-///
-/// If you wish to manually drop the lock with a safety check, call the function [`Self::drop_safe`].
-///
-/// # Kernel panic
-///
-/// Raising the IRQL above safe limits whilst using the mutex will cause a Kernel Panic if not appropriately handled.
-/// When RAII drops this type, the mutex is released, if the mutex goes out of scope whilst you hold an IRQL that
-/// is too high, you will receive a kernel panic.
-///
-pub struct KMutexGuard<'a, T> {
-    kmutex: &'a KMutex<T>,
-}
+        // Cast the memory allocation to a pointer to the inner
+        let kmutex_inner_ptr = inner_heap_ptr as *mut KMutexInner<T>;
 
-impl<T> Display for KMutexGuard<'_, T>
-where
-    T: Display,
-{
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
-        write!(f, "{}", unsafe { &(*self.kmutex.inner).data })
-    }
-}
+        let data_ptr: *mut T = if !paged {
+            unsafe { inner_heap_ptr.byte_add(data_offset) as *mut T }
+        } else if size_of::<T>() == 0 {
+            // Zero sized types never require an allocation
+            ptr::NonNull::dangling().as_ptr()
+        } else {
+            let data_heap_ptr = unsafe { allocate_aligned(POOL_FLAG_PAGED, Layout::new::<T>(), tag) };
+            if data_heap_ptr.is_null() {
+                unsafe { free_aligned_with(inner_heap_ptr, layout.align(), allocator.free) };
+                return Err(DriverMutexError::AllocFailed {
+                    requested: size_of::<T>(),
+                });
+            }
 
-impl<T> Deref for KMutexGuard<'_, T> {
-    type Target = T;
+            data_heap_ptr as *mut T
+        };
 
-    fn deref(&self) -> &Self::Target {
-        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
-        unsafe { &(*self.kmutex.inner).data }
-    }
-}
+        // SAFETY: The pointer validity is checked above.
+        unsafe { Self::init_inner(kmutex_inner_ptr, data_ptr, data, tag, paged, Some(allocator), null_mut()) };
 
-impl<T> DerefMut for KMutexGuard<'_, T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
-        // Mutable access is safe due to Self only being given out whilst a mutex is held from the
-        // kernel.
-        unsafe { &mut (*self.kmutex.inner).data }
+        Ok(Self {
+            inner: kmutex_inner_ptr,
+        })
     }
-}
 
-impl<T> Drop for KMutexGuard<'_, T> {
-    fn drop(&mut self) {
-        // NOT SAFE AT A IRQL TOO HIGH
-        unsafe { KeReleaseMutex(&mut (*self.kmutex.inner).mutex, FALSE as u8) };
+    /// Returns the size and alignment of the allocation a `KMutex<T>` requires, for use with
+    /// [`Self::from_raw_parts`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let layout = KMutex::<Connection>::raw_layout();
+    /// ```
+    pub fn raw_layout() -> Layout {
+        // KMutexInner and T are both sized, so their combined size can only overflow for a T which could never be
+        // allocated in the first place
+        Layout::new::<KMutexInner<T>>()
+            .extend(Layout::new::<T>())
+            .expect("KMutex layout overflowed")
+            .0
+            .pad_to_align()
     }
-}
 
-impl<T> KMutexGuard<'_, T> {
-    /// Safely drop the KMutexGuard, an alternative to RAII.
+    /// Creates a new KMUTEX Windows Kernel Driver Mutex in a signaled (free) state, in a non-paged allocation
+    /// provided by the caller rather than one made through `ExAllocatePool2`.
     ///
-    /// This function checks the IRQL before attempting to drop the guard.
+    /// This allows a driver to manage the memory backing its mutexes itself, for example from a lookaside list
+    /// backed object pool. The mutex does not free `ptr` when it is dropped; once the mutex has been dropped (or
+    /// consumed through [`Self::into_inner`] or [`Self::to_owned_box`]) the memory may be reused or returned to the
+    /// pool it came from.
     ///
-    /// # Errors
+    /// The mutex reports a pool tag of `0` through [`Self::tag`], as the tag of the caller's allocation is not known.
     ///
-    /// If the IRQL > DISPATCH_LEVEL, no unlock will occur and a DriverMutexError will be returned to the
-    /// caller.
+    /// # Safety
+    ///
+    /// - `ptr` must be non-null, and valid for writes of at least [`Self::raw_layout`]'s size.
+    /// - `ptr` must be aligned to [`Self::raw_layout`]'s alignment.
+    /// - The memory must be non-paged, and must remain valid and otherwise unused until the mutex has been dropped.
     ///
     /// # IRQL
     ///
-    /// This function is safe to call at any IRQL, but it will not release the mutex if IRQL > DISPATCH_LEVEL
-    pub fn drop_safe(&mut self) -> Result<(), DriverMutexError> {
-        let irql = unsafe { KeGetCurrentIrql() };
-        if irql > DISPATCH_LEVEL as u8 {
-            return Err(DriverMutexError::IrqlTooHigh);
-        }
-
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // The lookaside list was initialised with an entry size of KMutex::<Connection>::raw_layout().size()
+    /// let entry = unsafe { ExAllocateFromNPagedLookasideList(&mut lookaside) };
+    /// let mtx = unsafe { KMutex::from_raw_parts(entry, Connection::default()) };
+    ///
+    /// // ...
+    ///
+    /// drop(mtx);
+    /// unsafe { ExFreeToNPagedLookasideList(&mut lookaside, entry) };
+    /// ```
+    pub unsafe fn from_raw_parts(ptr: *mut c_void, data: T) -> Self {
+        let (_, data_offset) = Layout::new::<KMutexInner<T>>()
+            .extend(Layout::new::<T>())
+            .expect("KMutex layout overflowed");
+
+        let kmutex_inner_ptr = ptr as *mut KMutexInner<T>;
+
+        // SAFETY: The caller guarantees the pointer is valid, aligned, and large enough for the layout.
+        unsafe {
+            let data_ptr = ptr.byte_add(data_offset) as *mut T;
+            Self::init_inner(kmutex_inner_ptr, data_ptr, data, 0, false, None, null_mut());
+        }
+
+        Self {
+            inner: kmutex_inner_ptr,
+        }
+    }
+
+    /// Creates a new KMUTEX Windows Kernel Driver Mutex in a signaled (free) state, allocated from `lookaside` rather
+    /// than directly from the pool.
+    ///
+    /// When the mutex is dropped (or consumed), its allocation is returned to the lookaside list, where it is cached
+    /// for reuse by the next mutex allocated from the list. This is intended for drivers which create and destroy
+    /// many mutex protected objects, see [`KMutexLookaside`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`, or the lookaside list could not allocate an entry.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`, and the mutex must be dropped at IRQL `<= DISPATCH_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let lookaside = KMutexLookaside::<Request>::new().unwrap();
+    /// let mtx = KMutex::new_from_lookaside(&lookaside, Request::default()).unwrap();
+    /// ```
+    pub fn new_from_lookaside(
+        lookaside: &KMutexLookaside<T>,
+        data: T,
+    ) -> Result<Self, DriverMutexError> {
+        // Check the IRQL is <= DISPATCH_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-exallocatefromnpagedlookasidelist
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        let (entry, list) = lookaside.allocate().ok_or(DriverMutexError::AllocFailed {
+            requested: Self::raw_layout().size(),
+        })?;
+
+        let (_, data_offset) = Layout::new::<KMutexInner<T>>()
+            .extend(Layout::new::<T>())
+            .expect("KMutex layout overflowed");
+
+        let kmutex_inner_ptr = entry as *mut KMutexInner<T>;
+
+        // SAFETY: The entry is sized for the layout, and `KMutexLookaside` guarantees the alignment.
+        unsafe {
+            let data_ptr = entry.byte_add(data_offset) as *mut T;
+            Self::init_inner(
+                kmutex_inner_ptr,
+                data_ptr,
+                data,
+                lookaside.tag(),
+                false,
+                None,
+                list,
+            );
+        }
+
+        Ok(Self {
+            inner: kmutex_inner_ptr,
+        })
+    }
+
+    /// Writes the control block and data, and initialises the `KMUTEX`.
+    ///
+    /// # Safety
+    ///
+    /// `inner` and `data_ptr` must be valid for writes and correctly aligned, and `data_ptr` must be in the paged pool
+    /// only if `paged` is set.
+    unsafe fn init_inner(
+        inner: *mut KMutexInner<T>,
+        data_ptr: *mut T,
+        data: T,
+        tag: u32,
+        paged: bool,
+        allocator: Option<AllocatorFns>,
+        lookaside: *mut LookasideInner,
+    ) {
+        unsafe {
+            ptr::write(data_ptr, data);
+            ptr::write(
+                inner,
+                KMutexInner {
+                    mutex: KMUTEX::default(),
+                    tag,
+                    armed: AtomicBool::new(true),
+                    poisoned: AtomicBool::new(false),
+                    #[cfg(feature = "debug")]
+                    last_acquire_site: AtomicPtr::new(null_mut()),
+                    #[cfg(feature = "debug")]
+                    exclusive: AtomicBool::new(false),
+                    #[cfg(feature = "stats")]
+                    stats: StatCounters::new(),
+                    paged,
+                    allocator,
+                    lookaside,
+                    data: data_ptr,
+                },
+            );
+
+            // Initialise the KMUTEX object via the kernel
+            KeInitializeMutex(&(*inner).mutex as *const _ as *mut _, 0);
+        }
+    }
+
+    /// Creates a new mutex in the unarmed state, holding `placeholder` until the real data is supplied through
+    /// [`Self::arm`]. See [Arming](#arming).
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let device = KMutex::new_unarmed(DeviceInfo::default()).unwrap();
+    /// assert_eq!(device.lock().err(), Some(DriverMutexError::NotArmed));
+    ///
+    /// // Later, in the device arrival callback
+    /// device.arm(DeviceInfo::from(arrival)).unwrap();
+    /// let info = device.lock().unwrap();
+    /// ```
+    pub fn new_unarmed(placeholder: T) -> Result<Self, DriverMutexError> {
+        let mtx = Self::new(placeholder)?;
+
+        // SAFETY: The mutex has just been allocated and has not been shared.
+        unsafe { (*mtx.inner).armed.store(false, SeqCst) };
+
+        Ok(mtx)
+    }
+
+    /// Arms a mutex created with [`Self::new_unarmed`], replacing the placeholder with `data` and allowing the mutex
+    /// to be acquired from then on. The placeholder is dropped.
+    ///
+    /// The replacement is made whilst holding the mutex, and the mutex is armed before it is released, so no thread
+    /// can observe the placeholder.
+    ///
+    /// # Errors
+    ///
+    /// - [`DriverMutexError::AlreadyArmed`] if the mutex is already armed, in which case `data` is dropped.
+    /// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `APC_LEVEL`.
+    /// - [`DriverMutexError::NotInitialised`] if the mutex has not been initialised.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn arm(&self, data: T) -> Result<(), DriverMutexError> {
+        if self.inner.is_null() {
+            return Err(DriverMutexError::NotInitialised);
+        }
+
+        if self.is_armed() {
+            return Err(DriverMutexError::AlreadyArmed);
+        }
+
+        // The mutex is not armed, so is acquired without the usability check made by `acquire`
+        let mut guard = self.wait_for_ownership(Executive)?;
+
+        // Another thread may have armed the mutex whilst we were waiting
+        if self.is_armed() {
+            return Err(DriverMutexError::AlreadyArmed);
+        }
+
+        *guard = data;
+        unsafe { (*self.inner).armed.store(true, SeqCst) };
+
+        Ok(())
+    }
+
+    /// Returns `true` if the mutex is initialised and armed, see [Arming](#arming).
+    pub fn is_armed(&self) -> bool {
+        // SAFETY: The pointer is checked for null before it is dereferenced.
+        !self.inner.is_null() && unsafe { (*self.inner).armed.load(SeqCst) }
+    }
+
+    /// Checks the mutex is in a state in which it may be acquired.
+    fn check_usable(&self) -> Result<(), DriverMutexError> {
+        if self.inner.is_null() {
+            return Err(DriverMutexError::NotInitialised);
+        }
+
+        if !self.is_armed() {
+            return Err(DriverMutexError::NotArmed);
+        }
+
+        Ok(())
+    }
+
+    /// Checks the mutex may be acquired, for use by [`crate::lock_ordered`]. Not part of the public API.
+    #[doc(hidden)]
+    pub fn __check_usable(&self) -> Result<(), DriverMutexError> {
+        self.check_usable()
+    }
+
+    /// Creates a placeholder `KMutex` which has not yet been allocated, for use in a `static` initialiser.
+    ///
+    /// The mutex must be initialised with [`Self::initialize`] before it can be used; until then, functions which
+    /// acquire the mutex return [`DriverMutexError::NotInitialised`]. An uninitialised `KMutex` may be dropped, which
+    /// does nothing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// static mut CONFIG: KMutex<Config> = KMutex::uninit();
+    ///
+    /// #[export_name = "DriverEntry"]
+    /// pub unsafe extern "system" fn driver_entry(
+    ///     driver: &mut DRIVER_OBJECT,
+    ///     registry_path: PCUNICODE_STRING,
+    /// ) -> NTSTATUS {
+    ///     if let Err(e) = unsafe { (*addr_of_mut!(CONFIG)).initialize(Config::default()) } {
+    ///         println!("Error initialising mutex: {:?}", e);
+    ///         return STATUS_UNSUCCESSFUL;
+    ///     }
+    ///
+    ///     // ...
+    /// }
+    /// ```
+    pub const fn uninit() -> Self {
+        Self { inner: null_mut() }
+    }
+
+    /// Allocates and initialises a mutex created with [`Self::uninit`], protecting `data`.
+    ///
+    /// As this takes `&mut self`, it must be done before the mutex is shared with other threads. If the mutex was
+    /// already initialised, the existing mutex and the data it protects are dropped and replaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the non-paged pool allocation fails, in which case the mutex is left unchanged.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut mtx = KMutex::uninit();
+    /// assert_eq!(mtx.lock().err(), Some(DriverMutexError::NotInitialised));
+    ///
+    /// mtx.initialize(0u32).unwrap();
+    /// *mtx.lock().unwrap() += 1;
+    /// ```
+    pub fn initialize(&mut self, data: T) -> Result<(), DriverMutexError> {
+        *self = Self::new(data)?;
+
+        Ok(())
+    }
+
+    /// Returns `true` if the mutex has been allocated, either through [`Self::new`] or [`Self::initialize`].
+    pub fn is_initialised(&self) -> bool {
+        !self.inner.is_null()
+    }
+
+    /// Returns the pool tag the mutex was allocated with, or `0` if the mutex has not been initialised or was created
+    /// through [`Self::from_raw_parts`].
+    ///
+    /// This is the tag which the allocation is attributed to in tools such as `!poolused`.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mtx = KMutex::new(0u32).unwrap();
+    /// assert_eq!(mtx.tag(), u32::from_be_bytes(*b"kmtx"));
+    /// ```
+    pub fn tag(&self) -> u32 {
+        if self.inner.is_null() {
+            return 0;
+        }
+
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        unsafe { (*self.inner).tag }
+    }
+
+    /// Returns `true` if `self` and `other` refer to the same underlying `KMUTEX`.
+    ///
+    /// This compares the identity of the mutexes, not the protected data, so is useful for assertions that two
+    /// references obtained by different routes (such as one passed in by a caller and one fetched from the
+    /// [`crate::grt::Grt`]) are the same lock, or for keying a cache on a mutex. An uninitialised mutex is only the
+    /// same as itself.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let from_grt = Grt::get_kmutex::<u32>("my_test_mutex").unwrap();
+    /// debug_assert!(mtx.same_mutex(from_grt));
+    /// ```
+    pub fn same_mutex(&self, other: &KMutex<T>) -> bool {
+        if self.inner.is_null() {
+            return ptr::eq(self, other);
+        }
+
+        self.inner == other.inner
+    }
+
+    /// Returns `true` if the mutex is currently held by any thread, including the current one.
+    ///
+    /// This reads the signal state of the `KMUTEX` through `KeReadStateMutex` without acquiring it. Another thread
+    /// may acquire or release the mutex at any time, so the result may be out of date by the time it is used; it is
+    /// only meaningful for diagnostics and debug assertions, and must not be used to decide whether to acquire the
+    /// mutex. Use [`Self::try_lock`] for that.
+    ///
+    /// Returns `false` if the mutex has not been initialised.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mtx = KMutex::new(0u32).unwrap();
+    /// debug_assert!(!mtx.is_locked());
+    ///
+    /// let lock = mtx.lock().unwrap();
+    /// debug_assert!(mtx.is_locked());
+    /// ```
+    pub fn is_locked(&self) -> bool {
+        if self.inner.is_null() {
+            return false;
+        }
+
+        // The signal state is 1 whilst the mutex is free, and is decremented for each (recursive) acquisition.
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        unsafe { KeReadStateMutex(&mut (*self.inner).mutex) <= 0 }
+    }
+
+    /// Returns the number of times the owning thread has acquired the mutex without releasing it, or `0` if the mutex
+    /// is free.
+    ///
+    /// As a `KMUTEX` is recursive, the owning thread may acquire it repeatedly, see [Recursion](#recursion). The count
+    /// is derived from the signal state read through `KeReadStateMutex`. As with [`Self::is_locked`], the result is
+    /// only stable when called by the owning thread, and is otherwise only meaningful for diagnostics.
+    ///
+    /// Returns `0` if the mutex has not been initialised.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mtx = KMutex::new(0u32).unwrap();
+    ///
+    /// let outer = mtx.lock().unwrap();
+    /// let inner = mtx.lock().unwrap();
+    /// assert_eq!(mtx.recursion_count(), 2);
+    ///
+    /// drop(inner);
+    /// assert_eq!(mtx.recursion_count(), 1);
+    /// ```
+    pub fn recursion_count(&self) -> i32 {
+        if self.inner.is_null() {
+            return 0;
+        }
+
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        let state = unsafe { KeReadStateMutex(&mut (*self.inner).mutex) };
+
+        // A free mutex has a signal state of 1, each acquisition decrements it
+        1 - state
+    }
+
+    /// Returns `true` if the mutex has been poisoned, see [Poisoning](#poisoning).
+    ///
+    /// Returns `false` if the mutex has not been initialised.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn is_poisoned(&self) -> bool {
+        // SAFETY: The pointer is checked for null before it is dereferenced.
+        !self.inner.is_null() && unsafe { (*self.inner).poisoned.load(SeqCst) }
+    }
+
+    /// Clears the poisoned state of the mutex, allowing it to be acquired through [`Self::lock`] again.
+    ///
+    /// This should only be called once the protected data has been checked or repaired, typically whilst holding a
+    /// guard from [`Self::lock_ignore_poison`]. Does nothing if the mutex has not been initialised.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn clear_poison(&self) {
+        if self.inner.is_null() {
+            return;
+        }
+
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        unsafe { (*self.inner).poisoned.store(false, SeqCst) };
+    }
+
+    /// Constructs a guard for a mutex which the current thread has just acquired, unless the mutex is poisoned, in
+    /// which case the mutex is released again.
+    fn guard_unless_poisoned(&self, irql: u8) -> Result<KMutexGuard<'_, T>, DriverMutexError> {
+        let guard = KMutexGuard::new(self, irql);
+
+        if self.is_poisoned() {
+            drop(guard);
+            return Err(DriverMutexError::Poisoned);
+        }
+
+        Ok(guard)
+    }
+
+    /// Acquires a mutex in a non-alertable manner.
+    ///
+    /// Once the thread has acquired the mutex, it will return a `KMutexGuard` which is a RAII scoped
+    /// guard allowing exclusive access to the inner T.
+    ///
+    /// # Errors
+    ///
+    /// If the IRQL is too high, this function will return an error and will not acquire a lock. To prevent
+    /// a kernel panic, the caller should match the return value rather than just unwrapping the value.
+    ///
+    /// If the mutex was created with [`Self::uninit`] and has not yet been initialised, this function will return
+    /// [`DriverMutexError::NotInitialised`]. If it was created with [`Self::new_unarmed`] and has not yet been armed,
+    /// this function will return [`DriverMutexError::NotArmed`].
+    ///
+    /// If the mutex has been poisoned, it is released again and [`DriverMutexError::Poisoned`] is returned, see
+    /// [Poisoning](#poisoning).
+    ///
+    /// # Poisoning
+    ///
+    /// The returned guard does not poison the mutex if it is dropped whilst unwinding. To have a panic poison the
+    /// mutex, use [`Self::with_lock`], or call [`KMutexGuard::poison`] when an update fails.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`, if the IRQL is higher than this,
+    /// the function will return an error.
+    ///
+    /// It is the callers responsibility to ensure the IRQL is sufficient to call this function and it
+    /// will not alter the IRQL for the caller, as this may introduce undefined behaviour elsewhere in the
+    /// driver / kernel.
+    ///
+    /// # Recursion
+    ///
+    /// Unlike `std::sync::Mutex`, a `KMUTEX` is recursive: a thread which already holds the mutex may acquire it again
+    /// without deadlocking, and the mutex is only released once every guard has been dropped. See
+    /// [`Self::recursion_count`]. Each guard dereferences mutably to the same data, so a thread holding more than one
+    /// guard must take care not to hold references obtained through both at once.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mtx = KMutex::new(0u32).unwrap();
+    /// let lock = mtx.lock().unwrap();
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn lock(&self) -> Result<KMutexGuard<'_, T>, DriverMutexError> {
+        let guard = self.lock_ignore_poison()?;
+
+        if self.is_poisoned() {
+            drop(guard);
+            return Err(DriverMutexError::Poisoned);
+        }
+
+        Ok(guard)
+    }
+
+    /// Acquires the mutex as per [`Self::lock`], even if it has been poisoned.
+    ///
+    /// This allows the protected data of a poisoned mutex to be inspected and repaired, after which the poison can be
+    /// cleared with [`Self::clear_poison`]. See [Poisoning](#poisoning).
+    ///
+    /// # Errors
+    ///
+    /// As per [`Self::lock`], other than [`DriverMutexError::Poisoned`].
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if mtx.is_poisoned() {
+    ///     let mut lock = mtx.lock_ignore_poison().unwrap();
+    ///     *lock = Config::default();
+    ///     mtx.clear_poison();
+    /// }
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn lock_ignore_poison(&self) -> Result<KMutexGuard<'_, T>, DriverMutexError> {
+        self.acquire(Executive)
+    }
+
+    /// Acquires the mutex as per [`Self::lock`], passing `reason` to `KeWaitForSingleObject` as the wait reason in
+    /// place of `Executive`.
+    ///
+    /// The wait reason is recorded against the thread whilst it is blocked, and is shown by debugger extensions such
+    /// as `!thread`, so a more specific reason such as `WrMutex` can make blocked threads easier to identify when
+    /// analysing a hang. It does not otherwise affect the wait.
+    ///
+    /// # Errors
+    ///
+    /// As per [`Self::lock`].
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use wdk_sys::_KWAIT_REASON::WrMutex;
+    ///
+    /// let lock = mtx.lock_with_reason(WrMutex).unwrap();
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn lock_with_reason(&self, reason: KWAIT_REASON) -> Result<KMutexGuard<'_, T>, DriverMutexError> {
+        let guard = self.acquire(reason)?;
+
+        if self.is_poisoned() {
+            drop(guard);
+            return Err(DriverMutexError::Poisoned);
+        }
+
+        Ok(guard)
+    }
+
+    /// Acquires the mutex with the wait reason `reason`, regardless of whether it has been poisoned.
+    #[cfg_attr(feature = "debug", track_caller)]
+    fn acquire(&self, reason: KWAIT_REASON) -> Result<KMutexGuard<'_, T>, DriverMutexError> {
+        self.check_usable()?;
+
+        self.wait_for_ownership(reason)
+    }
+
+    /// Waits for the mutex with the wait reason `reason` and no timeout, without checking the state of the mutex or
+    /// whether it has been poisoned.
+    ///
+    /// This is the wait shared by every blocking acquisition, including [`Self::arm`], so that IRQL checking, status
+    /// handling and the `stats` / `debug` bookkeeping are made in one place.
+    #[cfg_attr(feature = "debug", track_caller)]
+    fn wait_for_ownership(&self, reason: KWAIT_REASON) -> Result<KMutexGuard<'_, T>, DriverMutexError> {
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-kewaitforsingleobject
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // When counting contention, poll the mutex first so that we know whether we had to wait for it
+        #[cfg(feature = "stats")]
+        let acquired = {
+            let mut timeout = LARGE_INTEGER { QuadPart: 0 };
+            let status = unsafe {
+                // SAFETY: As below.
+                KeWaitForSingleObject(
+                    &mut (*self.inner).mutex as *mut _ as *mut _,
+                    reason,
+                    KernelMode as i8,
+                    FALSE as u8,
+                    &mut timeout,
+                )
+            };
+            status != STATUS_TIMEOUT
+        };
+        #[cfg(not(feature = "stats"))]
+        let acquired = false;
+
+        if !acquired {
+            // Discard the return value; the status code does not represent an error or contain information
+            // relevant to the context of no timeout.
+            let _ = unsafe {
+                // SAFETY: The IRQL is sufficient for the operation as checked above, and we know our pointer
+                // is valid as RAII manages the lifetime of the heap allocation, ensuring it will only be deallocated
+                // once Self gets dropped.
+                KeWaitForSingleObject(
+                    &mut (*self.inner).mutex as *mut _ as *mut _,
+                    reason,
+                    KernelMode as i8,
+                    FALSE as u8,
+                    null_mut(),
+                )
+            };
+        }
+
+        #[cfg(feature = "stats")]
+        unsafe { (*self.inner).stats.record_acquire(!acquired) };
+
+        #[cfg(feature = "debug")]
+        self.debug_on_acquire();
+
+        Ok(KMutexGuard::new(self, irql))
+    }
+
+    /// Acquires the mutex in a non-alertable manner without checking the IRQL, the state of the mutex, or whether it
+    /// has been poisoned.
+    ///
+    /// This skips the `KeGetCurrentIrql` call made by [`Self::lock`], for hot paths which acquire the mutex very
+    /// frequently from a context whose IRQL is already known. [`Self::lock`] should be preferred everywhere else.
+    ///
+    /// As the IRQL is not read, [`KMutexGuard::irql_at_acquire`] reports `PASSIVE_LEVEL` for the returned guard unless
+    /// the `debug` feature is enabled. With the `debug` feature, the preconditions below are checked and a violation
+    /// panics, so the IRQL check is not skipped in debug builds.
+    ///
+    /// With the `stats` feature the acquisition is counted, however it is never counted as contended, as detecting
+    /// contention would require the extra non-blocking wait this function exists to avoid.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that:
+    ///
+    /// - The current IRQL is `<= APC_LEVEL`. Waiting on a dispatcher object at `DISPATCH_LEVEL` or above will bugcheck.
+    /// - The mutex has been initialised (and armed, if created with [`Self::new_unarmed`]).
+    ///
+    /// A poisoned mutex is acquired as per [`Self::lock_ignore_poison`].
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Only ever called from a worker thread running at PASSIVE_LEVEL
+    /// fn record_sample(samples: &KMutex<Histogram>, value: u64) {
+    ///     // SAFETY: The worker thread runs at PASSIVE_LEVEL, and the mutex is initialised in DriverEntry.
+    ///     let mut lock = unsafe { samples.lock_unchecked() };
+    ///     lock.record(value);
+    /// }
+    /// ```
+    #[inline]
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub unsafe fn lock_unchecked(&self) -> KMutexGuard<'_, T> {
+        #[cfg(feature = "debug")]
+        let irql = {
+            assert!(
+                self.check_usable().is_ok(),
+                "KMutex::lock_unchecked called on a mutex which is not initialised or armed"
+            );
+
+            let irql = unsafe { KeGetCurrentIrql() };
+            assert!(irql <= APC_LEVEL as u8, "KMutex::lock_unchecked called at IRQL {}", irql);
+            irql
+        };
+        #[cfg(not(feature = "debug"))]
+        let irql = PASSIVE_LEVEL as u8;
+
+        // Discard the return value; the status code does not represent an error or contain information relevant to
+        // the context of no timeout.
+        let _ = unsafe {
+            // SAFETY: The caller guarantees the IRQL is sufficient and that the mutex is initialised.
+            KeWaitForSingleObject(
+                &mut (*self.inner).mutex as *mut _ as *mut _,
+                Executive,
+                KernelMode as i8,
+                FALSE as u8,
+                null_mut(),
+            )
+        };
+
+        #[cfg(feature = "stats")]
+        unsafe { (*self.inner).stats.record_acquire(false) };
+
+        #[cfg(feature = "debug")]
+        self.debug_on_acquire();
+
+        KMutexGuard::new(self, irql)
+    }
+
+    /// Acquires the mutex in an alertable manner.
+    ///
+    /// This behaves as [`Self::lock`], except that the wait may be interrupted: if the thread is alerted, or (for a
+    /// thread which entered the kernel from user mode) a user APC is queued to it, the wait ends without the mutex
+    /// having been acquired. In that case `KeWaitForSingleObject` returns `STATUS_ALERTED` or `STATUS_USER_APC`, and
+    /// this function returns [`DriverMutexError::Alerted`] rather than a guard, so a guard is only ever handed out for
+    /// a mutex the thread owns. The caller may retry, or unwind to allow the APC to be delivered.
+    ///
+    /// # Errors
+    ///
+    /// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `APC_LEVEL`.
+    /// - [`DriverMutexError::Alerted`] if the wait was interrupted before the mutex was acquired.
+    /// - [`DriverMutexError::NotInitialised`] if the mutex has not been initialised.
+    /// - [`DriverMutexError::NotArmed`] if the mutex has not been armed.
+    /// - [`DriverMutexError::Poisoned`] if the mutex has been poisoned.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`, as a waiting call to `KeWaitForSingleObject` must be.
+    /// Note that alerts and user APCs are only delivered to a thread waiting at `PASSIVE_LEVEL`; at `APC_LEVEL` the
+    /// wait behaves as a non-alertable wait.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// loop {
+    ///     match mtx.lock_alertable() {
+    ///         Ok(mut lock) => {
+    ///             *lock += 1;
+    ///             break;
+    ///         }
+    ///         Err(DriverMutexError::Alerted) => continue,
+    ///         Err(e) => {
+    ///             println!("Error: {:?}", e);
+    ///             break;
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn lock_alertable(&self) -> Result<KMutexGuard<'_, T>, DriverMutexError> {
+        self.check_usable()?;
+
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-kewaitforsingleobject
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        let status = unsafe {
+            // SAFETY: The IRQL is sufficient for the operation as checked above, and the pointer is valid as RAII
+            // manages the lifetime of the heap allocation.
+            KeWaitForSingleObject(
+                &mut (*self.inner).mutex as *mut _ as *mut _,
+                Executive,
+                KernelMode as i8,
+                TRUE as u8,
+                null_mut(),
+            )
+        };
+
+        // The wait was satisfied by an alert or APC rather than by the mutex, so the mutex is not owned
+        if status == STATUS_ALERTED || status == STATUS_USER_APC {
+            return Err(DriverMutexError::Alerted);
+        }
+
+        #[cfg(feature = "stats")]
+        unsafe { (*self.inner).stats.record_acquire(false) };
+
+        #[cfg(feature = "debug")]
+        self.debug_on_acquire();
+
+        self.guard_unless_poisoned(irql)
+    }
+
+    /// Acquires the mutex in a non-alertable manner, unless `flag` is set.
+    ///
+    /// This is intended for use with a driver wide "unloading" flag, so that threads arriving during teardown do not
+    /// block on a mutex which is about to be destroyed. `flag` is checked before waiting on the mutex, and again once
+    /// the mutex has been acquired; if it is set at either point, no guard is returned (the mutex is released if it was
+    /// acquired) and [`DriverMutexError::Aborted`] is returned.
+    ///
+    /// # Race window
+    ///
+    /// The flag is not observed whilst the thread is waiting. A thread which passed the first check before the flag
+    /// was set will remain blocked until the current owner releases the mutex, at which point the second check causes
+    /// it to return `Aborted`. The teardown path must therefore still release (or never hold) the mutex to allow
+    /// waiting threads to drain.
+    ///
+    /// # Errors
+    ///
+    /// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `APC_LEVEL`.
+    /// - [`DriverMutexError::Aborted`] if `flag` is set.
+    /// - [`DriverMutexError::NotInitialised`] if the mutex has not been initialised.
+    /// - [`DriverMutexError::NotArmed`] if the mutex has not been armed.
+    /// - [`DriverMutexError::Poisoned`] if the mutex has been poisoned.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// static DRIVER_UNLOADING: AtomicBool = AtomicBool::new(false);
+    ///
+    /// match mtx.lock_unless(&DRIVER_UNLOADING) {
+    ///     Ok(mut lock) => *lock += 1,
+    ///     Err(DriverMutexError::Aborted) => return STATUS_DELETE_PENDING,
+    ///     Err(e) => println!("Error: {:?}", e),
+    /// }
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn lock_unless(&self, flag: &AtomicBool) -> Result<KMutexGuard<'_, T>, DriverMutexError> {
+        if flag.load(SeqCst) {
+            return Err(DriverMutexError::Aborted);
+        }
+
+        let guard = self.lock()?;
+
+        // The flag may have been set whilst this thread was waiting
+        if flag.load(SeqCst) {
+            drop(guard);
+            return Err(DriverMutexError::Aborted);
+        }
+
+        Ok(guard)
+    }
+
+    /// Acquires the mutex, runs `f` against the protected data, and releases the mutex before returning the result
+    /// of `f`.
+    ///
+    /// Scoping the lock to a closure ensures the guard cannot escape or be held across later code, for example across
+    /// a call which raises the IRQL.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`Self::lock`], in which case `f` is not run.
+    ///
+    /// # Poisoning
+    ///
+    /// If `f` unwinds, the mutex is poisoned before it is released, see [Poisoning](#poisoning).
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let count = mtx.with_lock(|count| {
+    ///     *count += 1;
+    ///     *count
+    /// }).unwrap();
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, DriverMutexError> {
+        let mut guard = self.lock()?;
+
+        let poison = PoisonOnUnwind(self);
+        let result = f(&mut guard);
+        forget(poison);
+
+        drop(guard);
+
+        Ok(result)
+    }
+
+    /// As per [`Self::with_lock`], but gives up if the mutex has not been acquired within `relative_100ns`, as per
+    /// [`Self::lock_timeout`].
+    ///
+    /// This bounds how long a caller can be blocked by a wedged lock, for example on a shutdown or unload path which
+    /// must make progress even if another thread never releases the mutex.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`Self::lock_timeout`], including [`DriverMutexError::Timeout`], in which case `f` is
+    /// not run.
+    ///
+    /// # Poisoning
+    ///
+    /// If `f` unwinds, the mutex is poisoned before it is released, see [Poisoning](#poisoning).
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Give up after 100ms rather than hanging unload
+    /// match state.with_lock_timeout(100 * 10_000, |state| state.shutdown()) {
+    ///     Ok(()) => (),
+    ///     Err(DriverMutexError::Timeout) => println!("State lock wedged, skipping orderly shutdown"),
+    ///     Err(e) => println!("Error: {:?}", e),
+    /// }
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn with_lock_timeout<R>(
+        &self,
+        relative_100ns: i64,
+        f: impl FnOnce(&mut T) -> R,
+    ) -> Result<R, DriverMutexError> {
+        let mut guard = self.lock_timeout(relative_100ns)?;
+
+        let poison = PoisonOnUnwind(self);
+        let result = f(&mut guard);
+        forget(poison);
+
+        drop(guard);
+
+        Ok(result)
+    }
+
+    /// Acquires the mutex, copies the protected value out, and releases the mutex.
+    ///
+    /// This is a shorthand for `*mtx.lock()?` for small `Copy` values such as counters and flags, without a guard
+    /// being held by the caller.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`Self::lock`].
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let packets = KMutex::new(0u64).unwrap();
+    /// println!("Packets seen: {}", packets.get().unwrap());
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn get(&self) -> Result<T, DriverMutexError>
+    where
+        T: Copy,
+    {
+        Ok(*self.lock()?)
+    }
+
+    /// Acquires the mutex, replaces the protected value with `value`, and releases the mutex.
+    ///
+    /// The previous value is dropped whilst the mutex is held. Use [`Self::with_lock`] to read and update the value in
+    /// the same critical section.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`Self::lock`], in which case `value` is dropped without being stored.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let enabled = KMutex::new(false).unwrap();
+    /// enabled.set(true).unwrap();
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn set(&self, value: T) -> Result<(), DriverMutexError> {
+        *self.lock()? = value;
+
+        Ok(())
+    }
+
+    /// Acquires the mutex, replaces the protected value with `value`, and releases the mutex, returning the previous
+    /// value.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`Self::lock`], in which case `value` is dropped without being stored.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Take the pending batch, leaving an empty one in its place
+    /// let batch = pending.replace(Vec::new()).unwrap();
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn replace(&self, value: T) -> Result<T, DriverMutexError> {
+        Ok(mem::replace(&mut *self.lock()?, value))
+    }
+
+    /// Creates a new, independent mutex protecting a clone of the data protected by this one.
+    ///
+    /// The mutex is acquired whilst the data is cloned, so the copy is a consistent snapshot. The result is **not**
+    /// shared state: it is a separate `KMUTEX` with its own copy of the data, and changes made through either mutex
+    /// are not visible through the other. To share one mutex between several owners, use
+    /// [`crate::shared_mutex::SharedMutex`] or the [`crate::grt::Grt`] instead.
+    ///
+    /// The new mutex is allocated with the same pool tag and through the same [`PoolAllocator`], and if this mutex was
+    /// created with [`Self::new_paged`], its data is also placed in the paged pool. A mutex whose memory was not
+    /// allocated by the crate, from [`Self::from_raw_parts`] or [`Self::new_from_lookaside`], is cloned into the
+    /// default [`NonPagedPool`].
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`Self::lock`], or [`DriverMutexError::AllocFailed`] if the new mutex could not be
+    /// allocated.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Take a private copy of the configuration to validate without holding the original lock
+    /// let candidate = config.try_clone().unwrap();
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn try_clone(&self) -> Result<KMutex<T>, DriverMutexError>
+    where
+        T: Clone,
+    {
+        let data = T::clone(&*self.lock()?);
+
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation, and `lock` succeeding
+        // means the mutex is initialised.
+        let (tag, paged, allocator) = unsafe { ((*self.inner).tag, (*self.inner).paged, (*self.inner).allocator) };
+
+        Self::allocate(data, tag, paged, allocator.unwrap_or(AllocatorFns::of::<NonPagedPool>()))
+    }
+
+    /// Moves this mutex into a non-paged, reference counted [`ArcNP`], so that it can be cloned into several
+    /// callbacks or threads without registering it in the [`crate::grt::Grt`].
+    ///
+    /// Only the `KMutex` handle itself is moved into the new allocation: the `KMUTEX` and the protected data stay in
+    /// the pool allocation made when this mutex was created, so their addresses do not change and the data is not
+    /// copied. The only allocation made is the small `ArcNP` block holding the reference counts alongside the handle,
+    /// which is always taken from the non-paged pool, whichever pool the data lives in.
+    ///
+    /// For a handle which also provides [`crate::shared_mutex::SharedMutex::lock_owned`], see
+    /// [`crate::shared_mutex::SharedMutex`].
+    ///
+    /// # Errors
+    ///
+    /// As per [`ArcNP::new`]. On error the mutex is dropped along with the data it protects.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= DISPATCH_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let shared = KMutex::new(0u32).unwrap().into_shared().unwrap();
+    ///
+    /// let for_first = shared.clone();
+    /// let for_second = shared.clone();
+    /// // Move `for_first` and `for_second` into two system threads ...
+    /// ```
+    pub fn into_shared(self) -> Result<ArcNP<KMutex<T>>, DriverMutexError> {
+        ArcNP::new(self)
+    }
+
+    /// Acquires both `self` and `other`, swaps the values they protect, and releases them.
+    ///
+    /// The mutexes are acquired through [`crate::multi_lock::lock_both`], in order of address, so two threads
+    /// swapping the same pair of mutexes in opposite directions cannot deadlock. Swapping a mutex with itself does
+    /// nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`Self::lock`] for either mutex, in which case neither value is changed.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Promote the staged configuration, keeping the old one staged for rollback
+    /// active.swap(&staged).unwrap();
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn swap(&self, other: &KMutex<T>) -> Result<(), DriverMutexError> {
+        if self.same_mutex(other) {
+            return Ok(());
+        }
+
+        let (mut this, mut other) = lock_both(self, other)?;
+        mem::swap(&mut *this, &mut *other);
+
+        Ok(())
+    }
+
+    /// Attempts to acquire the mutex without waiting, runs `f` against the protected data if successful, and returns
+    /// both the result of `f` and how long the mutex was held for.
+    ///
+    /// This is an all in one primitive for instrumented, non-blocking critical sections: if the mutex is already held
+    /// by another thread, `f` is not run and [`DriverMutexError::WouldBlock`] is returned immediately.
+    ///
+    /// # Timer source
+    ///
+    /// The hold duration is measured with `KeQueryPerformanceCounter` immediately before and after `f` runs, and is
+    /// returned in performance counter ticks. To convert to wall time, divide by the frequency reported through the
+    /// `PerformanceFrequency` argument of `KeQueryPerformanceCounter`. The measurement excludes the acquire and release
+    /// of the mutex itself.
+    ///
+    /// # Poisoning
+    ///
+    /// If `f` unwinds, the mutex is poisoned before it is released, see [Poisoning](#poisoning).
+    ///
+    /// # Errors
+    ///
+    /// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `APC_LEVEL`.
+    /// - [`DriverMutexError::WouldBlock`] if the mutex is held by another thread.
+    /// - [`DriverMutexError::NotInitialised`] if the mutex has not been initialised.
+    /// - [`DriverMutexError::NotArmed`] if the mutex has not been armed.
+    /// - [`DriverMutexError::Poisoned`] if the mutex has been poisoned.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// match mtx.try_with_timed(|count| { *count += 1; *count }) {
+    ///     Ok((count, ticks)) => println!("Count {} updated in {} ticks", count, ticks),
+    ///     Err(DriverMutexError::WouldBlock) => println!("Contended, skipping sample"),
+    ///     Err(e) => println!("Error: {:?}", e),
+    /// }
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn try_with_timed<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<(R, u64), DriverMutexError> {
+        let mut guard = self.try_lock()?;
+
+        let poison = PoisonOnUnwind(self);
+        let start = unsafe { KeQueryPerformanceCounter(null_mut()).QuadPart };
+        let result = f(&mut guard);
+        let end = unsafe { KeQueryPerformanceCounter(null_mut()).QuadPart };
+        forget(poison);
+
+        drop(guard);
+
+        Ok((result, end.saturating_sub(start) as u64))
+    }
+
+    /// Attempts to acquire the mutex without waiting.
+    ///
+    /// The mutex is polled by calling `KeWaitForSingleObject` with a zero timeout. If it is free (or already owned by
+    /// the current thread, as a `KMUTEX` is recursive), a `KMutexGuard` is returned as per [`Self::lock`]. If it is
+    /// held by another thread, [`DriverMutexError::WouldBlock`] is returned immediately rather than waiting.
+    ///
+    /// # Errors
+    ///
+    /// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `APC_LEVEL`.
+    /// - [`DriverMutexError::WouldBlock`] if the mutex is held by another thread.
+    /// - [`DriverMutexError::NotInitialised`] if the mutex has not been initialised.
+    /// - [`DriverMutexError::NotArmed`] if the mutex has not been armed.
+    /// - [`DriverMutexError::Poisoned`] if the mutex has been poisoned.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`, if the IRQL is higher than this, the function will return
+    /// an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// match mtx.try_lock() {
+    ///     Ok(mut lock) => *lock += 1,
+    ///     Err(DriverMutexError::WouldBlock) => { /* Busy, try again later */ }
+    ///     Err(e) => println!("Error: {:?}", e),
+    /// }
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn try_lock(&self) -> Result<KMutexGuard<'_, T>, DriverMutexError> {
+        self.check_usable()?;
+
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // A zero timeout polls the mutex state rather than waiting
+        let mut timeout = LARGE_INTEGER { QuadPart: 0 };
+
+        let status = unsafe {
+            // SAFETY: The IRQL is sufficient for the operation as checked above, and the pointer is valid as RAII
+            // manages the lifetime of the heap allocation.
+            KeWaitForSingleObject(
+                &mut (*self.inner).mutex as *mut _ as *mut _,
+                Executive,
+                KernelMode as i8,
+                FALSE as u8,
+                &mut timeout,
+            )
+        };
+        if status == STATUS_TIMEOUT {
+            #[cfg(feature = "stats")]
+            unsafe { (*self.inner).stats.record_contention() };
+
+            return Err(DriverMutexError::WouldBlock);
+        }
+
+        #[cfg(feature = "stats")]
+        unsafe { (*self.inner).stats.record_acquire(false) };
+
+        #[cfg(feature = "debug")]
+        self.debug_on_acquire();
+
+        self.guard_unless_poisoned(irql)
+    }
+
+    /// Acquires the mutex in a non-alertable manner, giving up if it has not been acquired within `relative_100ns`.
+    ///
+    /// The timeout is a relative interval in units of 100 nanoseconds, so one millisecond is `10_000`. It is passed to
+    /// `KeWaitForSingleObject` as a negative `LARGE_INTEGER`, per the kernel's convention for relative timeouts. A
+    /// value of `0` polls the mutex and returns immediately, as per [`Self::try_lock`]; negative values are treated as
+    /// `0`.
+    ///
+    /// # Errors
+    ///
+    /// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `APC_LEVEL`.
+    /// - [`DriverMutexError::Timeout`] if the mutex could not be acquired within the timeout.
+    /// - [`DriverMutexError::NotInitialised`] if the mutex has not been initialised.
+    /// - [`DriverMutexError::NotArmed`] if the mutex has not been armed.
+    /// - [`DriverMutexError::Poisoned`] if the mutex has been poisoned.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`, if the IRQL is higher than this, the function will return
+    /// an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Wait for up to 50ms
+    /// match mtx.lock_timeout(50 * 10_000) {
+    ///     Ok(mut lock) => *lock += 1,
+    ///     Err(DriverMutexError::Timeout) => println!("Timed out waiting for the mutex"),
+    ///     Err(e) => println!("Error: {:?}", e),
+    /// }
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn lock_timeout(&self, relative_100ns: i64) -> Result<KMutexGuard<'_, T>, DriverMutexError> {
+        self.check_usable()?;
+
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-kewaitforsingleobject
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // When counting contention, poll the mutex first so that we know whether we had to wait for it
+        #[cfg(feature = "stats")]
+        let acquired = {
+            let mut timeout = LARGE_INTEGER { QuadPart: 0 };
+            let status = unsafe {
+                // SAFETY: As below.
+                KeWaitForSingleObject(
+                    &mut (*self.inner).mutex as *mut _ as *mut _,
+                    Executive,
+                    KernelMode as i8,
+                    FALSE as u8,
+                    &mut timeout,
+                )
+            };
+            status != STATUS_TIMEOUT
+        };
+        #[cfg(not(feature = "stats"))]
+        let acquired = false;
+
+        if !acquired {
+            // A negative value is interpreted by the kernel as relative to the current time
+            let mut timeout = LARGE_INTEGER {
+                QuadPart: -relative_100ns.max(0),
+            };
+
+            let status = unsafe {
+                // SAFETY: The IRQL is sufficient for the operation as checked above, and the pointer is valid as RAII
+                // manages the lifetime of the heap allocation.
+                KeWaitForSingleObject(
+                    &mut (*self.inner).mutex as *mut _ as *mut _,
+                    Executive,
+                    KernelMode as i8,
+                    FALSE as u8,
+                    &mut timeout,
+                )
+            };
+            if status == STATUS_TIMEOUT {
+                #[cfg(feature = "stats")]
+                unsafe { (*self.inner).stats.record_contention() };
+
+                return Err(DriverMutexError::Timeout);
+            }
+        }
+
+        #[cfg(feature = "stats")]
+        unsafe { (*self.inner).stats.record_acquire(!acquired) };
+
+        #[cfg(feature = "debug")]
+        self.debug_on_acquire();
+
+        self.guard_unless_poisoned(irql)
+    }
+
+    /// Forcibly returns the mutex to a signaled (free), unowned state by re-initialising the underlying `KMUTEX`.
+    ///
+    /// This is intended for recovery after the mutex has been abandoned, for example when a thread terminated whilst
+    /// holding it and `KeWaitForSingleObject` reported `STATUS_ABANDONED`. Once the protected data has been repaired,
+    /// the mutex can be reset and reused rather than reallocated. The protected data is left untouched.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that no thread holds, or is waiting on, the mutex at the point of the call, and that
+    /// no [`KMutexGuard`] for the mutex is still alive. Re-initialising a mutex which is owned or has waiters corrupts
+    /// the kernel's dispatcher state for those threads, which will likely result in a bug check.
+    ///
+    /// # Errors
+    ///
+    /// If the IRQL is above `DISPATCH_LEVEL`, or the mutex has not been initialised, the mutex is not reset and an
+    /// error is returned.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= DISPATCH_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // All threads using the mutex have been stopped, and the data has been repaired
+    /// unsafe { mtx.force_reset().unwrap() };
+    ///
+    /// let lock = mtx.lock().unwrap();
+    /// ```
+    pub unsafe fn force_reset(&self) -> Result<(), DriverMutexError> {
+        if self.inner.is_null() {
+            return Err(DriverMutexError::NotInitialised);
+        }
+
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation, the caller guarantees
+        // the mutex is not in use.
+        unsafe { KeInitializeMutex(&mut (*self.inner).mutex, 0) };
+
+        Ok(())
+    }
+
+    /// Consumes the mutex and returns an owned copy of the protected data (`T`).
+    ///
+    /// This method performs a deep copy of the data (`T`) guarded by the mutex before
+    /// deallocating the internal memory. Be cautious when using this method with large
+    /// data types, as it may lead to inefficiencies or stack overflows.
+    ///
+    /// For scenarios involving large data that you prefer not to allocate on the stack,
+    /// consider using [`Self::to_owned_box`] instead.
+    ///
+    /// # Safety
+    ///
+    /// This function moves `T` out of the mutex without running `Drop` on the original 
+    /// in-place value. The returned `T` remains fully owned by the caller and will be 
+    /// dropped normally.
+    /// 
+    /// - **Initialised:** The mutex must have been initialised, see [`Self::uninit`].
+    /// - **Single Ownership Guarantee:** After calling [`Self::to_owned`], ensure that
+    ///   no other references (especially static or global ones) attempt to access the
+    ///   underlying mutex. This is because the mutexes memory is deallocated once this
+    ///   method is invoked.
+    /// - **Exclusive Access:** This function should only be called when you can guarantee
+    ///   that there will be no further access to the protected `T`. Violating this can
+    ///   lead to undefined behavior since the memory is freed after the call.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// unsafe {
+    ///     let owned_data: T = mutex.to_owned();
+    ///     // Use `owned_data` safely here
+    /// }
+    /// ```
+    pub unsafe fn to_owned(self) -> T {
+        self.take_data()
+    }
+
+    /// Consumes the mutex and returns an owned `Box<T>` containing the protected data (`T`).
+    ///
+    /// This method is an alternative to [`Self::to_owned`] and is particularly useful when
+    /// dealing with large data types. By returning a `Box<T>`, the data is pool-allocated,
+    /// avoiding potential stack overflows associated with large stack allocations.
+    ///
+    /// # Safety
+    /// 
+    /// This function moves `T` out of the mutex without running `Drop` on the original 
+    /// in-place value. The returned `T` remains fully owned by the caller and will be 
+    /// dropped normally.
+    ///
+    /// - **Initialised:** The mutex must have been initialised, see [`Self::uninit`].
+    /// - **Single Ownership Guarantee:** After calling [`Self::to_owned_box`], ensure that
+    /// no other references (especially static or global ones) attempt to access the
+    /// underlying mutex. This is because the mutexes memory is deallocated once this
+    /// method is invoked.
+    /// - **Exclusive Access:** This function should only be called when you can guarantee
+    /// that there will be no further access to the protected `T`. Violating this can
+    /// lead to undefined behavior since the memory is freed after the call.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// unsafe {
+    ///     let boxed_data: Box<T> = mutex.to_owned_box();
+    ///     // Use `boxed_data` safely here
+    /// }
+    /// ```
+    pub unsafe fn to_owned_box(self) -> Box<T> {
+        Box::new(self.take_data())
+    }
+
+    /// Consumes the mutex, returning the protected data (`T`).
+    ///
+    /// As the mutex is taken by value, no guard for it can be alive, so no lock needs to be acquired. The data is
+    /// moved out of the pool allocation, which is then freed exactly once without running the destructor of the
+    /// moved out data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex has not been initialised, see [`Self::uninit`].
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL` as the pool allocation is freed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let v = KMutex::new(5u32).unwrap().into_inner();
+    /// assert_eq!(v, 5);
+    /// ```
+    pub fn into_inner(self) -> T {
+        assert!(!self.inner.is_null(), "into_inner called on an uninitialised KMutex");
+
+        self.take_data()
+    }
+
+    /// Consumes the mutex, returning the protected data (`T`) in a `Box`. This is the safe counterpart to
+    /// [`Self::to_owned_box`].
+    ///
+    /// The mutex is acquired and released before the data is moved out, so the extraction goes through the same IRQL
+    /// and state checks as [`Self::lock`]. The data is then moved into a new `Box` and the pool allocation freed,
+    /// without running the destructor of the moved out data.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`Self::lock`], along with the mutex itself, so that it is not lost and can be retried
+    /// or dropped normally.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let table = match mtx.try_into_box() {
+    ///     Ok(table) => table,
+    ///     Err((mtx, e)) => {
+    ///         println!("Could not extract the table: {}", e);
+    ///         return Err(mtx);
+    ///     }
+    /// };
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn try_into_box(self) -> Result<Box<T>, (Self, DriverMutexError)> {
+        let locked = self.lock().map(drop);
+        if let Err(e) = locked {
+            return Err((self, e));
+        }
+
+        Ok(Box::new(self.take_data()))
+    }
+
+    /// Consumes the mutex and leaks it, returning a `'static` reference, as per `Box::leak`.
+    ///
+    /// This is intended for a small number of mutexes which live for the whole lifetime of the driver, where the
+    /// lookup of the [`crate::grt::Grt`] is not wanted. The `KMutex` handle is moved into a `Box` which is leaked along
+    /// with the pool allocation it points to, so the reference may be stored in a `static` or handed to any thread or
+    /// callback.
+    ///
+    /// # Memory
+    ///
+    /// The mutex and the protected data are never dropped or freed: the destructor of `T` does not run, and the pool
+    /// allocations remain until the driver is unloaded. Calling this in a loop will exhaust the pool.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`, as the handle is boxed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// static CONFIG: AtomicPtr<KMutex<Config>> = AtomicPtr::new(null_mut());
+    ///
+    /// let config: &'static KMutex<Config> = KMutex::new(Config::default()).unwrap().leak();
+    /// CONFIG.store(config as *const _ as *mut _, SeqCst);
+    /// ```
+    pub fn leak(self) -> &'static KMutex<T>
+    where
+        T: 'static,
+    {
+        Box::leak(Box::new(self))
+    }
+
+    /// Returns a mutable reference to the protected data, without acquiring the mutex.
+    ///
+    /// As this takes `&mut self`, the borrow checker guarantees no other reference to the mutex (and so no guard)
+    /// exists, so no kernel wait is required. This is useful during single threaded initialisation before the mutex
+    /// is shared. If the mutex is unarmed, the placeholder is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex has not been initialised, see [`Self::uninit`].
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut mtx = KMutex::new(0u32).unwrap();
+    /// *mtx.get_mut() = 10;
+    /// ```
+    pub fn get_mut(&mut self) -> &mut T {
+        assert!(!self.inner.is_null(), "get_mut called on an uninitialised KMutex");
+
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation, and `&mut self`
+        // guarantees exclusive access.
+        unsafe { &mut *(*self.inner).data }
+    }
+
+    /// Moves the data out of the mutex and frees the pool allocation.
+    ///
+    /// The mutex's `Drop` is suppressed, as it would otherwise run the destructor of the data which has been moved
+    /// out, causing a double drop. The mutex must be initialised.
+    fn take_data(self) -> T {
+        let manually_dropped = ManuallyDrop::new(self);
+
+        // SAFETY: The caller guarantees the mutex is initialised, and it is owned so no guard can be alive. The data
+        // is read exactly once and the allocation is not accessed again after it is freed.
+        unsafe {
+            let data_read = ptr::read((*manually_dropped.inner).data);
+            Self::free_allocation(manually_dropped.inner);
+
+            data_read
+        }
+    }
+
+    /// Frees the pool allocations backing a mutex, without running the destructor of the data.
+    ///
+    /// # Safety
+    ///
+    /// `inner` must be a live control block made by [`Self::allocate`], [`Self::from_raw_parts`] or
+    /// [`Self::new_from_lookaside`], which is not used again.
+    unsafe fn free_allocation(inner: *mut KMutexInner<T>) {
+        unsafe {
+            if (*inner).paged && size_of::<T>() != 0 {
+                free_aligned((*inner).data as *mut _, align_of::<T>());
+            }
+
+            // Memory provided by the caller through `from_raw_parts` is theirs to free
+            if !(*inner).lookaside.is_null() {
+                free_entry((*inner).lookaside, inner as *mut _);
+            } else if let Some(allocator) = (*inner).allocator {
+                let align = if (*inner).paged {
+                    align_of::<KMutexInner<T>>()
+                } else {
+                    Self::raw_layout().align()
+                };
+                free_aligned_with(inner as *mut _, align, allocator.free);
+            }
+        }
+    }
+
+    /// Returns a pointer to the underlying `KMUTEX` dispatcher object, for use with the multiple object wait in
+    /// [`crate::lock_ordered`], or null if the mutex has not been initialised. Not part of the public API.
+    #[doc(hidden)]
+    pub fn __dispatcher_object(&self) -> *mut c_void {
+        if self.inner.is_null() {
+            return null_mut();
+        }
+
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        unsafe { ptr::addr_of_mut!((*self.inner).mutex) as *mut c_void }
+    }
+
+    /// Constructs a guard for a mutex which has already been acquired by the current thread, for use by
+    /// [`crate::lock_ordered`]. Not part of the public API.
+    ///
+    /// # Safety
+    ///
+    /// The current thread must own the mutex through an acquisition which has not yet been paired with a release.
+    #[doc(hidden)]
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub unsafe fn __assume_locked(&self) -> KMutexGuard<'_, T> {
+        #[cfg(feature = "stats")]
+        unsafe { (*self.inner).stats.record_acquire(false) };
+
+        #[cfg(feature = "debug")]
+        self.debug_on_acquire();
+
+        KMutexGuard::new(self, unsafe { KeGetCurrentIrql() })
+    }
+
+    /// Returns a pointer to the protected data, for guards defined outside of this module.
+    ///
+    /// # Safety
+    ///
+    /// The mutex must be initialised, and the pointer must only be dereferenced whilst the mutex is held.
+    pub(crate) unsafe fn data_ptr(&self) -> *mut T {
+        unsafe { (*self.inner).data }
+    }
+
+    /// Releases the mutex on behalf of a guard defined outside of this module.
+    ///
+    /// # Safety
+    ///
+    /// The current thread must own the mutex through an acquisition whose guard was forgotten, and which has not yet
+    /// been paired with a release.
+    pub(crate) unsafe fn release(&self) {
+        // NOT SAFE AT A IRQL TOO HIGH
+        unsafe { KeReleaseMutex(&mut (*self.inner).mutex, FALSE as u8) };
+    }
+
+    /// Returns the source location at which the mutex was most recently acquired, to aid diagnosing deadlocks.
+    ///
+    /// Acquisition sites are only recorded when the `debug` feature is enabled; the tracking is compiled out
+    /// otherwise and this function always returns `None`. It also returns `None` if the mutex has never been
+    /// acquired, or has not been initialised.
+    ///
+    /// The location is that of the most recent acquisition, which may have since been released.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if let Some(site) = mtx.last_acquire_site() {
+    ///     println!("Mutex last acquired at {}:{}", site.file(), site.line());
+    /// }
+    /// ```
+    pub fn last_acquire_site(&self) -> Option<&'static Location<'static>> {
+        #[cfg(feature = "debug")]
+        {
+            if self.inner.is_null() {
+                return None;
+            }
+
+            // SAFETY: The only non-null values stored are `&'static Location` references from `Location::caller`.
+            unsafe { (*self.inner).last_acquire_site.load(SeqCst).as_ref() }
+        }
+
+        #[cfg(not(feature = "debug"))]
+        None
+    }
+
+    /// Returns a snapshot of the acquisition and contention counters for the mutex.
+    ///
+    /// Only available with the `stats` feature. An uninitialised mutex reports zero for every counter.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> MutexStats {
+        if self.inner.is_null() {
+            return MutexStats::default();
+        }
+
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        unsafe { (*self.inner).stats.snapshot() }
+    }
+
+    /// Returns the number of times the mutex has been acquired, as per [`Self::stats`].
+    ///
+    /// Only available with the `stats` (or `metrics`) feature.
+    #[cfg(feature = "stats")]
+    pub fn acquire_count(&self) -> u64 {
+        self.stats().acquisitions
+    }
+
+    /// Returns the number of acquisition attempts which found the mutex already held, as per [`Self::stats`].
+    ///
+    /// Only available with the `stats` (or `metrics`) feature.
+    #[cfg(feature = "stats")]
+    pub fn contended_count(&self) -> u64 {
+        self.stats().contentions
+    }
+
+    /// Debug bookkeeping performed on every acquisition: checks that the data is not currently lent out by
+    /// [`KMutexGuard::with_exclusive`], and records the caller's source location as the most recent acquisition site.
+    #[cfg(feature = "debug")]
+    #[track_caller]
+    fn debug_on_acquire(&self) {
+        self.assert_not_exclusive();
+
+        let site: *const Location<'static> = Location::caller();
+        unsafe { (*self.inner).last_acquire_site.store(site as *mut _, SeqCst) };
+    }
+}
+
+impl<T> KMutex<T> {
+    /// Panics if a raw pointer to the data is currently lent out by [`KMutexGuard::with_exclusive`].
+    ///
+    /// Whilst the flag is set the mutex is owned by the thread running `with_exclusive`, so any other access observed
+    /// here is a nested access from that same thread.
+    #[cfg(feature = "debug")]
+    fn assert_not_exclusive(&self) {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        let exclusive = unsafe { (*self.inner).exclusive.load(SeqCst) };
+        assert!(
+            !exclusive,
+            "KMutex data accessed whilst a raw pointer to it is lent out by KMutexGuard::with_exclusive"
+        );
+    }
+}
+
+impl<T> KMutex<*mut T> {
+    /// Publishes a new pointer into the mutex, returning the previously published pointer.
+    ///
+    /// This supports the common pattern of building a new object (such as a configuration) off to the side and then
+    /// swapping it in for readers. The store is made whilst holding the mutex, so it is serialised with other calls to
+    /// [`Self::publish`] and [`Self::read_published`], and is made atomically so that lock free readers using
+    /// [`Self::read_published_relaxed`] never observe a torn pointer.
+    ///
+    /// As `*mut T` is not `Send`, a `KMutex<*mut T>` is neither `Send` nor `Sync`, so cannot itself be moved or shared
+    /// between threads. Register it in the [`crate::grt::Grt`] to make it reachable from other threads.
+    ///
+    /// # Reclamation
+    ///
+    /// The returned pointer must not be freed until no reader can still be using it. A reader which obtained the old
+    /// pointer (in particular via [`Self::read_published_relaxed`], which takes no lock) may still be dereferencing it
+    /// after this function returns, so the caller must use some other mechanism (reference counting, a grace period,
+    /// or simply never freeing superseded objects until driver unload) before releasing the old object.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mutex could not be acquired, for example if the IRQL is too high.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let current = KMutex::new(null_mut::<Config>()).unwrap();
+    ///
+    /// let new_config = Box::into_raw(Box::new(Config::default()));
+    /// let old = current.publish(new_config).unwrap();
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn publish(&self, ptr: *mut T) -> Result<*mut T, DriverMutexError> {
+        let _guard = self.lock()?;
+
+        Ok(self.published().swap(ptr, SeqCst))
+    }
+
+    /// Reads the currently published pointer whilst holding the mutex.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mutex could not be acquired, for example if the IRQL is too high.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn read_published(&self) -> Result<*mut T, DriverMutexError> {
+        let _guard = self.lock()?;
+
+        Ok(self.published().load(SeqCst))
+    }
+
+    /// Reads the currently published pointer without acquiring the mutex.
+    ///
+    /// This is intended for fast path readers which can tolerate a stale pointer: a call to [`Self::publish`] may be
+    /// in progress, in which case either the old or the new pointer is returned. The pointer is never torn.
+    ///
+    /// Returns null if the mutex has not been initialised.
+    ///
+    /// # Safety
+    ///
+    /// Lock free reads are only coherent with writes made through [`Self::publish`]. The caller must ensure that no
+    /// other thread writes the pointer through a guard from [`Self::lock`] (or any other non atomic access to the
+    /// protected data) whilst this function runs, as such a write races with this read and is undefined behaviour.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub unsafe fn read_published_relaxed(&self) -> *mut T {
+        if self.inner.is_null() {
+            return null_mut();
+        }
+
+        self.published().load(SeqCst)
+    }
+
+    /// Views the protected pointer as an atomic so that it can be read without holding the mutex.
+    fn published(&self) -> &AtomicPtr<T> {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation, `*mut T` has the same
+        // size and alignment as `AtomicPtr<T>`, and every access to the data made by this impl is atomic.
+        unsafe { AtomicPtr::from_ptr((*self.inner).data) }
+    }
+}
+
+impl<T> Drop for KMutex<T> {
+    fn drop(&mut self) {
+        // An uninitialised mutex owns no allocation
+        if self.inner.is_null() {
+            return;
+        }
+
+        unsafe {
+            // Drop the underlying data and run destructors for the data, this would be relevant in the
+            // case where Self contains other heap allocated types which have their own deallocation
+            // methods.
+            drop_in_place((*self.inner).data);
+
+            // Free the memory we allocated
+            Self::free_allocation(self.inner);
+        }
+    }
+}
+
+/// The alignment of allocations made by `ExAllocatePool2` on 64-bit Windows.
+const POOL_ALIGNMENT: usize = 16;
+
+/// Allocates memory for `layout` from the pool, honouring alignments greater than the pool guarantees.
+///
+/// For an over-aligned layout, the allocation is padded so that a block aligned to `layout.align()` fits within it,
+/// and the start of the pool allocation is stored in the pointer sized slot immediately before the returned block.
+/// Returns null if the allocation fails. The memory must be freed with [`free_aligned`] with the same alignment.
+pub(crate) unsafe fn allocate_aligned(flags: u64, layout: Layout, tag: u32) -> *mut c_void {
+    unsafe { allocate_aligned_with(layout, |size| ExAllocatePool2(flags, size as u64, tag)) }
+}
+
+/// As per [`allocate_aligned`], allocating with `allocate`, which must return memory aligned to at least
+/// `POOL_ALIGNMENT`, or null.
+unsafe fn allocate_aligned_with(layout: Layout, allocate: impl FnOnce(usize) -> *mut c_void) -> *mut c_void {
+    if layout.align() <= POOL_ALIGNMENT {
+        return allocate(layout.size());
+    }
+
+    // The pool allocation is POOL_ALIGNMENT aligned, so the aligned block starts between POOL_ALIGNMENT and
+    // layout.align() bytes into it, always leaving room for the slot
+    let Some(total_sz_required) = layout.size().checked_add(layout.align()) else {
+        return null_mut();
+    };
+    let allocation = allocate(total_sz_required);
+    if allocation.is_null() {
+        return null_mut();
+    }
+
+    let offset = layout.align() - (allocation as usize % layout.align());
+
+    // SAFETY: The offset is within the allocation, and at least POOL_ALIGNMENT bytes precede the aligned block.
+    unsafe {
+        let aligned = allocation.byte_add(offset);
+        (aligned as *mut *mut c_void).sub(1).write(allocation);
+
+        aligned
+    }
+}
+
+/// Frees memory allocated by [`allocate_aligned`].
+///
+/// # Safety
+///
+/// `ptr` must have been returned by [`allocate_aligned`] with a layout of alignment `align`, and not freed already.
+pub(crate) unsafe fn free_aligned(ptr: *mut c_void, align: usize) {
+    unsafe { free_aligned_with(ptr, align, NonPagedPool::free) }
+}
+
+/// As per [`free_aligned`], for memory allocated by [`allocate_aligned_with`], freeing it with `free`.
+unsafe fn free_aligned_with(ptr: *mut c_void, align: usize, free: unsafe fn(*mut c_void)) {
+    unsafe {
+        if align <= POOL_ALIGNMENT {
+            free(ptr);
+        } else {
+            free((ptr as *mut *mut c_void).sub(1).read());
+        }
+    }
+}
+
+/// Poisons a mutex when dropped, used to detect a closure unwinding whilst the mutex is held. It is forgotten once
+/// the closure returns normally.
+struct PoisonOnUnwind<'a, T>(&'a KMutex<T>);
+
+impl<T> Drop for PoisonOnUnwind<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: The mutex is held by the caller, so it is initialised.
+        unsafe { (*self.0.inner).poisoned.store(true, SeqCst) };
+    }
+}
+
+impl<T> Debug for KMutex<T>
+where
+    T: Debug,
+{
+    /// Formats the protected data if the mutex can be acquired without waiting, otherwise formats the data as
+    /// `<locked>`, or `<poisoned>` if the mutex has been poisoned. Formatting never blocks; if the IRQL is too high to acquire the mutex, `<locked>` is also used.
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut d = f.debug_struct("KMutex");
+        match self.try_lock() {
+            Ok(guard) => d.field("data", &&*guard),
+            Err(DriverMutexError::NotInitialised) => d.field("data", &format_args!("<uninitialised>")),
+            Err(DriverMutexError::Poisoned) => d.field("data", &format_args!("<poisoned>")),
+            Err(_) => d.field("data", &format_args!("<locked>")),
+        };
+
+        d.finish()
+    }
+}
+
+/// A RAII scoped guard for the inner data protected by the mutex. Once this guard is given out, the protected data
+/// may be safely mutated by the caller as we guarantee exclusive access via Windows Kernel Mutex primitives.
+///
+/// When this structure is dropped (falls out of scope), the lock will be unlocked.
+///
+/// # IRQL
+///
+/// Access to the data within this guard must be done at <= APC_LEVEL if a non-alertable lock was acquired, or <=
+/// DISPATCH_LEVEL if an alertable lock was acquired. It is the callers responsible to manage APC levels whilst
+/// using the KMutex.
+///
+/// If the mutex was created with [`KMutex::new_paged`], the data may be paged out and must only be accessed at
+/// IRQL `<= APC_LEVEL`.
+///
+/// If you wish to manually drop the lock with a safety check, call the function [`Self::drop_safe`].
+///
+/// # Kernel panic
+///
+/// Raising the IRQL above safe limits whilst using the mutex will cause a Kernel Panic if not appropriately handled.
+/// When RAII drops this type, the mutex is released, if the mutex goes out of scope whilst you hold an IRQL that
+/// is too high, you will receive a kernel panic. The guard records the IRQL it was acquired at (see
+/// [`Self::irql_at_acquire`]); with the `debug` feature enabled, a guard dropped above `DISPATCH_LEVEL` reports both
+/// IRQLs through the log sink and panics before the release is attempted.
+///
+pub struct KMutexGuard<'a, T> {
+    kmutex: &'a KMutex<T>,
+    /// False once the guard no longer owns the mutex, after `drop_safe` or a failed re-acquire in `unlocked`
+    owned: bool,
+    /// The IRQL the mutex was acquired at, see `irql_at_acquire`
+    irql_at_acquire: u8,
+}
+
+impl<T> Display for KMutexGuard<'_, T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", &**self)
+    }
+}
+
+/// Compares the protected data against a value, so `guard == 5` may be written in place of `*guard == 5`.
+impl<T> PartialEq<T> for KMutexGuard<'_, T>
+where
+    T: PartialEq,
+{
+    fn eq(&self, other: &T) -> bool {
+        **self == *other
+    }
+}
+
+impl<T> core::fmt::Write for KMutexGuard<'_, T>
+where
+    T: core::fmt::Write,
+{
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        (**self).write_str(s)
+    }
+}
+
+impl<T> Deref for KMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.assert_owned();
+        #[cfg(feature = "debug")]
+        self.kmutex.assert_not_exclusive();
+
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        unsafe { &*(*self.kmutex.inner).data }
+    }
+}
+
+impl<T> DerefMut for KMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.assert_owned();
+        #[cfg(feature = "debug")]
+        self.kmutex.assert_not_exclusive();
+
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        // Mutable access is safe due to Self only being given out whilst a mutex is held from the
+        // kernel.
+        unsafe { &mut *(*self.kmutex.inner).data }
+    }
+}
+
+impl<T> Drop for KMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
+
+        #[cfg(feature = "debug")]
+        check_release_irql("KMutexGuard", self.irql_at_acquire, PASSIVE_LEVEL as u8, DISPATCH_LEVEL as u8);
+
+        // NOT SAFE AT A IRQL TOO HIGH
         unsafe { KeReleaseMutex(&mut (*self.kmutex.inner).mutex, FALSE as u8) };
+    }
+}
+
+impl<'a, T> KMutexGuard<'a, T> {
+    /// Constructs a guard for a mutex which the current thread has just acquired at IRQL `irql`.
+    fn new(kmutex: &'a KMutex<T>, irql: u8) -> Self {
+        Self {
+            kmutex,
+            owned: true,
+            irql_at_acquire: irql,
+        }
+    }
+
+    /// Projects the guard onto a part of the protected data, such as a field, returning a guard which derefs to that
+    /// part only.
+    ///
+    /// The mutex remains held until the returned [`MappedKMutexGuard`] is dropped. This allows a helper to be handed
+    /// access to a single field of a large structure without exposing the rest of it.
+    ///
+    /// This is an associated function rather than a method, so that it does not shadow a `map` method on `T`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let config = KMutex::new(Config::default()).unwrap();
+    ///
+    /// let mut timeout = KMutexGuard::map(config.lock().unwrap(), |config| &mut config.timeout);
+    /// *timeout = 30;
+    /// ```
+    pub fn map<U>(mut guard: Self, f: impl FnOnce(&mut T) -> &mut U) -> MappedKMutexGuard<'a, U> {
+        let data: *mut U = f(&mut guard);
+
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        let mutex = unsafe { ptr::addr_of_mut!((*guard.kmutex.inner).mutex) };
+
+        let irql_at_acquire = guard.irql_at_acquire;
+
+        // Responsibility for releasing the mutex passes to the mapped guard
+        forget(guard);
+
+        MappedKMutexGuard {
+            mutex,
+            data,
+            irql_at_acquire,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> KMutexGuard<'_, T> {
+    /// Returns the IRQL the current thread was running at when it acquired the mutex.
+    ///
+    /// This is captured by the IRQL check each acquisition already performs, so costs nothing extra. Logging it
+    /// alongside the IRQL at the point of use can help diagnose a section of code which raises the IRQL whilst the
+    /// mutex is held, or a mutex acquired from an unexpected context.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let lock = mtx.lock().unwrap();
+    /// println!("Acquired at IRQL {}", lock.irql_at_acquire());
+    /// ```
+    pub fn irql_at_acquire(&self) -> u8 {
+        self.irql_at_acquire
+    }
+
+    /// Poisons the mutex, marking the protected data as potentially inconsistent.
+    ///
+    /// Once the guard is dropped, further calls to [`KMutex::lock`] return [`DriverMutexError::Poisoned`] until
+    /// [`KMutex::clear_poison`] is called. See [Poisoning](KMutex#poisoning).
+    ///
+    /// This is the only way a guard poisons the mutex: dropping a guard whilst unwinding from a panic does not poison
+    /// it, only the closure based functions such as [`KMutex::with_lock`] do.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut table = mtx.lock().unwrap();
+    /// if let Err(e) = table.rebuild() {
+    ///     // The table was left part way through the rebuild
+    ///     table.poison();
+    ///     return e;
+    /// }
+    /// ```
+    pub fn poison(&self) {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        unsafe { (*self.kmutex.inner).poisoned.store(true, SeqCst) };
+    }
+
+    /// Safely drop the KMutexGuard, an alternative to RAII.
+    ///
+    /// This function checks the IRQL before attempting to drop the guard.
+    ///
+    /// # Errors
+    ///
+    /// If the IRQL > DISPATCH_LEVEL, no unlock will occur and a DriverMutexError will be returned to the
+    /// caller.
+    ///
+    /// # IRQL
+    ///
+    /// This function is safe to call at any IRQL, but it will not release the mutex if IRQL > DISPATCH_LEVEL
+    pub fn drop_safe(&mut self) -> Result<(), DriverMutexError> {
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        if self.owned {
+            unsafe { KeReleaseMutex(&mut (*self.kmutex.inner).mutex, FALSE as u8) };
+            self.owned = false;
+        }
 
         Ok(())
     }
+
+    /// Temporarily releases the mutex whilst `f` runs, re-acquiring it before returning the result of `f`.
+    ///
+    /// This allows calling a routine which itself acquires the same mutex whilst holding a guard. As the mutex is
+    /// released whilst `f` runs, other threads may modify the data in the meantime, so any state read through the
+    /// guard before the call should be re-validated afterwards.
+    ///
+    /// # Errors
+    ///
+    /// If the IRQL is above `APC_LEVEL` when `f` returns, the mutex cannot be re-acquired and
+    /// [`DriverMutexError::IrqlTooHigh`] is returned. The guard is then poisoned: it no longer owns the mutex, so it
+    /// will not release it when dropped, and dereferencing it panics. The guard should be dropped.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`, and `f` must return at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut lock = mtx.lock().unwrap();
+    /// *lock += 1;
+    ///
+    /// // `flush_all` acquires `mtx` itself
+    /// lock.unlocked(|| flush_all(&mtx)).unwrap();
+    ///
+    /// *lock += 1;
+    /// ```
+    pub fn unlocked<R>(&mut self, f: impl FnOnce() -> R) -> Result<R, DriverMutexError> {
+        self.assert_owned();
+
+        // SAFETY: The guard owns the mutex, and the pointer is valid as RAII manages the heap allocation.
+        unsafe { KeReleaseMutex(&mut (*self.kmutex.inner).mutex, FALSE as u8) };
+
+        let result = f();
+
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-kewaitforsingleobject
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            self.owned = false;
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // Discard the return value; the status code does not represent an error or contain information
+        // relevant to the context of no timeout.
+        let _ = unsafe {
+            // SAFETY: The IRQL is sufficient for the operation as checked above, and the pointer is valid as RAII
+            // manages the lifetime of the heap allocation.
+            KeWaitForSingleObject(
+                &mut (*self.kmutex.inner).mutex as *mut _ as *mut _,
+                Executive,
+                KernelMode as i8,
+                FALSE as u8,
+                null_mut(),
+            )
+        };
+
+        Ok(result)
+    }
+
+    /// Panics if the guard no longer owns the mutex.
+    fn assert_owned(&self) {
+        assert!(self.owned, "KMutexGuard used after it released the mutex");
+    }
+
+    /// Lends a raw pointer to the protected data to `f`, for passing to foreign code which requires exclusive access
+    /// to the data for the duration of the call.
+    ///
+    /// The pointer is valid, and exclusively owned by `f`, until `f` returns. It must not be retained or used after
+    /// that point.
+    ///
+    /// With the `debug` feature enabled, the mutex is flagged whilst `f` runs, and any other access to the data made
+    /// by the current thread through the mutex during that time, such as re-acquiring the (recursive) mutex or
+    /// dereferencing another guard for it, panics, as it would alias the pointer lent to `f`. Without the `debug`
+    /// feature the check is compiled out and this simply passes the pointer to `f`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut lock = mtx.lock().unwrap();
+    ///
+    /// let status = lock.with_exclusive(|state| unsafe { ffi_update_state(state) });
+    /// ```
+    pub fn with_exclusive<R>(&mut self, f: impl FnOnce(*mut T) -> R) -> R {
+        self.assert_owned();
+
+        #[cfg(feature = "debug")]
+        {
+            self.kmutex.assert_not_exclusive();
+            // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+            unsafe { (*self.kmutex.inner).exclusive.store(true, SeqCst) };
+        }
+
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation, and the mutex is held.
+        let result = f(unsafe { (*self.kmutex.inner).data });
+
+        #[cfg(feature = "debug")]
+        unsafe { (*self.kmutex.inner).exclusive.store(false, SeqCst) };
+
+        result
+    }
+}
+
+/// A RAII scoped guard for part of the data protected by a [`KMutex`], created by [`KMutexGuard::map`].
+///
+/// When this structure is dropped (falls out of scope), the mutex is released.
+///
+/// # IRQL
+///
+/// As per [`KMutexGuard`].
+pub struct MappedKMutexGuard<'a, U> {
+    /// The mutex which is held, and released when the guard is dropped
+    mutex: *mut KMUTEX,
+    /// The projected part of the protected data
+    data: *mut U,
+    /// As per `KMutexGuard`
+    irql_at_acquire: u8,
+    _marker: PhantomData<&'a mut U>,
+}
+
+impl<'a, U> MappedKMutexGuard<'a, U> {
+    /// Projects the guard further onto a part of `U`, as per [`KMutexGuard::map`].
+    pub fn map<V>(guard: Self, f: impl FnOnce(&mut U) -> &mut V) -> MappedKMutexGuard<'a, V> {
+        // Responsibility for releasing the mutex passes to the new mapped guard
+        let guard = ManuallyDrop::new(guard);
+        let data = guard.data;
+
+        // SAFETY: The data is valid and exclusively accessible whilst the mutex is held by the guard.
+        let data: *mut V = f(unsafe { &mut *data });
+
+        MappedKMutexGuard {
+            mutex: guard.mutex,
+            data,
+            irql_at_acquire: guard.irql_at_acquire,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<U> Display for MappedKMutexGuard<'_, U>
+where
+    U: Display,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", &**self)
+    }
+}
+
+impl<U> Deref for MappedKMutexGuard<'_, U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: The data is valid and exclusively accessible whilst the mutex is held by the guard.
+        unsafe { &*self.data }
+    }
+}
+
+impl<U> DerefMut for MappedKMutexGuard<'_, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: The data is valid and exclusively accessible whilst the mutex is held by the guard.
+        unsafe { &mut *self.data }
+    }
+}
+
+impl<U> Drop for MappedKMutexGuard<'_, U> {
+    fn drop(&mut self) {
+        #[cfg(feature = "debug")]
+        check_release_irql("MappedKMutexGuard", self.irql_at_acquire, PASSIVE_LEVEL as u8, DISPATCH_LEVEL as u8);
+
+        // NOT SAFE AT A IRQL TOO HIGH
+        unsafe { KeReleaseMutex(self.mutex, FALSE as u8) };
+    }
 }
@@ -0,0 +1,267 @@
+//! A [`KMutex`] protecting a slice whose length is chosen when the mutex is allocated.
+//!
+//! [`KMutex`]: crate::kmutex::KMutex
+
+use core::{
+    alloc::Layout,
+    ffi::c_void,
+    ops::{Deref, DerefMut},
+    ptr::{self, null_mut},
+};
+use wdk_sys::{
+    ntddk::{KeGetCurrentIrql, KeInitializeMutex, KeReleaseMutex, KeWaitForSingleObject},
+    APC_LEVEL, DISPATCH_LEVEL, FALSE, KMUTEX, POOL_FLAG_NON_PAGED,
+    _KWAIT_REASON::Executive,
+    _MODE::KernelMode,
+};
+
+use crate::{
+    errors::DriverMutexError,
+    kmutex::{allocate_aligned, free_aligned},
+};
+
+/// A mutex protecting a `[T]` of a length decided at runtime, held in a single non-paged pool allocation alongside
+/// the `KMUTEX`.
+///
+/// A variable length buffer could be protected with a `KMutex<Box<[T]>>`, however that requires two allocations (and
+/// two frees), and an extra pointer indirection on every access. A `KMutexSlice` places the elements directly after
+/// the control block, so the guard dereferences to `&mut [T]` within the same pool block. It is created with
+/// [`Self::new`], or [`crate::kmutex::KMutex::new_slice`].
+///
+/// The length of the slice is fixed once allocated.
+///
+/// # Examples
+///
+/// ```
+/// let buffer = KMutexSlice::new(packet_len, |_| 0u8).unwrap();
+///
+/// let mut lock = buffer.lock().unwrap();
+/// lock.copy_from_slice(packet);
+/// ```
+pub struct KMutexSlice<T> {
+    inner: *mut KMutexSliceInner,
+    /// The elements, which follow the control block in the same allocation
+    data: *mut T,
+}
+
+/// The non-paged control block at the start of a `KMutexSlice` allocation.
+struct KMutexSliceInner {
+    /// A KMUTEX structure allocated into KMutexSliceInner
+    mutex: KMUTEX,
+    /// The pool tag this structure was allocated with
+    tag: u32,
+    /// The number of elements following the control block
+    len: usize,
+    /// The alignment the allocation was made with, needed to free it
+    align: usize,
+}
+
+unsafe impl<T: Send> Sync for KMutexSlice<T> {}
+unsafe impl<T: Send> Send for KMutexSlice<T> {}
+
+impl<T> KMutexSlice<T> {
+    /// Creates a new mutex protecting a slice of `len` elements, with element `i` initialised to `init(i)`.
+    ///
+    /// # Errors
+    ///
+    /// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `DISPATCH_LEVEL`.
+    /// - [`DriverMutexError::AllocFailed`] if the non-paged pool allocation fails, or the size of the slice overflows.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`, subject to the requirements of `init`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let slots = KMutexSlice::new(cpu_count, |cpu| PerCpuState::new(cpu)).unwrap();
+    /// ```
+    pub fn new(len: usize, init: impl FnMut(usize) -> T) -> Result<Self, DriverMutexError> {
+        Self::new_with_tag(len, init, u32::from_be_bytes(*b"kmtx"))
+    }
+
+    /// Creates a new mutex protecting a slice of `len` elements as per [`Self::new`], allocating it with the pool tag
+    /// `tag`.
+    ///
+    /// # Errors
+    ///
+    /// As per [`Self::new`].
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`, subject to the requirements of `init`.
+    pub fn new_with_tag(
+        len: usize,
+        mut init: impl FnMut(usize) -> T,
+        tag: u32,
+    ) -> Result<Self, DriverMutexError> {
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        let alloc_failed = DriverMutexError::AllocFailed {
+            requested: size_of::<KMutexSliceInner>()
+                .saturating_add(size_of::<T>().saturating_mul(len)),
+        };
+
+        let (layout, data_offset) = Layout::array::<T>(len)
+            .and_then(|array| Layout::new::<KMutexSliceInner>().extend(array))
+            .map_err(|_| alloc_failed)?;
+
+        let inner_heap_ptr: *mut c_void =
+            unsafe { allocate_aligned(POOL_FLAG_NON_PAGED, layout, tag) };
+        if inner_heap_ptr.is_null() {
+            return Err(DriverMutexError::AllocFailed {
+                requested: layout.size(),
+            });
+        }
+
+        let inner = inner_heap_ptr as *mut KMutexSliceInner;
+
+        // SAFETY: The allocation is checked above, and is sized and aligned for the control block followed by the
+        // elements.
+        unsafe {
+            let data = inner_heap_ptr.byte_add(data_offset) as *mut T;
+            for i in 0..len {
+                ptr::write(data.add(i), init(i));
+            }
+
+            ptr::write(
+                inner,
+                KMutexSliceInner {
+                    mutex: KMUTEX::default(),
+                    tag,
+                    len,
+                    align: layout.align(),
+                },
+            );
+
+            // Initialise the KMUTEX object via the kernel
+            KeInitializeMutex(&mut (*inner).mutex, 0);
+
+            Ok(Self { inner, data })
+        }
+    }
+
+    /// Returns the number of elements in the protected slice.
+    ///
+    /// The length is fixed at allocation, so this does not require the mutex to be held.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn len(&self) -> usize {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        unsafe { (*self.inner).len }
+    }
+
+    /// Returns `true` if the protected slice has no elements.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the pool tag the mutex was allocated with.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn tag(&self) -> u32 {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        unsafe { (*self.inner).tag }
+    }
+
+    /// Acquires the mutex in a non-alertable manner, returning a guard which dereferences to the protected slice.
+    ///
+    /// As with [`crate::kmutex::KMutex::lock`], the mutex is recursive.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `APC_LEVEL`.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= APC_LEVEL`.
+    pub fn lock(&self) -> Result<KMutexSliceGuard<'_, T>, DriverMutexError> {
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-kewaitforsingleobject
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // Discard the return value; the status code does not represent an error or contain information
+        // relevant to the context of no timeout.
+        let _ = unsafe {
+            // SAFETY: The IRQL is sufficient for the operation as checked above, and the pointer is valid as RAII
+            // manages the lifetime of the heap allocation.
+            KeWaitForSingleObject(
+                &mut (*self.inner).mutex as *mut _ as *mut _,
+                Executive,
+                KernelMode as i8,
+                FALSE as u8,
+                null_mut(),
+            )
+        };
+
+        Ok(KMutexSliceGuard { kmutex: self })
+    }
+
+    /// Returns a mutable reference to the protected slice, without acquiring the mutex.
+    ///
+    /// As this takes `&mut self`, no guard for the mutex can be alive.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn get_mut(&mut self) -> &mut [T] {
+        // SAFETY: The elements were initialised in `new`, and `&mut self` guarantees exclusive access.
+        unsafe { core::slice::from_raw_parts_mut(self.data, self.len()) }
+    }
+}
+
+impl<T> Drop for KMutexSlice<T> {
+    fn drop(&mut self) {
+        unsafe {
+            // Run the destructors of the elements, then free the single allocation holding them and the mutex
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.data, self.len()));
+            free_aligned(self.inner as *mut _, (*self.inner).align);
+        }
+    }
+}
+
+/// A RAII scoped guard for the slice protected by a [`KMutexSlice`]. When this structure is dropped, the mutex is
+/// released.
+///
+/// # IRQL
+///
+/// As per [`crate::kmutex::KMutexGuard`]; the guard must be dropped at IRQL `<= DISPATCH_LEVEL`.
+pub struct KMutexSliceGuard<'a, T> {
+    kmutex: &'a KMutexSlice<T>,
+}
+
+impl<T> Deref for KMutexSliceGuard<'_, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: The elements are initialised, and the mutex is held.
+        unsafe { core::slice::from_raw_parts(self.kmutex.data, self.kmutex.len()) }
+    }
+}
+
+impl<T> DerefMut for KMutexSliceGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: The elements are initialised, and mutable access is exclusive whilst the mutex is held.
+        unsafe { core::slice::from_raw_parts_mut(self.kmutex.data, self.kmutex.len()) }
+    }
+}
+
+impl<T> Drop for KMutexSliceGuard<'_, T> {
+    fn drop(&mut self) {
+        // NOT SAFE AT A IRQL TOO HIGH
+        unsafe { KeReleaseMutex(&mut (*self.kmutex.inner).mutex, FALSE as u8) };
+    }
+}
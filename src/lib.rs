@@ -21,6 +21,33 @@
 //!
 //! As per the above comment, ensure either `driver_model__driver_type="WDM"` for WDM, or `driver_model__driver_type="KMDF"`.
 //!
+//! ### UMDF
+//!
+//! The mutex types in this crate are built on kernel mode routines (`Ke*` / `Ex*`) which do not exist for user mode
+//! UMDF drivers. The crate can be added as a dependency of a UMDF driver, however all modules are compiled out
+//! unless `driver_model__driver_type` is `WDM` or `KMDF`. UMDF drivers run in user mode, where there is no IRQL to
+//! manage, and should use the Win32 synchronisation primitives (`SRWLOCK`, `CRITICAL_SECTION`) directly.
+//!
+//! ### Features
+//!
+//! - `debug`: Enables additional runtime assertions, and records the source location each `KMutex` is acquired from
+//!   (see `KMutex::last_acquire_site`), checks for aliasing access during `KMutexGuard::with_exclusive`, and checks
+//!   that `KMutex` / `FastMutex` guards are dropped at an IRQL from which the mutex can be released, writing a
+//!   diagnostic through the log sink before panicking if not. A recursive `FastMutex` acquisition returns
+//!   `DriverMutexError::WouldDeadlock` rather than deadlocking. This adds overhead to every acquisition so is intended
+//!   for development builds.
+//! - `stats`: Counts acquisitions and contended acquisitions for each `KMutex` and `FastMutex`, readable through
+//!   `stats()` on each mutex or `Grt::collect_stats` for every mutex in the `Grt`. Detecting contention requires a
+//!   non-blocking acquisition attempt before each blocking one. The totals are also available individually through
+//!   `acquire_count()` / `contended_count()`.
+//! - `metrics`: An alias of `stats`.
+//! - `global-alloc`: Registers an allocator which allocates from the non-paged pool as the `#[global_allocator]`, for
+//!   drivers which do not provide their own. A binary may only have one global allocator, so this must not be enabled
+//!   if the driver (or another dependency, such as `wdk-alloc`) already defines one. Off by default. Allocations are
+//!   made with the pool tag `kmtx`, which can be changed at compile time by setting the `WDK_MUTEX_POOL_TAG`
+//!   environment variable to a four character tag (for example in the `[env]` section of `.cargo/config.toml`), so
+//!   that they can be told apart from other allocations in `!poolused`.
+//!
 //! ### Crate Info
 //!
 //! The crate will safely check IRQL before doing operations which would cause a STOP CODE of
@@ -54,16 +81,74 @@
 // Public modules
 //
 #[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod arc;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod condvar;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod dyn_mutex;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod eresource;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
 pub mod errors;
 #[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod event;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
 pub mod fast_mutex;
 #[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod fixed_str;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
 pub mod grt;
 #[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod guarded_mutex;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod handoff;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod irql;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
 pub mod kmutex;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod kmutex_slice;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod log;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod lookaside;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod multi_lock;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod np_vec;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod pool;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod protected;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod pushlock;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod ring_buffer;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod shared_mutex;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod spin_mutex;
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub mod static_kmutex;
+#[cfg(all(
+    any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc),
+    feature = "stats"
+))]
+pub mod stats;
 
 //
 // Private modules
 //
-#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+#[cfg(all(
+    any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc),
+    feature = "global-alloc"
+))]
 mod alloc;
+
+//
+// Re-exports
+//
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub use irql::{with_raised_irql, RaisedIrql};
+#[cfg(any(driver_model__driver_type = "WDM", driver_model__driver_type = "KMDF", doc))]
+pub use log::{clear_logger, flush_log, set_debug_logger, set_logger};
@@ -0,0 +1,130 @@
+//! A pluggable sink for diagnostic output produced by `wdk-mutex`.
+//!
+//! By default diagnostics are written to the kernel debugger. A driver which captures its own telemetry, for example
+//! through an ETW provider or a ring buffer, can register a sink with [`set_logger`] to receive the messages instead.
+
+#[cfg(feature = "debug")]
+use core::fmt::{self, Write};
+use core::{
+    mem::transmute,
+    ptr::null_mut,
+    sync::atomic::{AtomicPtr, Ordering::SeqCst},
+};
+
+#[cfg(feature = "debug")]
+use crate::fixed_str::FixedStr;
+
+// Function pointers for the registered sink, stored type erased so they can live in an atomic. A null pointer
+// means no sink (or no flush hook) is registered.
+static LOG_WRITE: AtomicPtr<()> = AtomicPtr::new(null_mut());
+static LOG_FLUSH: AtomicPtr<()> = AtomicPtr::new(null_mut());
+
+/// Register a sink to receive the diagnostic messages emitted by `wdk-mutex`.
+///
+/// `write` is called with each message. `flush`, if provided, is called by [`flush_log`] and should persist any
+/// messages the sink has buffered; sinks which write synchronously can pass `None`.
+///
+/// Registering a sink replaces any previously registered sink.
+///
+/// # IRQL
+///
+/// This function can be called at any IRQL, however the sink may be invoked at whatever IRQL the crate is running
+/// at when it emits a message, so `write` and `flush` must not block or touch paged memory.
+///
+/// # Examples
+///
+/// ```
+/// fn my_etw_write(msg: &str) {
+///     // ...
+/// }
+///
+/// fn my_etw_flush() {
+///     // ...
+/// }
+///
+/// wdk_mutex::set_logger(my_etw_write, Some(my_etw_flush));
+/// ```
+pub fn set_logger(write: fn(&str), flush: Option<fn()>) {
+    let flush = flush.map_or(null_mut(), |f| f as *mut ());
+
+    LOG_FLUSH.store(flush, SeqCst);
+    LOG_WRITE.store(write as *mut (), SeqCst);
+}
+
+/// Register a sink for the diagnostic messages emitted by `wdk-mutex`, with no flush hook.
+///
+/// This is shorthand for `set_logger(write, None)`, for sinks which write each message synchronously. With the
+/// `debug` feature, the crate's diagnostics are passed to `write` in place of `wdk::println!` until the sink is
+/// removed with [`clear_logger`].
+///
+/// # IRQL
+///
+/// As per [`set_logger`].
+///
+/// # Examples
+///
+/// ```
+/// fn record(msg: &str) {
+///     DIAGNOSTICS.push(msg);
+/// }
+///
+/// wdk_mutex::set_debug_logger(record);
+/// ```
+pub fn set_debug_logger(write: fn(&str)) {
+    set_logger(write, None);
+}
+
+/// Remove any sink registered with [`set_logger`], returning diagnostic output to the kernel debugger.
+pub fn clear_logger() {
+    LOG_WRITE.store(null_mut(), SeqCst);
+    LOG_FLUSH.store(null_mut(), SeqCst);
+}
+
+/// Flush the sink registered with [`set_logger`].
+///
+/// If a sink with a flush hook is registered, the hook is called; otherwise this is a no-op. Call this before an
+/// intentional bugcheck or during driver unload to ensure diagnostics have been persisted.
+///
+/// # IRQL
+///
+/// This can be called at any IRQL which the registered flush hook supports.
+///
+/// # Examples
+///
+/// ```
+/// extern "C" fn driver_exit(driver: *mut DRIVER_OBJECT) {
+///     wdk_mutex::flush_log();
+/// }
+/// ```
+pub fn flush_log() {
+    let flush = LOG_FLUSH.load(SeqCst);
+    if flush.is_null() {
+        return;
+    }
+
+    // SAFETY: The only non-null value ever stored in LOG_FLUSH is a `fn()` in `set_logger`.
+    let flush = unsafe { transmute::<*mut (), fn()>(flush) };
+    flush();
+}
+
+/// Writes a diagnostic message to the sink registered with [`set_logger`], or to the kernel debugger if no sink is
+/// registered.
+///
+/// The message is formatted into a fixed size buffer rather than allocated, so this may be called at any IRQL the sink
+/// supports. Messages longer than the buffer are truncated.
+#[cfg(feature = "debug")]
+pub(crate) fn emit(args: fmt::Arguments<'_>) {
+    let mut msg = FixedStr::<256>::new();
+    // A message which does not fit is truncated rather than discarded
+    let _ = msg.write_fmt(args);
+
+    let write = LOG_WRITE.load(SeqCst);
+    if write.is_null() {
+        wdk::println!("{}", msg);
+        return;
+    }
+
+    // SAFETY: The only non-null value ever stored in LOG_WRITE is a `fn(&str)` in `set_logger`.
+    let write = unsafe { transmute::<*mut (), fn(&str)>(write) };
+    write(&msg);
+}
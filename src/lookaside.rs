@@ -0,0 +1,226 @@
+//! A non-paged lookaside list for allocating many short lived [`KMutex`]es protecting the same type.
+//!
+//! [`KMutex`]: crate::kmutex::KMutex
+
+use core::{
+    ffi::c_void,
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{fence, AtomicUsize, Ordering},
+};
+use wdk_sys::{
+    ntddk::{
+        ExAllocateFromNPagedLookasideList, ExAllocatePool2, ExDeleteNPagedLookasideList,
+        ExFreePool, ExFreeToNPagedLookasideList, ExInitializeNPagedLookasideList, KeGetCurrentIrql,
+    },
+    DISPATCH_LEVEL, NPAGED_LOOKASIDE_LIST, POOL_FLAG_NON_PAGED,
+};
+
+use crate::{errors::DriverMutexError, kmutex::KMutex};
+
+/// The alignment guaranteed for entries allocated from a lookaside list on 64-bit Windows.
+const LOOKASIDE_ENTRY_ALIGNMENT: usize = 16;
+
+/// A non-paged lookaside list (`NPAGED_LOOKASIDE_LIST`) sized for the allocations backing a `KMutex<T>`.
+///
+/// Creating and dropping a [`KMutex`] ordinarily makes an `ExAllocatePool2` / `ExFreePool` call each time. Drivers
+/// which create and destroy mutex protected objects at a high rate can instead allocate them with
+/// [`KMutex::new_from_lookaside`], which takes the allocation from this list. Freed entries are cached by the kernel
+/// and handed back out, avoiding the pool for most allocations and reducing fragmentation.
+///
+/// Each mutex allocated from the list holds a reference to it, so the list is only deleted once both the
+/// `KMutexLookaside` and every mutex allocated from it have been dropped.
+///
+/// # IRQL
+///
+/// The list may be created, allocated from and dropped at IRQL `<= DISPATCH_LEVEL`.
+///
+/// # Examples
+///
+/// ```
+/// let lookaside = KMutexLookaside::<Request>::new().unwrap();
+///
+/// for request in incoming {
+///     let mtx = KMutex::new_from_lookaside(&lookaside, request).unwrap();
+///     // ...
+/// } // Each mutex's allocation is returned to the lookaside list as it is dropped
+/// ```
+pub struct KMutexLookaside<T> {
+    inner: *mut LookasideInner,
+    _marker: PhantomData<fn() -> T>,
+}
+
+/// The reference counted, non-paged state shared between a `KMutexLookaside` and the mutexes allocated from it.
+pub(crate) struct LookasideInner {
+    /// The lookaside list itself, which must not move once initialised
+    list: NPAGED_LOOKASIDE_LIST,
+    /// The number of mutexes allocated from the list, plus one for the `KMutexLookaside`
+    refs: AtomicUsize,
+    /// The pool tag the list allocates entries with
+    tag: u32,
+    /// The start of the pool allocation, which may precede this structure to satisfy its alignment
+    allocation: *mut c_void,
+}
+
+// SAFETY: Lookaside lists are designed to be allocated from and freed to concurrently, and the list only hands out
+// uninitialised memory, so no `T` is shared through it.
+unsafe impl<T> Send for KMutexLookaside<T> {}
+unsafe impl<T> Sync for KMutexLookaside<T> {}
+
+impl<T> KMutexLookaside<T> {
+    /// Creates a lookaside list for `KMutex<T>` allocations, with the default `kmtx` pool tag.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`, or the non-paged pool allocation for the list fails.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`.
+    pub fn new() -> Result<Self, DriverMutexError> {
+        Self::new_with_tag(u32::from_be_bytes(*b"kmtx"))
+    }
+
+    /// Creates a lookaside list for `KMutex<T>` allocations, with the pool tag `tag`.
+    ///
+    /// The tag is applied to both the list and every entry it allocates, and is reported by [`KMutex::tag`] for
+    /// mutexes allocated from the list.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`, or the non-paged pool allocation for the list fails.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let lookaside = KMutexLookaside::<Request>::new_with_tag(u32::from_be_bytes(*b"req1")).unwrap();
+    /// ```
+    pub fn new_with_tag(tag: u32) -> Result<Self, DriverMutexError> {
+        // Entries are only guaranteed the pool alignment, so the mutex allocation may not require more
+        const {
+            assert!(
+                align_of::<T>() <= LOOKASIDE_ENTRY_ALIGNMENT,
+                "KMutexLookaside does not support types aligned to more than 16 bytes"
+            )
+        };
+
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // The list may require a greater alignment than the pool provides, so over allocate and align within it
+        let total_sz_required = size_of::<LookasideInner>() + align_of::<LookasideInner>() - 1;
+        let allocation: *mut c_void =
+            unsafe { ExAllocatePool2(POOL_FLAG_NON_PAGED, total_sz_required as u64, tag) };
+        if allocation.is_null() {
+            return Err(DriverMutexError::AllocFailed {
+                requested: total_sz_required,
+            });
+        }
+
+        let inner = unsafe {
+            allocation.byte_add(allocation.align_offset(align_of::<LookasideInner>()))
+                as *mut LookasideInner
+        };
+
+        // SAFETY: The allocation is checked above, and is large enough for the aligned structure.
+        unsafe {
+            ptr::write(
+                inner,
+                LookasideInner {
+                    list: NPAGED_LOOKASIDE_LIST::default(),
+                    refs: AtomicUsize::new(1),
+                    tag,
+                    allocation,
+                },
+            );
+
+            // Use the default pool allocate and free routines, and let the system manage the depth
+            ExInitializeNPagedLookasideList(
+                &mut (*inner).list,
+                None,
+                None,
+                0,
+                KMutex::<T>::raw_layout().size() as u64,
+                tag,
+                0,
+            );
+        }
+
+        Ok(Self {
+            inner,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Allocates an entry from the list, returning it along with a new reference to the list, or `None` if the
+    /// allocation failed. The reference must be released by passing the entry to [`free_entry`].
+    ///
+    /// Must be called at IRQL `<= DISPATCH_LEVEL`.
+    pub(crate) fn allocate(&self) -> Option<(*mut c_void, *mut LookasideInner)> {
+        // SAFETY: The list is valid whilst self is alive.
+        let entry = unsafe { ExAllocateFromNPagedLookasideList(&mut (*self.inner).list) };
+        if entry.is_null() {
+            return None;
+        }
+
+        // Overflow is not possible, as each reference corresponds to a live pool allocation
+        unsafe { (*self.inner).refs.fetch_add(1, Ordering::Relaxed) };
+
+        Some((entry, self.inner))
+    }
+
+    /// Returns the pool tag the list allocates entries with.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn tag(&self) -> u32 {
+        // SAFETY: The pointer is valid whilst self is alive.
+        unsafe { (*self.inner).tag }
+    }
+}
+
+impl<T> Drop for KMutexLookaside<T> {
+    fn drop(&mut self) {
+        // SAFETY: This releases the reference held by self, which is not used again.
+        unsafe { release(self.inner) };
+    }
+}
+
+/// Returns `entry` to the lookaside list and releases the reference to the list taken when it was allocated.
+///
+/// # Safety
+///
+/// `entry` and `inner` must have been returned together by `KMutexLookaside::allocate`, and neither may be used
+/// again. Must be called at IRQL `<= DISPATCH_LEVEL`.
+pub(crate) unsafe fn free_entry(inner: *mut LookasideInner, entry: *mut c_void) {
+    unsafe {
+        ExFreeToNPagedLookasideList(&mut (*inner).list, entry);
+        release(inner);
+    }
+}
+
+/// Releases a reference to the list, deleting it and freeing its allocation if it was the last.
+///
+/// # Safety
+///
+/// The caller must own a reference to `inner`, which is not used again.
+unsafe fn release(inner: *mut LookasideInner) {
+    if unsafe { (*inner).refs.fetch_sub(1, Ordering::Release) } != 1 {
+        return;
+    }
+
+    // Synchronise with every other release before the list is deleted
+    fence(Ordering::Acquire);
+
+    // SAFETY: This was the last reference, so no entry is outstanding and the list is not used again.
+    unsafe {
+        ExDeleteNPagedLookasideList(&mut (*inner).list);
+        ExFreePool((*inner).allocation);
+    }
+}
@@ -0,0 +1,464 @@
+//! Helpers for acquiring several [`crate::kmutex::KMutex`]es together without risking a lock ordering deadlock.
+//!
+//! See the [`crate::lock_ordered`] macro, [`lock_array`] for locking a fixed size array of mutexes of the same type,
+//! and [`lock_both`] for locking a pair of mutexes in a consistent order. [`wait_for_all`] and [`wait_for_any`] wait
+//! on a slice of mutexes whose length is only known at runtime.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::{ffi::c_void, mem::MaybeUninit, ptr::null_mut};
+use wdk_sys::{
+    ntddk::{ExAllocatePool2, ExFreePool, KeGetCurrentIrql, KeWaitForMultipleObjects},
+    _KWAIT_REASON::Executive,
+    _MODE::KernelMode,
+    _WAIT_TYPE::{WaitAll, WaitAny},
+    APC_LEVEL, FALSE, KWAIT_BLOCK, MAXIMUM_WAIT_OBJECTS, NTSTATUS, POOL_FLAG_NON_PAGED,
+    STATUS_ABANDONED_WAIT_0, STATUS_WAIT_0, THREAD_WAIT_OBJECTS, WAIT_TYPE,
+};
+
+use crate::{
+    errors::DriverMutexError,
+    kmutex::{KMutex, KMutexGuard},
+};
+
+/// Acquires several, possibly differently typed, [`crate::kmutex::KMutex`]es in a single atomic wait, returning a
+/// tuple of guards in the same order as the arguments.
+///
+/// The underlying `KMUTEX` objects are sorted by address and passed to `KeWaitForMultipleObjects` with `WaitAll`, so
+/// the kernel grants either every mutex or none of them. As the thread never holds a subset of the mutexes whilst
+/// waiting for the rest, two threads locking the same mutexes in a different argument order cannot deadlock.
+///
+/// The macro evaluates to `Result<(KMutexGuard<'_, A>, KMutexGuard<'_, B>, ...), DriverMutexError>`. Each argument
+/// must be a `&KMutex<_>`, and is expanded more than once, so pass references rather than expressions with side effects.
+///
+/// # Errors
+///
+/// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `APC_LEVEL`.
+/// - [`DriverMutexError::TooManyWaitObjects`] if more than `MAXIMUM_WAIT_OBJECTS` (64) mutexes are passed, which is
+///   the limit imposed by `KeWaitForMultipleObjects`.
+/// - [`DriverMutexError::DuplicateMutex`] if the same mutex is passed more than once.
+/// - [`DriverMutexError::NotInitialised`] if any of the mutexes has not been initialised.
+/// - [`DriverMutexError::NotArmed`] if any of the mutexes has not been armed.
+/// - [`DriverMutexError::Poisoned`] if any of the mutexes has been poisoned, in which case every mutex is released
+///   again.
+/// - [`DriverMutexError::AllocFailed`] if more than `THREAD_WAIT_OBJECTS` (3) mutexes are passed and the
+///   wait block array required by the kernel could not be allocated.
+///
+/// # IRQL
+///
+/// This must be called at IRQL `<= APC_LEVEL`.
+///
+/// # Examples
+///
+/// ```
+/// let config = KMutex::new(Config::default()).unwrap();
+/// let stats = KMutex::new(0u64).unwrap();
+/// let ready = KMutex::new(false).unwrap();
+///
+/// let (config, mut stats, mut ready) = lock_ordered!(&config, &stats, &ready).unwrap();
+/// *stats += 1;
+/// ```
+#[macro_export]
+macro_rules! lock_ordered {
+    ($($mutex:expr),+ $(,)?) => {{
+        let mut objects = [$($crate::kmutex::KMutex::__dispatcher_object($mutex)),+];
+        let checked: ::core::result::Result<(), $crate::errors::DriverMutexError> =
+            ::core::result::Result::Ok(())
+                $(.and_then(|()| $crate::kmutex::KMutex::__check_usable($mutex)))+;
+        match checked.and_then(|()| $crate::multi_lock::__wait_all(&mut objects)) {
+            ::core::result::Result::Ok(()) => {
+                // SAFETY: `__wait_all` returning `Ok` means this thread now owns every mutex passed in.
+                let guards = ($(unsafe { $crate::kmutex::KMutex::__assume_locked($mutex) },)+);
+                if [$($crate::kmutex::KMutex::is_poisoned($mutex)),+].contains(&true) {
+                    ::core::mem::drop(guards);
+                    ::core::result::Result::Err($crate::errors::DriverMutexError::Poisoned)
+                } else {
+                    ::core::result::Result::Ok(guards)
+                }
+            }
+            ::core::result::Result::Err(e) => ::core::result::Result::Err(e),
+        }
+    }};
+}
+
+/// Acquires every mutex in a fixed size array in a single atomic wait, returning the guards in a fixed size array in
+/// the same order, without allocating.
+///
+/// This is the array counterpart to [`crate::lock_ordered`] for mutexes which all protect the same type, and gives the
+/// same deadlock freedom: the mutexes are acquired through one `KeWaitForMultipleObjects` `WaitAll` wait in address
+/// order, so the thread never holds some of the mutexes whilst waiting for the rest. On error no mutex is acquired, so
+/// there is nothing to release.
+///
+/// `N` may not exceed `MAXIMUM_WAIT_OBJECTS` (64), which is checked at compile time. When `N` is 0 there is nothing to
+/// acquire, so an empty array is returned without calling into the kernel.
+///
+/// When `N` is more than `THREAD_WAIT_OBJECTS` (3), the kernel requires a `KWAIT_BLOCK` for each mutex. These are
+/// placed in an array on the stack rather than in the pool, using `N * size_of::<KWAIT_BLOCK>()` bytes of stack for
+/// the duration of the wait (about 3KB on x64 at the maximum of 64 mutexes). The wait is a kernel mode wait, so the
+/// stack stays resident whilst the kernel uses the wait blocks.
+///
+/// # Errors
+///
+/// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `APC_LEVEL`.
+/// - [`DriverMutexError::DuplicateMutex`] if the same mutex appears in the array more than once.
+/// - [`DriverMutexError::NotInitialised`] if any of the mutexes has not been initialised.
+/// - [`DriverMutexError::NotArmed`] if any of the mutexes has not been armed.
+/// - [`DriverMutexError::Poisoned`] if any of the mutexes has been poisoned, in which case every mutex is released
+///   again.
+///
+/// # IRQL
+///
+/// This must be called at IRQL `<= APC_LEVEL`.
+///
+/// # Examples
+///
+/// ```
+/// let counters: [KMutex<u64>; 4] = /* ... */;
+///
+/// let mut guards = lock_array(&[&counters[0], &counters[1], &counters[2], &counters[3]]).unwrap();
+/// for counter in guards.iter_mut() {
+///     **counter = 0;
+/// }
+/// ```
+pub fn lock_array<'a, T, const N: usize>(
+    mutexes: &[&'a KMutex<T>; N],
+) -> Result<[KMutexGuard<'a, T>; N], DriverMutexError> {
+    const {
+        assert!(
+            N <= MAXIMUM_WAIT_OBJECTS as usize,
+            "lock_array cannot wait on more than MAXIMUM_WAIT_OBJECTS mutexes"
+        )
+    };
+
+    if N == 0 {
+        return Ok(core::array::from_fn(|_| unreachable!()));
+    }
+
+    for mutex in mutexes {
+        mutex.__check_usable()?;
+    }
+
+    let mut objects = mutexes.map(KMutex::__dispatcher_object);
+    prepare_wait_all(&mut objects)?;
+
+    // The thread's built in wait blocks cover up to THREAD_WAIT_OBJECTS objects, beyond that use the stack
+    let mut wait_blocks = [const { MaybeUninit::<KWAIT_BLOCK>::uninit() }; N];
+    let wait_blocks = if N > THREAD_WAIT_OBJECTS as usize {
+        wait_blocks.as_mut_ptr() as *mut KWAIT_BLOCK
+    } else {
+        null_mut()
+    };
+
+    // SAFETY: The objects were validated by `prepare_wait_all`, and `wait_blocks` is either null with no more than
+    // THREAD_WAIT_OBJECTS objects, or holds a wait block per object and outlives the wait.
+    unsafe { wait_with_blocks(&mut objects, WaitAll, wait_blocks) };
+
+    // SAFETY: The `WaitAll` wait returning means this thread now owns every mutex in the array.
+    let guards = core::array::from_fn(|i| unsafe { mutexes[i].__assume_locked() });
+
+    if mutexes.iter().any(|mutex| mutex.is_poisoned()) {
+        drop(guards);
+        return Err(DriverMutexError::Poisoned);
+    }
+
+    Ok(guards)
+}
+
+/// Acquires two, possibly differently typed, [`KMutex`]es one after the other, in order of address.
+///
+/// Whichever order `a` and `b` are passed in, the mutex with the lower address is always acquired first, so two
+/// threads locking the same pair through `lock_both` cannot deadlock. Unlike [`crate::lock_ordered`], the mutexes
+/// are acquired by two separate waits, so the first is held whilst waiting for the second; the ordering guarantee
+/// only holds against other callers which also acquire the pair through `lock_both` (or `lock_ordered`).
+///
+/// The guards are returned in argument order, and each mutex is released when its guard is dropped.
+///
+/// # Errors
+///
+/// - [`DriverMutexError::DuplicateMutex`] if `a` and `b` are the same mutex, as the two guards would alias the
+///   same data.
+/// - Any error from [`KMutex::lock`]. If the second acquisition fails, the first mutex is released again.
+///
+/// # IRQL
+///
+/// This must be called at IRQL `<= APC_LEVEL`.
+///
+/// # Examples
+///
+/// ```
+/// fn transfer(from: &KMutex<Account>, to: &KMutex<Account>, amount: u64) -> Result<(), DriverMutexError> {
+///     // Safe against a concurrent transfer in the opposite direction
+///     let (mut from, mut to) = lock_both(from, to)?;
+///     from.balance -= amount;
+///     to.balance += amount;
+///     Ok(())
+/// }
+/// ```
+#[cfg_attr(feature = "debug", track_caller)]
+pub fn lock_both<'a, A, B>(
+    a: &'a KMutex<A>,
+    b: &'a KMutex<B>,
+) -> Result<(KMutexGuard<'a, A>, KMutexGuard<'a, B>), DriverMutexError> {
+    let (object_a, object_b) = (a.__dispatcher_object(), b.__dispatcher_object());
+    if !object_a.is_null() && object_a == object_b {
+        return Err(DriverMutexError::DuplicateMutex);
+    }
+
+    if object_a <= object_b {
+        let guard_a = a.lock()?;
+        let guard_b = b.lock()?;
+        Ok((guard_a, guard_b))
+    } else {
+        let guard_b = b.lock()?;
+        let guard_a = a.lock()?;
+        Ok((guard_a, guard_b))
+    }
+}
+
+/// Acquires every mutex in a slice in a single atomic wait, returning the guards in the same order.
+///
+/// This is the runtime length counterpart to [`lock_array`], with the same deadlock freedom: the mutexes are acquired
+/// through one `KeWaitForMultipleObjects` `WaitAll` wait, so the thread never holds some of the mutexes whilst
+/// waiting for the rest. On error no mutex is acquired, so there is nothing to release.
+///
+/// As with [`lock_array`], an empty slice has nothing to acquire, so an empty `Vec` is returned without calling into
+/// the kernel.
+///
+/// # Errors
+///
+/// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `APC_LEVEL`.
+/// - [`DriverMutexError::TooManyWaitObjects`] if more than `MAXIMUM_WAIT_OBJECTS` (64) mutexes are passed.
+/// - [`DriverMutexError::DuplicateMutex`] if the same mutex appears in the slice more than once.
+/// - [`DriverMutexError::NotInitialised`] if any of the mutexes has not been initialised.
+/// - [`DriverMutexError::NotArmed`] if any of the mutexes has not been armed.
+/// - [`DriverMutexError::Poisoned`] if any of the mutexes has been poisoned, in which case every mutex is released
+///   again.
+/// - [`DriverMutexError::AllocFailed`] if more than `THREAD_WAIT_OBJECTS` (3) mutexes are passed and the wait block
+///   array required by the kernel could not be allocated.
+///
+/// # IRQL
+///
+/// This must be called at IRQL `<= APC_LEVEL`.
+///
+/// # Examples
+///
+/// ```
+/// let queues: Vec<&KMutex<Queue>> = devices.iter().map(|device| &device.queue).collect();
+///
+/// for mut queue in wait_for_all(&queues).unwrap() {
+///     queue.clear();
+/// }
+/// ```
+pub fn wait_for_all<'a, T>(
+    mutexes: &[&'a KMutex<T>],
+) -> Result<Vec<KMutexGuard<'a, T>>, DriverMutexError> {
+    if mutexes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    for mutex in mutexes {
+        mutex.__check_usable()?;
+    }
+
+    let mut objects: Vec<*mut c_void> = mutexes
+        .iter()
+        .map(|mutex| mutex.__dispatcher_object())
+        .collect();
+    __wait_all(&mut objects)?;
+
+    // SAFETY: `__wait_all` returning `Ok` means this thread now owns every mutex in the slice.
+    let guards: Vec<KMutexGuard<'a, T>> = mutexes
+        .iter()
+        .map(|mutex| unsafe { mutex.__assume_locked() })
+        .collect();
+
+    if mutexes.iter().any(|mutex| mutex.is_poisoned()) {
+        drop(guards);
+        return Err(DriverMutexError::Poisoned);
+    }
+
+    Ok(guards)
+}
+
+/// Waits until any one of the mutexes in a slice can be acquired, returning its index in the slice along with its
+/// guard.
+///
+/// The mutexes are passed to `KeWaitForMultipleObjects` with `WaitAny`, so exactly one mutex is acquired; the others
+/// are left untouched. If several mutexes are free, the one with the lowest index is acquired. This suits a pool of
+/// equivalent resources, such as per channel buffers, where any free one will do.
+///
+/// # Errors
+///
+/// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `APC_LEVEL`.
+/// - [`DriverMutexError::TooManyWaitObjects`] if the slice is empty or more than `MAXIMUM_WAIT_OBJECTS` (64) mutexes
+///   are passed, as `KeWaitForMultipleObjects` requires between 1 and 64 objects.
+/// - [`DriverMutexError::NotInitialised`] if any of the mutexes has not been initialised.
+/// - [`DriverMutexError::NotArmed`] if any of the mutexes has not been armed.
+/// - [`DriverMutexError::Poisoned`] if the acquired mutex has been poisoned, in which case it is released again.
+/// - [`DriverMutexError::AllocFailed`] if more than `THREAD_WAIT_OBJECTS` (3) mutexes are passed and the wait block
+///   array required by the kernel could not be allocated.
+///
+/// # IRQL
+///
+/// This must be called at IRQL `<= APC_LEVEL`.
+///
+/// # Examples
+///
+/// ```
+/// let buffers = [&channel_a, &channel_b, &channel_c];
+///
+/// let (index, mut buffer) = wait_for_any(&buffers).unwrap();
+/// buffer.fill(&packet);
+/// println!("Packet written to channel {}", index);
+/// ```
+pub fn wait_for_any<'a, T>(
+    mutexes: &[&'a KMutex<T>],
+) -> Result<(usize, KMutexGuard<'a, T>), DriverMutexError> {
+    if mutexes.is_empty() {
+        return Err(DriverMutexError::TooManyWaitObjects);
+    }
+
+    for mutex in mutexes {
+        mutex.__check_usable()?;
+    }
+
+    // The objects are not sorted, so the index reported by the kernel is the index into `mutexes`
+    let mut objects: Vec<*mut c_void> = mutexes
+        .iter()
+        .map(|mutex| mutex.__dispatcher_object())
+        .collect();
+    check_wait_objects(&objects)?;
+    let status = wait_multiple(&mut objects, WaitAny)?;
+
+    // With no timeout and a non-alertable wait, the status is always STATUS_WAIT_0 or STATUS_ABANDONED_WAIT_0 plus
+    // the index of the object which satisfied the wait
+    let index = if status >= STATUS_ABANDONED_WAIT_0 {
+        status - STATUS_ABANDONED_WAIT_0
+    } else {
+        status - STATUS_WAIT_0
+    } as usize;
+
+    let mutex = mutexes[index];
+
+    // SAFETY: The wait was satisfied by the object at `index`, so this thread now owns that mutex.
+    let guard = unsafe { mutex.__assume_locked() };
+
+    if mutex.is_poisoned() {
+        drop(guard);
+        return Err(DriverMutexError::Poisoned);
+    }
+
+    Ok((index, guard))
+}
+
+/// Waits until every dispatcher object in `objects` has been acquired by the current thread.
+///
+/// `objects` is sorted in place by address. This is an implementation detail of [`crate::lock_ordered`] and is not
+/// part of the public API.
+#[doc(hidden)]
+pub fn __wait_all(objects: &mut [*mut c_void]) -> Result<(), DriverMutexError> {
+    prepare_wait_all(objects)?;
+
+    // Discard the status; with no timeout and a non-alertable wait the status code does not represent an error.
+    wait_multiple(objects, WaitAll)?;
+
+    Ok(())
+}
+
+/// Validates `objects` for a `WaitAll` wait and sorts them in place by address.
+fn prepare_wait_all(objects: &mut [*mut c_void]) -> Result<(), DriverMutexError> {
+    check_wait_objects(objects)?;
+
+    // Sorting gives a canonical order and places any duplicates next to each other. A duplicate would be acquired
+    // once but released by two guards.
+    objects.sort_unstable();
+    if objects.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err(DriverMutexError::DuplicateMutex);
+    }
+
+    Ok(())
+}
+
+/// Checks the IRQL and the dispatcher objects are valid for a call to `KeWaitForMultipleObjects`.
+fn check_wait_objects(objects: &[*mut c_void]) -> Result<(), DriverMutexError> {
+    // Check the IRQL is <= APC_LEVEL as per remarks at
+    // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-kewaitformultipleobjects
+    let irql = unsafe { KeGetCurrentIrql() };
+    if irql > APC_LEVEL as u8 {
+        return Err(DriverMutexError::IrqlTooHigh);
+    }
+
+    if objects.len() > MAXIMUM_WAIT_OBJECTS as usize {
+        return Err(DriverMutexError::TooManyWaitObjects);
+    }
+
+    // Uninitialised mutexes have no dispatcher object
+    if objects.iter().any(|object| object.is_null()) {
+        return Err(DriverMutexError::NotInitialised);
+    }
+
+    Ok(())
+}
+
+/// Waits on `objects` with no timeout, returning the status from `KeWaitForMultipleObjects`.
+///
+/// The objects must have been validated by `check_wait_objects`.
+fn wait_multiple(
+    objects: &mut [*mut c_void],
+    wait_type: WAIT_TYPE,
+) -> Result<NTSTATUS, DriverMutexError> {
+    //
+    // The thread's built in wait blocks only cover THREAD_WAIT_OBJECTS objects, beyond that the caller must
+    // provide a non-paged array of wait blocks.
+    //
+    let mut wait_blocks: *mut KWAIT_BLOCK = null_mut();
+    if objects.len() > THREAD_WAIT_OBJECTS as usize {
+        let sz = objects.len() * size_of::<KWAIT_BLOCK>();
+        wait_blocks =
+            unsafe { ExAllocatePool2(POOL_FLAG_NON_PAGED, sz as u64, u32::from_be_bytes(*b"kmtx")) }
+                as *mut KWAIT_BLOCK;
+        if wait_blocks.is_null() {
+            return Err(DriverMutexError::AllocFailed { requested: sz });
+        }
+    }
+
+    // SAFETY: The objects were validated by the caller, and the wait block array (if required) is sized for the
+    // number of objects.
+    let status = unsafe { wait_with_blocks(objects, wait_type, wait_blocks) };
+
+    if !wait_blocks.is_null() {
+        unsafe { ExFreePool(wait_blocks as *mut _) };
+    }
+
+    Ok(status)
+}
+
+/// Waits on `objects` with no timeout using the caller's wait blocks, returning the status from
+/// `KeWaitForMultipleObjects`.
+///
+/// # Safety
+///
+/// The objects must have been validated by `check_wait_objects`, and must be valid KMUTEX objects. `wait_blocks` may
+/// only be null if there are no more than `THREAD_WAIT_OBJECTS` objects, otherwise it must point to non-paged storage
+/// for at least one `KWAIT_BLOCK` per object which remains valid until this function returns.
+unsafe fn wait_with_blocks(
+    objects: &mut [*mut c_void],
+    wait_type: WAIT_TYPE,
+    wait_blocks: *mut KWAIT_BLOCK,
+) -> NTSTATUS {
+    // SAFETY: IRQL is checked by `check_wait_objects`, and the object pointers and wait blocks are valid by the
+    // contract of this function.
+    unsafe {
+        KeWaitForMultipleObjects(
+            objects.len() as u32,
+            objects.as_mut_ptr(),
+            wait_type,
+            Executive,
+            KernelMode as i8,
+            FALSE as u8,
+            null_mut(),
+            wait_blocks,
+        )
+    }
+}
@@ -0,0 +1,210 @@
+//! A growable array allocated directly from the non-paged pool, independent of the global allocator.
+
+use core::{
+    ffi::c_void,
+    ops::{Deref, DerefMut},
+    ptr::{self, drop_in_place, NonNull},
+    slice,
+};
+use wdk_sys::{
+    ntddk::{ExAllocatePool2, ExFreePool, KeGetCurrentIrql},
+    DISPATCH_LEVEL, POOL_FLAG_NON_PAGED,
+};
+
+use crate::errors::DriverMutexError;
+
+/// The alignment guaranteed by `ExAllocatePool2` on 64 bit Windows.
+const POOL_ALIGNMENT: usize = 16;
+
+/// A contiguous growable array type, like `alloc::vec::Vec`, whose buffer is allocated directly from the non-paged
+/// pool with a pool tag chosen by the caller.
+///
+/// `alloc::vec::Vec` allocates through whichever `#[global_allocator]` the driver has registered, so the pool type and
+/// tag used for a `KMutex<Vec<T>>` depend on the driver's allocator rather than on the collection. `NpVec` instead calls
+/// `ExAllocatePool2` itself, so the elements of a `KMutex<NpVec<T>>` are always non-paged, attributable to a known tag
+/// in `!poolused`, and work whether or not a global allocator exists.
+///
+/// Unlike `Vec`, growing the buffer can fail, so [`Self::push`] returns a `Result` rather than aborting on allocation
+/// failure. `NpVec` dereferences to a slice, so indexing, iteration and the other slice methods are available.
+///
+/// # IRQL
+///
+/// Growing the buffer (through [`Self::push`]) must occur at IRQL `<= DISPATCH_LEVEL`. As the buffer is non-paged, the
+/// elements may be accessed at any IRQL.
+///
+/// # Examples
+///
+/// ```
+/// let mtx = KMutex::new(NpVec::with_tag(u32::from_le_bytes(*b"nvec"))).unwrap();
+///
+/// {
+///     let mut list = mtx.lock().unwrap();
+///     list.push(1u32).unwrap();
+///     list.push(2u32).unwrap();
+///     list[0] += 10;
+/// }
+/// ```
+pub struct NpVec<T> {
+    ptr: NonNull<T>,
+    len: usize,
+    cap: usize,
+    tag: u32,
+}
+
+unsafe impl<T: Send> Send for NpVec<T> {}
+unsafe impl<T: Sync> Sync for NpVec<T> {}
+
+impl<T> NpVec<T> {
+    /// Creates a new, empty `NpVec` using the crate's default pool tag. No allocation is made until the first element
+    /// is pushed.
+    pub const fn new() -> Self {
+        Self::with_tag(u32::from_be_bytes(*b"kmtx"))
+    }
+
+    /// Creates a new, empty `NpVec` whose allocations are made with the pool tag `tag`. No allocation is made until the
+    /// first element is pushed.
+    pub const fn with_tag(tag: u32) -> Self {
+        Self {
+            ptr: NonNull::dangling(),
+            len: 0,
+            // Zero sized types never require an allocation
+            cap: if size_of::<T>() == 0 { usize::MAX } else { 0 },
+            tag,
+        }
+    }
+
+    /// Returns the number of elements in the vector.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the vector contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of elements the vector can hold before it must reallocate.
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Returns the pool tag the vector allocates with.
+    pub fn tag(&self) -> u32 {
+        self.tag
+    }
+
+    /// Appends an element to the back of the vector, growing the buffer if it is full.
+    ///
+    /// # Errors
+    ///
+    /// If the buffer needs to grow, this function will error if:
+    ///
+    /// - The IRQL is above `DISPATCH_LEVEL`
+    /// - The non-paged pool allocation fails
+    ///
+    /// In both cases the vector is left unchanged and `value` is dropped.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL` if the buffer needs to grow.
+    pub fn push(&mut self, value: T) -> Result<(), DriverMutexError> {
+        const {
+            assert!(
+                align_of::<T>() <= POOL_ALIGNMENT,
+                "NpVec does not support types aligned above the pool alignment"
+            )
+        };
+
+        if self.len == self.cap {
+            self.grow()?;
+        }
+
+        // SAFETY: `len < cap` so the slot is within the allocation, and is not yet initialised.
+        unsafe { ptr::write(self.ptr.as_ptr().add(self.len), value) };
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Removes the last element from the vector and returns it, or `None` if it is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        self.len -= 1;
+        // SAFETY: The element at the old `len - 1` is initialised, and decrementing `len` above ensures it is not
+        // read or dropped again.
+        Some(unsafe { ptr::read(self.ptr.as_ptr().add(self.len)) })
+    }
+
+    /// Doubles the capacity of the buffer, moving the existing elements into a new pool allocation.
+    fn grow(&mut self) -> Result<(), DriverMutexError> {
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        let new_cap = if self.cap == 0 { 4 } else { self.cap * 2 };
+        let new_sz = new_cap
+            .checked_mul(size_of::<T>())
+            .ok_or(DriverMutexError::AllocFailed {
+                requested: usize::MAX,
+            })?;
+
+        let new_ptr: *mut c_void =
+            unsafe { ExAllocatePool2(POOL_FLAG_NON_PAGED, new_sz as u64, self.tag) };
+        if new_ptr.is_null() {
+            return Err(DriverMutexError::AllocFailed { requested: new_sz });
+        }
+        let new_ptr = new_ptr as *mut T;
+
+        // SAFETY: Both allocations hold at least `len` elements and cannot overlap. The elements are moved
+        // bitwise, after which the old buffer is freed without dropping them.
+        unsafe {
+            ptr::copy_nonoverlapping(self.ptr.as_ptr(), new_ptr, self.len);
+            if self.cap != 0 {
+                ExFreePool(self.ptr.as_ptr() as *mut _);
+            }
+            self.ptr = NonNull::new_unchecked(new_ptr);
+        }
+        self.cap = new_cap;
+
+        Ok(())
+    }
+}
+
+impl<T> Default for NpVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Deref for NpVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: The first `len` elements of the buffer are initialised.
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> DerefMut for NpVec<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: The first `len` elements of the buffer are initialised.
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> Drop for NpVec<T> {
+    fn drop(&mut self) {
+        unsafe {
+            // Run destructors for every element still in the vector
+            drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr.as_ptr(), self.len));
+
+            // Free the buffer if one was ever allocated
+            if size_of::<T>() != 0 && self.cap != 0 {
+                ExFreePool(self.ptr.as_ptr() as *mut _);
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Pluggable sources of pool memory for the allocations backing a [`KMutex`].
+//!
+//! [`KMutex`]: crate::kmutex::KMutex
+
+use core::ffi::c_void;
+use wdk_sys::{
+    ntddk::{ExAllocatePool2, ExFreePool},
+    POOL_FLAG_NON_PAGED,
+};
+
+/// A source of non-paged memory for the control block of a [`crate::kmutex::KMutex`], passed to
+/// [`crate::kmutex::KMutex::new_in`].
+///
+/// This allows a driver to route mutex allocations through its own pool, for example to charge them against a quota
+/// or to count them, without forking the crate. [`NonPagedPool`] is the implementation used by
+/// [`crate::kmutex::KMutex::new`].
+///
+/// The functions are associated functions rather than methods, and an allocator is passed to `new_in` by type only: a
+/// mutex only records which allocator to free (and clone) its memory with, as function pointers, so an allocator
+/// cannot carry per instance state. An allocator which needs state should keep it in a `static`.
+///
+/// # Safety
+///
+/// Implementations must ensure that:
+///
+/// - [`Self::allocate`] returns either null, or a pointer to at least `size` bytes of non-paged memory aligned to at
+///   least 16 bytes (the alignment `ExAllocatePool2` guarantees on 64-bit Windows), which is not otherwise in use.
+/// - [`Self::free`] accepts any non-null pointer returned by [`Self::allocate`], at IRQL `<= DISPATCH_LEVEL`.
+///
+/// # Examples
+///
+/// ```
+/// static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+///
+/// struct CountingPool;
+///
+/// unsafe impl PoolAllocator for CountingPool {
+///     fn allocate(size: usize, tag: u32) -> *mut c_void {
+///         let ptr = NonPagedPool::allocate(size + 16, tag);
+///         if ptr.is_null() {
+///             return ptr;
+///         }
+///
+///         // Keep the size in a header, preserving the 16 byte alignment of the returned block
+///         LIVE_BYTES.fetch_add(size, SeqCst);
+///         unsafe { (ptr as *mut usize).write(size) };
+///         unsafe { ptr.byte_add(16) }
+///     }
+///
+///     unsafe fn free(ptr: *mut c_void) {
+///         let header = unsafe { ptr.byte_sub(16) };
+///         LIVE_BYTES.fetch_sub(unsafe { (header as *mut usize).read() }, SeqCst);
+///         unsafe { NonPagedPool::free(header) };
+///     }
+/// }
+///
+/// let mtx = KMutex::new_in::<CountingPool>(0u32).unwrap();
+/// ```
+pub unsafe trait PoolAllocator {
+    /// Allocates `size` bytes of non-paged memory attributed to the pool tag `tag`, returning null on failure.
+    fn allocate(size: usize, tag: u32) -> *mut c_void;
+
+    /// Frees memory returned by [`Self::allocate`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by [`Self::allocate`] and not freed already.
+    unsafe fn free(ptr: *mut c_void);
+}
+
+/// The default [`PoolAllocator`], allocating from the non-paged pool with `ExAllocatePool2`.
+///
+/// # IRQL
+///
+/// Allocation and freeing may be performed at IRQL `<= DISPATCH_LEVEL`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NonPagedPool;
+
+unsafe impl PoolAllocator for NonPagedPool {
+    fn allocate(size: usize, tag: u32) -> *mut c_void {
+        // SAFETY: ExAllocatePool2 has no preconditions beyond the IRQL, and returns null on failure.
+        unsafe { ExAllocatePool2(POOL_FLAG_NON_PAGED, size as u64, tag) }
+    }
+
+    unsafe fn free(ptr: *mut c_void) {
+        // SAFETY: The caller guarantees the pointer was returned by `allocate`.
+        unsafe { ExFreePool(ptr) };
+    }
+}
@@ -0,0 +1,164 @@
+//! A wrapper which protects data with a lock type selected at compile time.
+
+use core::{marker::PhantomData, ops::DerefMut};
+
+use crate::{
+    errors::DriverMutexError,
+    fast_mutex::{FastMutex, FastMutexGuard},
+    guarded_mutex::{GuardedMutex, GuardedMutexGuard},
+    kmutex::{KMutex, KMutexGuard},
+    spin_mutex::{SpinMutex, SpinMutexGuard},
+};
+
+/// A lock backend for [`Protected`], mapping a marker type to the kernel primitive which protects the data.
+///
+/// The backends provided by this crate are:
+///
+/// | Backend                 | Primitive        | Acquire at IRQL     | IRQL whilst held |
+/// |-------------------------|------------------|---------------------|------------------|
+/// | [`KMutexBackend`]       | `KMUTEX`         | `<= APC_LEVEL`      | Unchanged        |
+/// | [`FastMutexBackend`]    | `FAST_MUTEX`     | `<= APC_LEVEL`      | `APC_LEVEL`      |
+/// | [`GuardedMutexBackend`] | `KGUARDED_MUTEX` | `<= APC_LEVEL`      | Unchanged        |
+/// | [`SpinMutexBackend`]    | `KSPIN_LOCK`     | `<= DISPATCH_LEVEL` | `DISPATCH_LEVEL` |
+///
+/// See the documentation of the underlying mutex type for the full IRQL requirements.
+pub trait LockImpl {
+    /// The mutex type protecting a `T`.
+    type Mutex<T>;
+
+    /// The RAII guard returned when the mutex is acquired.
+    type Guard<'a, T: 'a>: DerefMut<Target = T>;
+
+    /// Creates a new mutex protecting `data`.
+    fn new<T>(data: T) -> Result<Self::Mutex<T>, DriverMutexError>;
+
+    /// Acquires the mutex.
+    fn lock<T>(mutex: &Self::Mutex<T>) -> Result<Self::Guard<'_, T>, DriverMutexError>;
+}
+
+/// Selects a [`KMutex`] as the backend of a [`Protected`].
+pub struct KMutexBackend;
+
+impl LockImpl for KMutexBackend {
+    type Mutex<T> = KMutex<T>;
+    type Guard<'a, T: 'a> = KMutexGuard<'a, T>;
+
+    fn new<T>(data: T) -> Result<Self::Mutex<T>, DriverMutexError> {
+        KMutex::new(data)
+    }
+
+    #[cfg_attr(feature = "debug", track_caller)]
+    fn lock<T>(mutex: &Self::Mutex<T>) -> Result<Self::Guard<'_, T>, DriverMutexError> {
+        mutex.lock()
+    }
+}
+
+/// Selects a [`FastMutex`] as the backend of a [`Protected`].
+pub struct FastMutexBackend;
+
+impl LockImpl for FastMutexBackend {
+    type Mutex<T> = FastMutex<T>;
+    type Guard<'a, T: 'a> = FastMutexGuard<'a, T>;
+
+    fn new<T>(data: T) -> Result<Self::Mutex<T>, DriverMutexError> {
+        FastMutex::new(data)
+    }
+
+    fn lock<T>(mutex: &Self::Mutex<T>) -> Result<Self::Guard<'_, T>, DriverMutexError> {
+        mutex.lock()
+    }
+}
+
+/// Selects a [`GuardedMutex`] as the backend of a [`Protected`].
+pub struct GuardedMutexBackend;
+
+impl LockImpl for GuardedMutexBackend {
+    type Mutex<T> = GuardedMutex<T>;
+    type Guard<'a, T: 'a> = GuardedMutexGuard<'a, T>;
+
+    fn new<T>(data: T) -> Result<Self::Mutex<T>, DriverMutexError> {
+        GuardedMutex::new(data)
+    }
+
+    fn lock<T>(mutex: &Self::Mutex<T>) -> Result<Self::Guard<'_, T>, DriverMutexError> {
+        mutex.lock()
+    }
+}
+
+/// Selects a [`SpinMutex`] as the backend of a [`Protected`].
+pub struct SpinMutexBackend;
+
+impl LockImpl for SpinMutexBackend {
+    type Mutex<T> = SpinMutex<T>;
+    type Guard<'a, T: 'a> = SpinMutexGuard<'a, T>;
+
+    fn new<T>(data: T) -> Result<Self::Mutex<T>, DriverMutexError> {
+        SpinMutex::new(data)
+    }
+
+    fn lock<T>(mutex: &Self::Mutex<T>) -> Result<Self::Guard<'_, T>, DriverMutexError> {
+        mutex.lock()
+    }
+}
+
+/// Data of type `T` protected by the lock primitive selected by `L`.
+///
+/// `Protected` allows the lock backing a piece of data to be changed by changing a single type parameter, rather than
+/// rewriting each use site against a different mutex type. For example, a type alias can be used to switch every
+/// user of a structure from a [`KMutex`] to a [`FastMutex`] at once.
+///
+/// See [`LockImpl`] for the available backends and their IRQL requirements.
+///
+/// # Examples
+///
+/// ```
+/// type ConnLock = FastMutexBackend;
+///
+/// let connections = Protected::<u32, ConnLock>::new(0).unwrap();
+///
+/// {
+///     let mut count = connections.lock().unwrap();
+///     *count += 1;
+/// }
+/// ```
+pub struct Protected<T, L: LockImpl> {
+    mutex: L::Mutex<T>,
+    _backend: PhantomData<L>,
+}
+
+impl<T, L: LockImpl> Protected<T, L> {
+    /// Creates a new `Protected` holding `data`, backed by `L`.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from creating the underlying mutex.
+    ///
+    /// # IRQL
+    ///
+    /// As per the `new` function of the backend's mutex type.
+    pub fn new(data: T) -> Result<Self, DriverMutexError> {
+        Ok(Self {
+            mutex: L::new(data)?,
+            _backend: PhantomData,
+        })
+    }
+
+    /// Acquires the underlying mutex, returning the backend's guard.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from acquiring the underlying mutex, such as the IRQL being too high.
+    ///
+    /// # IRQL
+    ///
+    /// As per the `lock` function of the backend's mutex type.
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn lock(&self) -> Result<L::Guard<'_, T>, DriverMutexError> {
+        L::lock(&self.mutex)
+    }
+
+    /// Returns a reference to the underlying mutex, for access to functionality specific to the backend.
+    pub fn inner(&self) -> &L::Mutex<T> {
+        &self.mutex
+    }
+}
@@ -0,0 +1,324 @@
+//! A Rust idiomatic Windows Kernel Driver EX_PUSH_LOCK reader/writer lock which protects the inner type T
+
+use core::{
+    ffi::c_void,
+    fmt::Display,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr::{self, drop_in_place},
+};
+use wdk_sys::{
+    ntddk::{
+        ExAcquirePushLockExclusiveEx, ExAcquirePushLockSharedEx, ExAllocatePool2, ExFreePool,
+        ExInitializePushLock, ExReleasePushLockExclusiveEx, ExReleasePushLockSharedEx,
+        KeEnterCriticalRegion, KeGetCurrentIrql, KeLeaveCriticalRegion,
+    },
+    APC_LEVEL, DISPATCH_LEVEL, EX_PUSH_LOCK, POOL_FLAG_NON_PAGED,
+};
+
+use crate::errors::DriverMutexError;
+
+/// The flags passed to the `Ex*PushLock*Ex` routines; no flags are defined for general use.
+const PUSH_LOCK_FLAGS: u32 = 0;
+
+/// A lightweight reader/writer lock implemented through an `EX_PUSH_LOCK` in the Windows kernel.
+///
+/// The type `PushLock<T>` allows any number of threads shared read access to the inner type T through
+/// [`Self::read`], or a single thread exclusive write access through [`Self::write`]. A push lock is a single pointer
+/// sized word, and is cheaper to acquire than an `ERESOURCE` (see [`crate::eresource::ResourceLock`]), however it
+/// does not support recursive acquisition, and has no fairness guarantees between readers and writers.
+///
+/// The push lock and the protected data are allocated in the non-paged pool.
+///
+/// Whilst a guard is held, normal kernel APCs are disabled for the thread through `KeEnterCriticalRegion`, as is
+/// required when acquiring a push lock. The region is left when the guard is dropped.
+///
+/// # Deallocation
+///
+/// PushLock handles the deallocation of resources at the point the PushLock is dropped.
+///
+/// # Examples
+///
+/// ```
+/// let routes = PushLock::new(RouteTable::default()).unwrap();
+///
+/// // Any number of threads may read at once
+/// let next_hop = routes.read().unwrap().lookup(addr);
+///
+/// // Writers have exclusive access
+/// routes.write().unwrap().insert(addr, hop);
+/// ```
+pub struct PushLock<T> {
+    inner: *mut PushLockInner<T>,
+}
+
+/// The underlying data which is non-page pool allocated which is pointed to by the `PushLock`.
+struct PushLockInner<T> {
+    lock: EX_PUSH_LOCK,
+    /// The pool tag this structure was allocated with
+    tag: u32,
+    /// The data for which the lock is protecting
+    data: T,
+}
+
+unsafe impl<T: Send + Sync> Sync for PushLock<T> {}
+unsafe impl<T: Send> Send for PushLock<T> {}
+
+impl<T> PushLock<T> {
+    /// Creates a new `EX_PUSH_LOCK` backed reader/writer lock.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`, or the non-paged pool allocation fails.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at IRQL <= DISPATCH_LEVEL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let lock = PushLock::new(0u32).unwrap();
+    /// ```
+    pub fn new(data: T) -> Result<Self, DriverMutexError> {
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        //
+        // Non-Paged heap alloc for all struct data required for PushLockInner
+        //
+        let tag = u32::from_be_bytes(*b"kmtx");
+        let total_sz_required = size_of::<PushLockInner<T>>();
+        let inner_heap_ptr: *mut c_void =
+            unsafe { ExAllocatePool2(POOL_FLAG_NON_PAGED, total_sz_required as u64, tag) };
+        if inner_heap_ptr.is_null() {
+            return Err(DriverMutexError::AllocFailed {
+                requested: total_sz_required,
+            });
+        }
+
+        // Cast the memory allocation to a pointer to the inner
+        let push_lock_inner_ptr = inner_heap_ptr as *mut PushLockInner<T>;
+
+        // SAFETY: This raw write is safe as the pointer validity is checked above.
+        unsafe {
+            ptr::write(push_lock_inner_ptr, PushLockInner { lock: 0, tag, data });
+
+            // Initialise the push lock via the kernel
+            ExInitializePushLock(&mut (*push_lock_inner_ptr).lock);
+        }
+
+        Ok(Self {
+            inner: push_lock_inner_ptr,
+        })
+    }
+
+    /// Acquires the lock for shared read access, waiting whilst a writer holds it.
+    ///
+    /// The returned [`PushLockReadGuard`] releases the lock, and leaves the critical region, when dropped.
+    ///
+    /// # Errors
+    ///
+    /// If the IRQL is above `APC_LEVEL`, this function will return an error and will not acquire the lock.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let value = *lock.read().unwrap();
+    /// ```
+    pub fn read(&self) -> Result<PushLockReadGuard<'_, T>, DriverMutexError> {
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/kernel/push-locks
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // SAFETY: RAII manages pointer validity and IRQL checked. Normal kernel APCs must be disabled whilst the
+        // push lock is held.
+        unsafe {
+            KeEnterCriticalRegion();
+            ExAcquirePushLockSharedEx(&mut (*self.inner).lock, PUSH_LOCK_FLAGS);
+        }
+
+        Ok(PushLockReadGuard {
+            lock: self,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Acquires the lock for exclusive write access, waiting whilst any reader or writer holds it.
+    ///
+    /// The returned [`PushLockWriteGuard`] releases the lock, and leaves the critical region, when dropped.
+    ///
+    /// # Errors
+    ///
+    /// If the IRQL is above `APC_LEVEL`, this function will return an error and will not acquire the lock.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// *lock.write().unwrap() += 1;
+    /// ```
+    pub fn write(&self) -> Result<PushLockWriteGuard<'_, T>, DriverMutexError> {
+        // Check the IRQL is <= APC_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/kernel/push-locks
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > APC_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // SAFETY: RAII manages pointer validity and IRQL checked. Normal kernel APCs must be disabled whilst the
+        // push lock is held.
+        unsafe {
+            KeEnterCriticalRegion();
+            ExAcquirePushLockExclusiveEx(&mut (*self.inner).lock, PUSH_LOCK_FLAGS);
+        }
+
+        Ok(PushLockWriteGuard {
+            lock: self,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Returns a mutable reference to the protected data, without acquiring the lock.
+    ///
+    /// As this takes `&mut self`, the borrow checker guarantees no other reference to the lock (and so no guard)
+    /// exists, so the lock does not need to be acquired.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn get_mut(&mut self) -> &mut T {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation, and `&mut self`
+        // guarantees exclusive access.
+        unsafe { &mut (*self.inner).data }
+    }
+
+    /// Returns the pool tag the lock was allocated with.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn tag(&self) -> u32 {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        unsafe { (*self.inner).tag }
+    }
+}
+
+impl<T> Drop for PushLock<T> {
+    fn drop(&mut self) {
+        unsafe {
+            // Drop the underlying data and run destructors for the data
+            drop_in_place(&mut (*self.inner).data);
+
+            // Free the memory we allocated, a push lock needs no teardown
+            ExFreePool(self.inner as *mut _);
+        }
+    }
+}
+
+/// A RAII scoped guard giving shared read access to the data protected by a [`PushLock`].
+///
+/// When this structure is dropped, the push lock is released and the critical region entered by [`PushLock::read`]
+/// is left.
+///
+/// # IRQL
+///
+/// This guard must be dropped at the same IRQL as the lock was acquired at.
+pub struct PushLockReadGuard<'a, T> {
+    lock: &'a PushLock<T>,
+    /// The push lock must be released, and the critical region left, by the thread which acquired it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Display for PushLockReadGuard<'_, T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        write!(f, "{}", unsafe { &(*self.lock.inner).data })
+    }
+}
+
+impl<T> Deref for PushLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations, and no writer can
+        // hold the push lock whilst this guard exists.
+        unsafe { &(*self.lock.inner).data }
+    }
+}
+
+impl<T> Drop for PushLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: The push lock is held shared by this guard, and the critical region was entered on acquisition.
+        unsafe {
+            ExReleasePushLockSharedEx(&mut (*self.lock.inner).lock, PUSH_LOCK_FLAGS);
+            KeLeaveCriticalRegion();
+        }
+    }
+}
+
+/// A RAII scoped guard giving exclusive write access to the data protected by a [`PushLock`].
+///
+/// When this structure is dropped, the push lock is released and the critical region entered by [`PushLock::write`]
+/// is left.
+///
+/// # IRQL
+///
+/// This guard must be dropped at the same IRQL as the lock was acquired at.
+pub struct PushLockWriteGuard<'a, T> {
+    lock: &'a PushLock<T>,
+    /// The push lock must be released, and the critical region left, by the thread which acquired it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Display for PushLockWriteGuard<'_, T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        write!(f, "{}", unsafe { &(*self.lock.inner).data })
+    }
+}
+
+impl<T> Deref for PushLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        unsafe { &(*self.lock.inner).data }
+    }
+}
+
+impl<T> DerefMut for PushLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        // Mutable access is safe due to Self only being given out whilst the push lock is held exclusively.
+        unsafe { &mut (*self.lock.inner).data }
+    }
+}
+
+impl<T> Drop for PushLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: The push lock is held exclusively by this guard, and the critical region was entered on
+        // acquisition.
+        unsafe {
+            ExReleasePushLockExclusiveEx(&mut (*self.lock.inner).lock, PUSH_LOCK_FLAGS);
+            KeLeaveCriticalRegion();
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! A bounded FIFO ring buffer protected by a [`KMutex`], for building producer / consumer queues.
+
+use core::mem::MaybeUninit;
+use wdk_sys::{ntddk::KeGetCurrentIrql, DISPATCH_LEVEL};
+
+use crate::{errors::DriverMutexError, kmutex::KMutex};
+
+/// A fixed capacity FIFO queue of up to `N` elements, protected by a [`KMutex`].
+///
+/// The ring is stored inline in the non-paged pool allocation made by the `KMutex`, so no further allocations occur
+/// after construction. Each call to [`Self::push`] or [`Self::pop`] acquires the mutex only for as long as it takes to
+/// move a single element in or out of the ring.
+///
+/// # IRQL
+///
+/// [`Self::push`] and [`Self::pop`] acquire the inner `KMutex` and so must be called at IRQL `<= APC_LEVEL`. If the IRQL
+/// is too high the lock is not acquired: `push` hands the value back to the caller and `pop` returns `None`, exactly
+/// as if the queue were full or empty respectively.
+///
+/// # Examples
+///
+/// ```
+/// let queue: RingBufferMutex<u32, 16> = RingBufferMutex::new().unwrap();
+///
+/// // Producer
+/// if let Err(value) = queue.push(42) {
+///     println!("Queue full, dropping {}", value);
+/// }
+///
+/// // Consumer
+/// while let Some(value) = queue.pop() {
+///     println!("Got {}", value);
+/// }
+/// ```
+pub struct RingBufferMutex<T, const N: usize> {
+    ring: KMutex<Ring<T, N>>,
+}
+
+/// The ring storage protected by the mutex. Slots `head .. head + len` (modulo `N`) are initialised.
+struct Ring<T, const N: usize> {
+    slots: [MaybeUninit<T>; N],
+    /// Index of the oldest element in the ring
+    head: usize,
+    /// Number of initialised elements in the ring
+    len: usize,
+}
+
+impl<T, const N: usize> RingBufferMutex<T, N> {
+    /// Creates a new, empty ring buffer with capacity for `N` elements.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`, or the inner [`KMutex`] could not be created.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`, as the ring is allocated from the non-paged pool.
+    pub fn new() -> Result<Self, DriverMutexError> {
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        let ring = Ring {
+            slots: [const { MaybeUninit::uninit() }; N],
+            head: 0,
+            len: 0,
+        };
+
+        Ok(Self {
+            ring: KMutex::new(ring)?,
+        })
+    }
+
+    /// Pushes a value onto the back of the queue.
+    ///
+    /// # Errors
+    ///
+    /// If the queue is full, or the lock could not be acquired because the IRQL is too high, the value is handed back
+    /// to the caller in the `Err` variant.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mut ring = match self.ring.lock() {
+            Ok(ring) => ring,
+            Err(_) => return Err(value),
+        };
+
+        if ring.len == N {
+            return Err(value);
+        }
+
+        let tail = (ring.head + ring.len) % N;
+        ring.slots[tail].write(value);
+        ring.len += 1;
+
+        Ok(())
+    }
+
+    /// Pops the value from the front of the queue, returning the oldest element pushed.
+    ///
+    /// Returns `None` if the queue is empty, or the lock could not be acquired because the IRQL is too high.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= APC_LEVEL`.
+    pub fn pop(&self) -> Option<T> {
+        let mut ring = self.ring.lock().ok()?;
+
+        if ring.len == 0 {
+            return None;
+        }
+
+        let head = ring.head;
+        // SAFETY: The slot at `head` is initialised as `len` is non-zero; advancing `head` below marks it as
+        // uninitialised so the value cannot be read twice.
+        let value = unsafe { ring.slots[head].assume_init_read() };
+        ring.head = (head + 1) % N;
+        ring.len -= 1;
+
+        Some(value)
+    }
+}
+
+impl<T, const N: usize> Drop for Ring<T, N> {
+    fn drop(&mut self) {
+        // Run destructors for any elements still queued
+        for i in 0..self.len {
+            let idx = (self.head + i) % N;
+            // SAFETY: Every slot in `head .. head + len` is initialised.
+            unsafe { self.slots[idx].assume_init_drop() };
+        }
+    }
+}
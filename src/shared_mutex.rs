@@ -0,0 +1,185 @@
+//! A reference counted [`KMutex`] which can be cloned and shared between threads without the `Grt`.
+
+use core::{
+    fmt::Display,
+    marker::PhantomData,
+    mem::forget,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{
+    arc::ArcNP,
+    errors::DriverMutexError,
+    kmutex::{KMutex, KMutexGuard},
+};
+
+/// A [`KMutex`] held in a non-paged [`ArcNP`], giving a conventional Rust sharing model for a mutex.
+///
+/// `SharedMutex::new` returns a handle which is cheap to clone; each clone increments the reference count and refers
+/// to the same mutex, which is freed once the last handle is dropped. This allows a mutex to be shared between
+/// threads and callbacks by moving a clone into each, rather than registering it in the global
+/// [`crate::grt::Grt`].
+///
+/// The handle dereferences to the [`KMutex`], so all of its functionality is available directly.
+///
+/// # Examples
+///
+/// ```
+/// let counter = SharedMutex::new(0u32).unwrap();
+///
+/// let for_thread = counter.clone();
+/// // Move `for_thread` into a system thread, which may then call `for_thread.lock()`
+///
+/// *counter.lock().unwrap() += 1;
+/// ```
+pub struct SharedMutex<T> {
+    inner: ArcNP<KMutex<T>>,
+}
+
+impl<T> SharedMutex<T> {
+    /// Creates a new shared mutex protecting `data`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`, or a non-paged pool allocation fails.
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= DISPATCH_LEVEL`.
+    pub fn new(data: T) -> Result<Self, DriverMutexError> {
+        Ok(Self {
+            inner: ArcNP::new(KMutex::new(data)?)?,
+        })
+    }
+
+    /// Acquires the mutex, as per [`KMutex::lock`].
+    ///
+    /// # Errors
+    ///
+    /// As per [`KMutex::lock`].
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= APC_LEVEL`.
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn lock(&self) -> Result<KMutexGuard<'_, T>, DriverMutexError> {
+        self.inner.lock()
+    }
+
+    /// Acquires the mutex as per [`KMutex::lock`], returning a guard which holds its own handle to the mutex rather
+    /// than borrowing this one.
+    ///
+    /// The returned [`OwnedKMutexGuard`] is `'static`, so it can be stored in a structure or returned from the function
+    /// which created the `SharedMutex` handle, and keeps the mutex alive until it is dropped.
+    ///
+    /// A mutex obtained from the [`crate::grt::Grt`] is already `'static`, so [`KMutex::lock`] on it returns a
+    /// `KMutexGuard<'static, T>` and this is not needed.
+    ///
+    /// # Errors
+    ///
+    /// As per [`KMutex::lock`].
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= APC_LEVEL`. The guard must be dropped at IRQL `<= DISPATCH_LEVEL`, by the
+    /// thread which acquired it, see [`OwnedKMutexGuard`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// struct Transaction {
+    ///     state: OwnedKMutexGuard<State>,
+    /// }
+    ///
+    /// fn begin(state: &SharedMutex<State>) -> Result<Transaction, DriverMutexError> {
+    ///     Ok(Transaction {
+    ///         state: state.lock_owned()?,
+    ///     })
+    /// }
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn lock_owned(&self) -> Result<OwnedKMutexGuard<T>, DriverMutexError> {
+        let guard = self.inner.lock()?;
+
+        // Responsibility for releasing the mutex passes to the owned guard
+        forget(guard);
+
+        Ok(OwnedKMutexGuard {
+            mutex: self.inner.clone(),
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Returns the number of handles to this mutex.
+    pub fn handle_count(&self) -> usize {
+        ArcNP::strong_count(&self.inner)
+    }
+}
+
+impl<T> Clone for SharedMutex<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Deref for SharedMutex<T> {
+    type Target = KMutex<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+/// A RAII scoped guard for a [`SharedMutex`], created by [`SharedMutex::lock_owned`], which holds a handle to the
+/// mutex rather than borrowing it.
+///
+/// When this structure is dropped (falls out of scope), the mutex is released, and the handle is dropped.
+///
+/// # Threads
+///
+/// A `KMUTEX` is owned by the thread which acquired it, and releasing it from any other thread bugchecks with
+/// `STATUS_MUTANT_NOT_OWNED`. The guard is therefore not `Send`, and cannot be moved into another system thread; to
+/// hand the data to another thread, pass a clone of the [`SharedMutex`] and lock it there.
+///
+/// # IRQL
+///
+/// This guard must be dropped at IRQL `<= DISPATCH_LEVEL`, as per [`KMutexGuard`].
+pub struct OwnedKMutexGuard<T> {
+    mutex: ArcNP<KMutex<T>>,
+    /// The mutex must be released by the thread which acquired it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Display for OwnedKMutexGuard<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", &**self)
+    }
+}
+
+impl<T> Deref for OwnedKMutexGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: The mutex is held by this guard, and the handle keeps the allocation alive.
+        unsafe { &*self.mutex.data_ptr() }
+    }
+}
+
+impl<T> DerefMut for OwnedKMutexGuard<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: The mutex is held by this guard, and the handle keeps the allocation alive.
+        unsafe { &mut *self.mutex.data_ptr() }
+    }
+}
+
+impl<T> Drop for OwnedKMutexGuard<T> {
+    fn drop(&mut self) {
+        // SAFETY: The guard taken in `lock_owned` was forgotten, so the mutex is released exactly once.
+        unsafe { self.mutex.release() };
+    }
+}
@@ -0,0 +1,378 @@
+//! A Rust idiomatic Windows Kernel Driver KSPIN_LOCK type which protects the inner type T
+
+use core::{
+    ffi::c_void,
+    fmt::Display,
+    hint::spin_loop,
+    ops::{Deref, DerefMut},
+    ptr::{self, drop_in_place},
+};
+use wdk_sys::{
+    ntddk::{
+        ExAllocatePool2, ExFreePool, KeAcquireSpinLockAtDpcLevel, KeAcquireSpinLockRaiseToDpc,
+        KeGetCurrentIrql, KeInitializeSpinLock, KeLowerIrql, KeReleaseSpinLock,
+        KeReleaseSpinLockFromDpcLevel, KeTryToAcquireSpinLockAtDpcLevel, KfRaiseIrql,
+    },
+    DISPATCH_LEVEL, KIRQL, KSPIN_LOCK, POOL_FLAG_NON_PAGED,
+};
+
+use crate::errors::DriverMutexError;
+
+/// A thread safe mutex implemented through acquiring a `KSPIN_LOCK` in the Windows kernel.
+///
+/// The type `SpinMutex<T>` provides mutually exclusive access to the inner type T allocated through this crate in the
+/// non-paged pool. Unlike [`crate::kmutex::KMutex`] and [`crate::fast_mutex::FastMutex`], a `SpinMutex` can be
+/// acquired at `DISPATCH_LEVEL`, so it may protect data which is touched from DPCs and other code running at
+/// `DISPATCH_LEVEL`.
+///
+/// Acquiring the lock raises the IRQL to `DISPATCH_LEVEL`, and waiting threads spin rather than sleep. Code holding
+/// the lock must therefore be short, must not touch paged memory, and must not wait.
+///
+/// # Deallocation
+///
+/// SpinMutex handles the deallocation of resources at the point the SpinMutex is dropped.
+///
+/// # Examples
+///
+/// ```
+/// {
+///     let mtx = SpinMutex::new(0u32).unwrap();
+///     let mut lock = mtx.lock().unwrap();
+///     *lock += 1;
+/// } // Lock released and IRQL restored as it is managed via RAII
+/// ```
+pub struct SpinMutex<T> {
+    inner: *mut SpinMutexInner<T>,
+}
+
+/// The underlying data which is non-page pool allocated which is pointed to by the `SpinMutex`.
+struct SpinMutexInner<T> {
+    lock: KSPIN_LOCK,
+    /// The pool tag this structure was allocated with
+    tag: u32,
+    /// The data for which the mutex is protecting
+    data: T,
+}
+
+// SAFETY: The mutex hands out exclusive access to the `T` to whichever thread holds the lock, and the `T` is dropped
+// by whichever thread drops the mutex, so the `T` is moved between threads and must be `Send`, as with
+// `std::sync::Mutex`.
+unsafe impl<T: Send> Sync for SpinMutex<T> {}
+unsafe impl<T: Send> Send for SpinMutex<T> {}
+
+impl<T> SpinMutex<T> {
+    /// Creates a new `KSPIN_LOCK` Windows Kernel Driver Mutex.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IRQL is above `DISPATCH_LEVEL`, or the non-paged pool allocation fails.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at IRQL <= DISPATCH_LEVEL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let my_mutex = SpinMutex::new(0u32).unwrap();
+    /// ```
+    pub fn new(data: T) -> Result<Self, DriverMutexError> {
+        if unsafe { KeGetCurrentIrql() } > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        //
+        // Non-Paged heap alloc for all struct data required for SpinMutexInner, the spin lock must be resident as
+        // it is acquired at DISPATCH_LEVEL
+        //
+        let tag = u32::from_be_bytes(*b"kmtx");
+        let total_sz_required = size_of::<SpinMutexInner<T>>();
+        let inner_heap_ptr: *mut c_void =
+            unsafe { ExAllocatePool2(POOL_FLAG_NON_PAGED, total_sz_required as u64, tag) };
+        if inner_heap_ptr.is_null() {
+            return Err(DriverMutexError::AllocFailed {
+                requested: total_sz_required,
+            });
+        }
+
+        // Cast the memory allocation to a pointer to the inner
+        let spin_mtx_inner_ptr = inner_heap_ptr as *mut SpinMutexInner<T>;
+
+        // SAFETY: This raw write is safe as the pointer validity is checked above.
+        unsafe {
+            ptr::write(spin_mtx_inner_ptr, SpinMutexInner { lock: 0, tag, data });
+
+            // Initialise the spin lock via the kernel
+            KeInitializeSpinLock(&mut (*spin_mtx_inner_ptr).lock);
+        }
+
+        Ok(Self {
+            inner: spin_mtx_inner_ptr,
+        })
+    }
+
+    /// Acquires the spin lock, raising the IRQL to `DISPATCH_LEVEL`.
+    ///
+    /// Once the thread has acquired the lock, it will return a `SpinMutexGuard` which is a RAII scoped guard allowing
+    /// exclusive access to the inner T. The guard stores the IRQL the lock was acquired at, and restores it when the
+    /// lock is released on drop.
+    ///
+    /// # Errors
+    ///
+    /// If the IRQL is too high, this function will return an error and will not acquire a lock.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= DISPATCH_LEVEL`, if the IRQL is higher than this, the function will
+    /// return an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mtx = SpinMutex::new(0u32).unwrap();
+    /// let lock = mtx.lock().unwrap();
+    /// ```
+    pub fn lock(&self) -> Result<SpinMutexGuard<'_, T>, DriverMutexError> {
+        // Check the IRQL is <= DISPATCH_LEVEL as per remarks at
+        // https://learn.microsoft.com/en-us/windows-hardware/drivers/ddi/wdm/nf-wdm-keacquirespinlock
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // SAFETY: RAII manages pointer validity and IRQL checked.
+        let old_irql = unsafe { KeAcquireSpinLockRaiseToDpc(&mut (*self.inner).lock) };
+
+        Ok(SpinMutexGuard {
+            spin_mutex: self,
+            old_irql,
+        })
+    }
+
+    /// Attempts to acquire the lock, spinning at most `spins` times before giving up.
+    ///
+    /// [`Self::lock`] spins for as long as the lock is held. Code at `DISPATCH_LEVEL`, such as a DPC, which would
+    /// rather defer its work than spin behind a long holder can use this instead. The IRQL is raised to
+    /// `DISPATCH_LEVEL`, and the lock is polled with `KeTryToAcquireSpinLockAtDpcLevel` up to `spins` times. If it
+    /// is not acquired, the IRQL is restored and [`DriverMutexError::WouldBlock`] is returned. A budget of `0` polls
+    /// the lock once.
+    ///
+    /// # Errors
+    ///
+    /// - [`DriverMutexError::IrqlTooHigh`] if the IRQL is above `DISPATCH_LEVEL`.
+    /// - [`DriverMutexError::WouldBlock`] if the lock was not acquired within the budget.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at IRQL `<= DISPATCH_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// match mtx.try_lock_for(1_000) {
+    ///     Ok(mut lock) => lock.push(sample),
+    ///     Err(DriverMutexError::WouldBlock) => dropped_samples.fetch_add(1, Relaxed),
+    ///     Err(e) => println!("Error: {:?}", e),
+    /// }
+    /// ```
+    pub fn try_lock_for(&self, spins: u32) -> Result<SpinMutexGuard<'_, T>, DriverMutexError> {
+        // Check the IRQL is <= DISPATCH_LEVEL, as the IRQL cannot be lowered to DISPATCH_LEVEL
+        let irql = unsafe { KeGetCurrentIrql() };
+        if irql > DISPATCH_LEVEL as u8 {
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // SAFETY: The IRQL is checked above to be no higher than DISPATCH_LEVEL.
+        let old_irql = unsafe { KfRaiseIrql(DISPATCH_LEVEL as u8) };
+
+        for attempt in 0..=spins {
+            // SAFETY: RAII manages pointer validity, and the IRQL is DISPATCH_LEVEL as required.
+            if unsafe { KeTryToAcquireSpinLockAtDpcLevel(&mut (*self.inner).lock) } != 0 {
+                // Releasing with KeReleaseSpinLock both releases the lock and restores the IRQL raised above
+                return Ok(SpinMutexGuard {
+                    spin_mutex: self,
+                    old_irql,
+                });
+            }
+
+            if attempt != spins {
+                spin_loop();
+            }
+        }
+
+        // SAFETY: Restores the IRQL captured above.
+        unsafe { KeLowerIrql(old_irql) };
+
+        Err(DriverMutexError::WouldBlock)
+    }
+
+    /// Acquires the lock with `KeAcquireSpinLockAtDpcLevel`, for code which is already running at `DISPATCH_LEVEL`.
+    ///
+    /// [`Self::lock`] saves and restores the IRQL around the lock, which is redundant when the caller is already at
+    /// `DISPATCH_LEVEL`, such as in a DPC routine. The returned [`SpinMutexDpcGuard`] releases the lock with
+    /// `KeReleaseSpinLockFromDpcLevel` when dropped, leaving the IRQL unchanged.
+    ///
+    /// # Safety
+    ///
+    /// The caller must be running at `DISPATCH_LEVEL`, and must remain at `DISPATCH_LEVEL` until the guard has been
+    /// dropped. With the `debug` feature enabled, the IRQL is asserted on entry.
+    ///
+    /// # IRQL
+    ///
+    /// This function must be called at `DISPATCH_LEVEL`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// unsafe extern "C" fn my_dpc(_: *mut KDPC, context: *mut c_void, _: *mut c_void, _: *mut c_void) {
+    ///     let queue = unsafe { &*(context as *const SpinMutex<RingBuffer>) };
+    ///     let mut lock = unsafe { queue.lock_at_dpc() };
+    ///     lock.push(read_status());
+    /// }
+    /// ```
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub unsafe fn lock_at_dpc(&self) -> SpinMutexDpcGuard<'_, T> {
+        #[cfg(feature = "debug")]
+        {
+            let irql = unsafe { KeGetCurrentIrql() };
+            assert_eq!(
+                irql, DISPATCH_LEVEL as u8,
+                "SpinMutex::lock_at_dpc called at IRQL {}",
+                irql
+            );
+        }
+
+        // SAFETY: RAII manages pointer validity, and the caller guarantees the IRQL is DISPATCH_LEVEL.
+        unsafe { KeAcquireSpinLockAtDpcLevel(&mut (*self.inner).lock) };
+
+        SpinMutexDpcGuard { spin_mutex: self }
+    }
+
+    /// Returns a mutable reference to the protected data, without acquiring the lock.
+    ///
+    /// As this takes `&mut self`, the borrow checker guarantees no other reference to the mutex (and so no guard)
+    /// exists, so the lock does not need to be acquired and the IRQL is not raised.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut mtx = SpinMutex::new(0u32).unwrap();
+    /// *mtx.get_mut() = 10;
+    /// ```
+    pub fn get_mut(&mut self) -> &mut T {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation, and `&mut self`
+        // guarantees exclusive access.
+        unsafe { &mut (*self.inner).data }
+    }
+
+    /// Returns the pool tag the mutex was allocated with.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn tag(&self) -> u32 {
+        // SAFETY: The pointer is valid as RAII manages the lifetime of the heap allocation.
+        unsafe { (*self.inner).tag }
+    }
+}
+
+impl<T> Drop for SpinMutex<T> {
+    fn drop(&mut self) {
+        unsafe {
+            // Drop the underlying data and run destructors for the data
+            drop_in_place(&mut (*self.inner).data);
+
+            // Free the memory we allocated
+            ExFreePool(self.inner as *mut _);
+        }
+    }
+}
+
+/// A RAII scoped guard for the inner data protected by a [`SpinMutex`].
+///
+/// When this structure is dropped (falls out of scope), the spin lock is released and the IRQL is restored to the
+/// level it was at when [`SpinMutex::lock`] (or [`SpinMutex::try_lock_for`]) was called.
+///
+/// # IRQL
+///
+/// The IRQL is `DISPATCH_LEVEL` for as long as the guard is held. Paged memory must not be accessed, and the thread
+/// must not wait, whilst holding the guard.
+pub struct SpinMutexGuard<'a, T> {
+    spin_mutex: &'a SpinMutex<T>,
+    /// The IRQL before the lock was acquired, restored on release
+    old_irql: KIRQL,
+}
+
+impl<T> Display for SpinMutexGuard<'_, T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        write!(f, "{}", unsafe { &(*self.spin_mutex.inner).data })
+    }
+}
+
+impl<T> Deref for SpinMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        unsafe { &(*self.spin_mutex.inner).data }
+    }
+}
+
+impl<T> DerefMut for SpinMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        // Mutable access is safe due to Self only being given out whilst the spin lock is held.
+        unsafe { &mut (*self.spin_mutex.inner).data }
+    }
+}
+
+impl<T> Drop for SpinMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: The lock is held by this guard, and the IRQL saved when it was acquired is restored.
+        unsafe { KeReleaseSpinLock(&mut (*self.spin_mutex.inner).lock, self.old_irql) };
+    }
+}
+
+/// A RAII scoped guard for the inner data protected by a [`SpinMutex`], acquired through [`SpinMutex::lock_at_dpc`].
+///
+/// When this structure is dropped, the spin lock is released with `KeReleaseSpinLockFromDpcLevel`, which does not
+/// alter the IRQL.
+///
+/// # IRQL
+///
+/// The guard must be held, and dropped, at `DISPATCH_LEVEL`.
+pub struct SpinMutexDpcGuard<'a, T> {
+    spin_mutex: &'a SpinMutex<T>,
+}
+
+impl<T> Deref for SpinMutexDpcGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        unsafe { &(*self.spin_mutex.inner).data }
+    }
+}
+
+impl<T> DerefMut for SpinMutexDpcGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: Dereferencing the inner data is safe as RAII controls the memory allocations.
+        // Mutable access is safe due to Self only being given out whilst the spin lock is held.
+        unsafe { &mut (*self.spin_mutex.inner).data }
+    }
+}
+
+impl<T> Drop for SpinMutexDpcGuard<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: The lock is held by this guard, and the caller of `lock_at_dpc` guarantees the IRQL is unchanged.
+        unsafe { KeReleaseSpinLockFromDpcLevel(&mut (*self.spin_mutex.inner).lock) };
+    }
+}
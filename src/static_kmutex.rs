@@ -0,0 +1,199 @@
+//! A [`KMutex`] which can be declared in a `static` and is allocated on first use, without the `Grt`.
+
+use core::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    sync::atomic::{AtomicU8, Ordering},
+};
+use wdk_sys::{ntddk::KeGetCurrentIrql, DISPATCH_LEVEL};
+
+use crate::{
+    errors::DriverMutexError,
+    kmutex::{KMutex, KMutexGuard},
+};
+
+/// The mutex has not been allocated.
+const UNINIT: u8 = 0;
+/// A thread is allocating the mutex.
+const INITIALISING: u8 = 1;
+/// The mutex has been allocated and may be used.
+const READY: u8 = 2;
+
+/// A [`KMutex`] with a `const` constructor, which is allocated and initialised the first time it is accessed.
+///
+/// This allows a mutex to be declared directly in a `static`, rather than being registered in the
+/// [`crate::grt::Grt`] or initialised through [`KMutex::initialize`] in `DriverEntry`. The first call to
+/// [`Self::get_or_init`] (or [`Self::lock_or_init`]) runs the initialiser and allocates the mutex; later calls return
+/// the same mutex.
+///
+/// If several threads race to initialise the mutex, one runs the initialiser and the others spin until it has
+/// finished, so the initialiser should be short and must not block. Callers at `DISPATCH_LEVEL` do not spin, see
+/// [`Self::get_or_init`].
+///
+/// # Deallocation
+///
+/// A `static` is never dropped, so the allocation lives until the driver is unloaded. Call [`Self::take`] from the
+/// driver's unload routine to free it.
+///
+/// # Examples
+///
+/// ```
+/// static COUNTER: StaticKMutex<u32> = StaticKMutex::new();
+///
+/// fn increment() -> Result<(), DriverMutexError> {
+///     *COUNTER.lock_or_init(|| 0)? += 1;
+///     Ok(())
+/// }
+/// ```
+pub struct StaticKMutex<T> {
+    state: AtomicU8,
+    mutex: UnsafeCell<KMutex<T>>,
+}
+
+// SAFETY: The mutex is only written by the thread which moves the state from UNINIT to INITIALISING, and is only
+// read once the state is READY, at which point it is never written again until `take`. `take` only has `&self`, so
+// it relies on its caller guaranteeing that no other thread is accessing the mutex, and that no reference or guard
+// obtained from it is still alive, for the duration of the call and afterwards.
+// As with `KMutex`, the `T` is handed between threads, so must be `Send`.
+unsafe impl<T: Send> Sync for StaticKMutex<T> {}
+unsafe impl<T: Send> Send for StaticKMutex<T> {}
+
+impl<T> StaticKMutex<T> {
+    /// Creates a new, unallocated mutex, for use in a `static` initialiser.
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(UNINIT),
+            mutex: UnsafeCell::new(KMutex::uninit()),
+        }
+    }
+
+    /// Returns the mutex, allocating it with the data returned by `init` if this is the first access.
+    ///
+    /// `init` is run at most once across all threads, unless allocation fails, in which case the mutex remains
+    /// unallocated and a later call will run its initialiser again.
+    ///
+    /// # Errors
+    ///
+    /// - [`DriverMutexError::IrqlTooHigh`] if the mutex needs to be allocated and the IRQL is above `DISPATCH_LEVEL`.
+    /// - [`DriverMutexError::WouldBlock`] if another thread is initialising the mutex and the IRQL is `DISPATCH_LEVEL`
+    ///   or above.
+    /// - Any error from allocating the mutex.
+    ///
+    /// # IRQL
+    ///
+    /// Once the mutex has been allocated it is returned at any IRQL. Allocating it uses the non-paged pool, so the
+    /// first call must be made at IRQL `<= DISPATCH_LEVEL`, subject to the requirements of `init`.
+    ///
+    /// A caller at `DISPATCH_LEVEL` or above never spins waiting for another thread to finish initialising the
+    /// mutex. That thread may have been interrupted on the same processor, in which case it could not run again until
+    /// the spinning caller returned, so [`DriverMutexError::WouldBlock`] is returned instead.
+    pub fn get_or_init(&self, init: impl FnOnce() -> T) -> Result<&KMutex<T>, DriverMutexError> {
+        let irql = unsafe { KeGetCurrentIrql() };
+
+        loop {
+            match self.state.compare_exchange_weak(
+                UNINIT,
+                INITIALISING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                // SAFETY: Once READY, the mutex is initialised and is not written again.
+                Err(READY) => return Ok(unsafe { &*self.mutex.get() }),
+                // Another thread is initialising the mutex, and may be the one this call has interrupted
+                Err(INITIALISING) if irql >= DISPATCH_LEVEL as u8 => return Err(DriverMutexError::WouldBlock),
+                // Another thread is initialising the mutex, wait for it to finish
+                Err(_) => spin_loop(),
+            }
+        }
+
+        if irql > DISPATCH_LEVEL as u8 {
+            self.state.store(UNINIT, Ordering::Release);
+            return Err(DriverMutexError::IrqlTooHigh);
+        }
+
+        // SAFETY: Moving the state to INITIALISING gives this thread exclusive access to the mutex.
+        let result = unsafe { (*self.mutex.get()).initialize(init()) };
+
+        match result {
+            Ok(()) => {
+                self.state.store(READY, Ordering::Release);
+                // SAFETY: As above.
+                Ok(unsafe { &*self.mutex.get() })
+            }
+            Err(e) => {
+                self.state.store(UNINIT, Ordering::Release);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the mutex if it has been allocated, without initialising it.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    pub fn get(&self) -> Option<&KMutex<T>> {
+        if self.state.load(Ordering::Acquire) != READY {
+            return None;
+        }
+
+        // SAFETY: Once READY, the mutex is initialised and is not written again.
+        Some(unsafe { &*self.mutex.get() })
+    }
+
+    /// Acquires the mutex as per [`KMutex::lock`], allocating it with the data returned by `init` if this is the
+    /// first access.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mutex could not be allocated, or any error from [`KMutex::lock`].
+    ///
+    /// # IRQL
+    ///
+    /// This must be called at IRQL `<= APC_LEVEL`.
+    #[cfg_attr(feature = "debug", track_caller)]
+    pub fn lock_or_init(
+        &self,
+        init: impl FnOnce() -> T,
+    ) -> Result<KMutexGuard<'_, T>, DriverMutexError> {
+        self.get_or_init(init)?.lock()
+    }
+
+    /// Frees the mutex, returning it to the unallocated state, and returns the protected data if it was allocated.
+    ///
+    /// # Safety
+    ///
+    /// No other thread may access the mutex during or after the call, and no reference returned by
+    /// [`Self::get_or_init`] or [`Self::get`], and no guard, may still be alive. This is intended to be called from
+    /// the driver's unload routine once all other threads have stopped.
+    ///
+    /// # IRQL
+    ///
+    /// This can be called at any IRQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern "C" fn driver_exit(driver: *mut DRIVER_OBJECT) {
+    ///     let _ = unsafe { COUNTER.take() };
+    /// }
+    /// ```
+    pub unsafe fn take(&self) -> Option<T> {
+        if self.state.load(Ordering::Acquire) != READY {
+            return None;
+        }
+
+        // SAFETY: The caller guarantees exclusive access to the mutex.
+        let mutex = unsafe { core::ptr::replace(self.mutex.get(), KMutex::uninit()) };
+        self.state.store(UNINIT, Ordering::Release);
+
+        Some(mutex.into_inner())
+    }
+}
+
+impl<T> Default for StaticKMutex<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,54 @@
+//! Acquisition and contention counters for the mutex types, enabled with the `stats` feature.
+
+use core::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+/// A snapshot of the usage counters for a single mutex.
+///
+/// Obtained from `KMutex::stats` / `FastMutex::stats`, for every mutex in the `Grt` at once through
+/// `Grt::collect_stats`, or for the `Grt`'s own lock through `Grt::internal_lock_stats`.
+///
+/// The counters are updated with relaxed atomics and are intended for telemetry, they are not synchronised with the
+/// mutex itself.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MutexStats {
+    /// The number of times the mutex has been acquired.
+    pub acquisitions: u64,
+    /// The number of acquisition attempts which found the mutex already held, whether they then waited for it or
+    /// (in the case of a non-blocking attempt) gave up.
+    pub contentions: u64,
+}
+
+/// The live counters stored alongside each mutex.
+pub(crate) struct StatCounters {
+    acquisitions: AtomicU64,
+    contentions: AtomicU64,
+}
+
+impl StatCounters {
+    pub(crate) const fn new() -> Self {
+        Self {
+            acquisitions: AtomicU64::new(0),
+            contentions: AtomicU64::new(0),
+        }
+    }
+
+    /// Records a successful acquisition, which may have had to wait for the mutex.
+    pub(crate) fn record_acquire(&self, contended: bool) {
+        self.acquisitions.fetch_add(1, Relaxed);
+        if contended {
+            self.record_contention();
+        }
+    }
+
+    /// Records an attempt which found the mutex held.
+    pub(crate) fn record_contention(&self) {
+        self.contentions.fetch_add(1, Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> MutexStats {
+        MutexStats {
+            acquisitions: self.acquisitions.load(Relaxed),
+            contentions: self.contentions.load(Relaxed),
+        }
+    }
+}